@@ -0,0 +1,336 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use requestty::{self, Question};
+use std::collections::HashSet;
+
+use crate::bookings::BookingList;
+use crate::helper::{format_money, parse_money};
+use crate::trains::{Train, TrainList};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AncillaryKind {
+    Bicycle,
+    Luggage, // Oversized luggage, normal bags travel free
+    Pet,
+    Meal,
+}
+
+impl AncillaryKind {
+    pub const ALL: [AncillaryKind; 4] = [
+        AncillaryKind::Bicycle,
+        AncillaryKind::Luggage,
+        AncillaryKind::Pet,
+        AncillaryKind::Meal,
+    ];
+
+    /// Short name used when offers are typed in, e.g. `bike:5.00:4`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AncillaryKind::Bicycle => "bike",
+            AncillaryKind::Luggage => "luggage",
+            AncillaryKind::Pet => "pet",
+            AncillaryKind::Meal => "meal",
+        }
+    }
+
+    fn from_code(code: &str) -> Result<Self> {
+        AncillaryKind::ALL
+            .into_iter()
+            .find(|kind| kind.code() == code.to_lowercase())
+            .ok_or_else(|| anyhow!("Unknown extra {}, expected bike, luggage, pet or meal", code))
+    }
+}
+
+impl std::fmt::Display for AncillaryKind {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            AncillaryKind::Bicycle => write!(f, "Bicycle"),
+            AncillaryKind::Luggage => write!(f, "Large luggage"),
+            AncillaryKind::Pet => write!(f, "Pet"),
+            AncillaryKind::Meal => write!(f, "Meal"),
+        }
+    }
+}
+
+/// An extra a train sells, with how many can be sold per travel date.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AncillaryOffer {
+    pub kind: AncillaryKind,
+    pub price: u32, // Price in cents
+    pub limit: u32, // Bike racks, pet places or meals per departure
+}
+
+/// An extra bought with a booking, at the price paid.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ancillary {
+    pub kind: AncillaryKind,
+    pub price: u32, // Price in cents when it was bought
+}
+
+/// Parses offers written as `kind:price:limit`, e.g. `bike:5.00:4, meal:12.50:40`.
+pub fn parse_ancillary_offers(input: &str) -> Result<Vec<AncillaryOffer>> {
+    let offers = input
+        .split(',')
+        .map(str::trim)
+        .filter(|offer| !offer.is_empty())
+        .map(|offer| {
+            let parts: Vec<&str> = offer.split(':').map(str::trim).collect();
+            if parts.len() != 3 {
+                return Err(anyhow!("Invalid extra {}, expected kind:price:limit", offer));
+            }
+            Ok(AncillaryOffer {
+                kind: AncillaryKind::from_code(parts[0])?,
+                price: parse_money(parts[1])?,
+                limit: parts[2].parse().context("Invalid extra limit")?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut kinds = HashSet::new();
+    if let Some(offer) = offers.iter().find(|offer| !kinds.insert(offer.kind.code())) {
+        return Err(anyhow!("Duplicate extra {}", offer.kind.code()));
+    }
+    Ok(offers)
+}
+
+pub fn format_ancillary_offers(offers: &[AncillaryOffer]) -> String {
+    offers
+        .iter()
+        .map(|offer| {
+            format!(
+                "{}:{}:{}",
+                offer.kind.code(),
+                format_money(offer.price).trim_start_matches('$'),
+                offer.limit
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn format_ancillaries(ancillaries: &[Ancillary]) -> String {
+    if ancillaries.is_empty() {
+        return "None".to_string();
+    }
+    ancillaries
+        .iter()
+        .map(|ancillary| ancillary.kind.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// How many of an extra are still for sale on the train for the travel date.
+pub fn ancillaries_left(
+    bookings: &BookingList,
+    train: &Train,
+    travel_date: NaiveDate,
+    kind: AncillaryKind,
+    excluding: Option<&str>,
+) -> u32 {
+    let Some(offer) = train.ancillaries.iter().find(|offer| offer.kind == kind) else {
+        return 0;
+    };
+    let sold = bookings
+        .values()
+        .filter(|booking| booking.train_line == train.line && booking.travel_date == travel_date)
+        .filter(|booking| Some(booking.id.as_str()) != excluding)
+        .flat_map(|booking| &booking.ancillaries)
+        .filter(|ancillary| ancillary.kind == kind)
+        .count() as u32;
+    offer.limit.saturating_sub(sold)
+}
+
+/// Checks the extras are in stock for the travel date and prices them.
+pub fn reserve_ancillaries(
+    bookings: &BookingList,
+    train: &Train,
+    travel_date: NaiveDate,
+    kinds: &[AncillaryKind],
+    excluding: Option<&str>,
+) -> Result<Vec<Ancillary>> {
+    kinds
+        .iter()
+        .map(|&kind| {
+            let offer = train
+                .ancillaries
+                .iter()
+                .find(|offer| offer.kind == kind)
+                .ok_or_else(|| anyhow!("Train {} does not offer {}", train.line, kind))?;
+            if ancillaries_left(bookings, train, travel_date, kind, excluding) == 0 {
+                return Err(anyhow!("No {} left on train {} on {}", kind, train.line, travel_date));
+            }
+            Ok(Ancillary {
+                kind,
+                price: offer.price,
+            })
+        })
+        .collect()
+}
+
+/// Adds extras to an existing booking, replacing any it already had.
+pub fn add_ancillaries(
+    bookings: &mut BookingList,
+    trains: &TrainList,
+    booking_id: &str,
+    kinds: &[AncillaryKind],
+) -> Result<()> {
+    let booking = bookings.get(booking_id).ok_or_else(|| anyhow!("Booking not found"))?;
+    let train = trains
+        .get(&booking.train_line)
+        .ok_or_else(|| anyhow!("Train not found"))?;
+
+    let ancillaries = reserve_ancillaries(bookings, train, booking.travel_date, kinds, Some(booking_id))?;
+    bookings.get_mut(booking_id).unwrap().ancillaries = ancillaries;
+    Ok(())
+}
+
+/// Lets the agent pick extras still in stock; trains without extras skip the question.
+pub fn prompt_ancillaries(
+    bookings: &BookingList,
+    train: &Train,
+    travel_date: NaiveDate,
+) -> Result<Vec<AncillaryKind>> {
+    let available: Vec<(&AncillaryOffer, u32)> = train
+        .ancillaries
+        .iter()
+        .map(|offer| (offer, ancillaries_left(bookings, train, travel_date, offer.kind, None)))
+        .filter(|(_, left)| *left > 0)
+        .collect();
+    if available.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let answer = requestty::prompt_one(
+        Question::multi_select("ancillaries")
+            .message("Select any extras")
+            .choices(
+                available
+                    .iter()
+                    .map(|(offer, left)| format!("{} {} ({} left)", offer.kind, format_money(offer.price), left)),
+            )
+            .build(),
+    )?;
+    Ok(answer
+        .as_list_items()
+        .unwrap()
+        .iter()
+        .map(|item| available[item.index].0.kind)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::remove_booking;
+    use crate::passengers::PassengerList;
+    use crate::test_support::{adult, book, express, travel_date};
+
+    fn setup() -> (BookingList, PassengerList, TrainList) {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+
+        let mut train = express();
+        train.fare = 2500;
+        train.ancillaries = parse_ancillary_offers("bike:5.00:1, meal:12.50:40").unwrap();
+        trains.insert(1, train);
+        for id in ["P1", "P2"] {
+            passengers.insert(id.to_string(), adult(id, &format!("Passenger {}", id)));
+            book(&mut bookings, &mut passengers, &mut trains, id, 1).unwrap();
+        }
+
+        (bookings, passengers, trains)
+    }
+
+    #[test]
+    fn test_parse_ancillary_offers() {
+        let offers = parse_ancillary_offers("Bike:5:4, meal:12.50:40").unwrap();
+        assert_eq!(
+            offers[0],
+            AncillaryOffer {
+                kind: AncillaryKind::Bicycle,
+                price: 500,
+                limit: 4
+            }
+        );
+        assert_eq!(format_ancillary_offers(&offers), "bike:5.00:4, meal:12.50:40");
+        assert!(parse_ancillary_offers("").unwrap().is_empty());
+        assert!(parse_ancillary_offers("bike:5.00").is_err());
+        assert!(parse_ancillary_offers("horse:5.00:1").is_err());
+        assert!(parse_ancillary_offers("pet:5.00:1, pet:6.00:2").is_err());
+    }
+
+    #[test]
+    fn test_add_ancillaries() {
+        let (mut bookings, _, trains) = setup();
+
+        add_ancillaries(
+            &mut bookings,
+            &trains,
+            "P1_1_20240610",
+            &[AncillaryKind::Bicycle, AncillaryKind::Meal],
+        )
+        .unwrap();
+        assert_eq!(bookings["P1_1_20240610"].total(), 2500 + 500 + 1250);
+        assert_eq!(
+            format_ancillaries(&bookings["P1_1_20240610"].ancillaries),
+            "Bicycle, Meal"
+        );
+
+        // The only bike rack is taken, meals are still available
+        assert_eq!(
+            add_ancillaries(&mut bookings, &trains, "P2_1_20240610", &[AncillaryKind::Bicycle])
+                .unwrap_err()
+                .to_string(),
+            "No Bicycle left on train 1 on 2024-06-10"
+        );
+        assert_eq!(
+            add_ancillaries(&mut bookings, &trains, "P2_1_20240610", &[AncillaryKind::Pet])
+                .unwrap_err()
+                .to_string(),
+            "Train 1 does not offer Pet"
+        );
+        add_ancillaries(&mut bookings, &trains, "P2_1_20240610", &[AncillaryKind::Meal]).unwrap();
+        assert_eq!(
+            ancillaries_left(&bookings, &trains[&1], travel_date(), AncillaryKind::Meal, None),
+            38
+        );
+
+        // Replacing a booking's own extras does not count them against it
+        add_ancillaries(&mut bookings, &trains, "P1_1_20240610", &[AncillaryKind::Bicycle]).unwrap();
+        assert_eq!(bookings["P1_1_20240610"].total(), 3000);
+    }
+
+    #[test]
+    fn test_ancillaries_sold_out_until_cancelled() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+
+        add_ancillaries(&mut bookings, &trains, "P1_1_20240610", &[AncillaryKind::Bicycle]).unwrap();
+        assert_eq!(
+            ancillaries_left(&bookings, &trains[&1], travel_date(), AncillaryKind::Bicycle, None),
+            0
+        );
+        // Stock is per travel date, so the next day still has its rack
+        let next_day = travel_date().succ_opt().unwrap();
+        assert_eq!(
+            ancillaries_left(&bookings, &trains[&1], next_day, AncillaryKind::Bicycle, None),
+            1
+        );
+        // A train without the extra never has any left
+        assert_eq!(
+            ancillaries_left(&bookings, &trains[&1], travel_date(), AncillaryKind::Pet, None),
+            0
+        );
+
+        // Cancelling the booking puts its bike rack back on sale
+        remove_booking(&mut bookings, &mut passengers, &mut trains, "P1_1_20240610".to_string()).unwrap();
+        add_ancillaries(&mut bookings, &trains, "P2_1_20240610", &[AncillaryKind::Bicycle]).unwrap();
+        assert_eq!(
+            ancillaries_left(&bookings, &trains[&1], travel_date(), AncillaryKind::Bicycle, None),
+            0
+        );
+    }
+}
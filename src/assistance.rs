@@ -0,0 +1,307 @@
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use requestty::{self, Question};
+
+use crate::bookings::{BookingList, BookingStatus};
+use crate::disruptions::expected_times;
+use crate::passengers::PassengerList;
+use crate::trains::{Train, TrainList};
+
+/// Wheelchair spaces on a new train. Spaces are part of the train's capacity, not extra seats.
+pub const DEFAULT_ACCESSIBLE_SPACES: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessibilityNeed {
+    Wheelchair, // Takes one of the train's accessible spaces
+    BoardingRamp,
+    SightAssistance,
+    MobilityAssistance,
+}
+
+impl AccessibilityNeed {
+    pub const ALL: [AccessibilityNeed; 4] = [
+        AccessibilityNeed::Wheelchair,
+        AccessibilityNeed::BoardingRamp,
+        AccessibilityNeed::SightAssistance,
+        AccessibilityNeed::MobilityAssistance,
+    ];
+}
+
+impl std::fmt::Display for AccessibilityNeed {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            AccessibilityNeed::Wheelchair => write!(f, "Wheelchair space"),
+            AccessibilityNeed::BoardingRamp => write!(f, "Boarding ramp"),
+            AccessibilityNeed::SightAssistance => write!(f, "Sight assistance"),
+            AccessibilityNeed::MobilityAssistance => write!(f, "Mobility assistance"),
+        }
+    }
+}
+
+pub fn format_needs(needs: &[AccessibilityNeed]) -> String {
+    if needs.is_empty() {
+        return "None".to_string();
+    }
+    needs.iter().map(|need| need.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Checks a wheelchair space is still free on the train for the travel date.
+pub fn check_accessible_space(
+    bookings: &BookingList,
+    train: &Train,
+    travel_date: NaiveDate,
+    needs: &[AccessibilityNeed],
+    excluding: Option<&str>,
+) -> Result<()> {
+    if !needs.contains(&AccessibilityNeed::Wheelchair) {
+        return Ok(());
+    }
+    let taken = bookings
+        .values()
+        .filter(|booking| booking.train_line == train.line && booking.travel_date == travel_date)
+        .filter(|booking| Some(booking.id.as_str()) != excluding)
+        .filter(|booking| booking.assistance.contains(&AccessibilityNeed::Wheelchair))
+        .count() as u32;
+    if taken >= train.accessible_spaces {
+        return Err(anyhow!(
+            "No wheelchair spaces left on train {} on {}",
+            train.line,
+            travel_date
+        ));
+    }
+    Ok(())
+}
+
+/// Multi-select question for a passenger's accessibility needs, with their current needs ticked.
+pub fn accessibility_question(current: &[AccessibilityNeed]) -> Question<'static> {
+    Question::multi_select("accessibility")
+        .message("Select any accessibility needs")
+        .choices_with_default(
+            AccessibilityNeed::ALL
+                .iter()
+                .map(|need| (need.to_string(), current.contains(need)))
+                .collect::<Vec<_>>(),
+        )
+        .build()
+}
+
+pub fn parse_accessibility_answer(answer: Option<&requestty::Answer>) -> Vec<AccessibilityNeed> {
+    answer
+        .and_then(|answer| answer.as_list_items())
+        .map(|items| items.iter().map(|item| AccessibilityNeed::ALL[item.index]).collect())
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TaskKind {
+    Boarding,
+    Alighting,
+}
+
+impl std::fmt::Display for TaskKind {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            TaskKind::Boarding => write!(f, "Boarding"),
+            TaskKind::Alighting => write!(f, "Alighting"),
+        }
+    }
+}
+
+/// One passenger the staff at a station have to help on or off a train.
+#[derive(Debug, PartialEq)]
+pub struct AssistanceTask {
+    pub station: String,
+    pub time: NaiveDateTime, // Expected departure or arrival, including delays
+    pub train_line: u32,
+    pub kind: TaskKind,
+    pub booking_id: String,
+    pub passenger_name: String,
+    pub seat: u32,
+    pub needs: Vec<AccessibilityNeed>,
+}
+
+/// Boardings and alightings needing assistance on a date, by station, time and train.
+pub fn assistance_report(
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    trains: &TrainList,
+    travel_date: NaiveDate,
+) -> Vec<AssistanceTask> {
+    let mut tasks = Vec::new();
+    for booking in bookings.values() {
+        if booking.travel_date != travel_date
+            || booking.assistance.is_empty()
+            || !matches!(booking.status, BookingStatus::Booked | BookingStatus::Boarded)
+        {
+            continue;
+        }
+        let Some(train) = trains.get(&booking.train_line) else {
+            continue;
+        };
+        let Some((departure, arrival)) = expected_times(train, travel_date) else {
+            continue;
+        };
+        let passenger_name = passengers
+            .get(&booking.passenger_id)
+            .map_or_else(|| "Unknown passenger".to_string(), |passenger| passenger.name.clone());
+        for (kind, station, time) in [
+            (TaskKind::Boarding, &train.origin, departure),
+            (TaskKind::Alighting, &train.destination, arrival),
+        ] {
+            tasks.push(AssistanceTask {
+                station: station.clone(),
+                time,
+                train_line: train.line,
+                kind,
+                booking_id: booking.id.clone(),
+                passenger_name: passenger_name.clone(),
+                seat: booking.seat,
+                needs: booking.assistance.clone(),
+            });
+        }
+    }
+    tasks.sort_by(|a, b| (&a.station, a.time, a.train_line, a.seat).cmp(&(&b.station, b.time, b.train_line, b.seat)));
+    tasks
+}
+
+pub fn print_assistance_report(
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    trains: &TrainList,
+    travel_date: NaiveDate,
+) {
+    let tasks = assistance_report(bookings, passengers, trains, travel_date);
+    if tasks.is_empty() {
+        println!("No assistance booked on {}", travel_date);
+        return;
+    }
+    let mut station = None;
+    let mut train = None;
+    for task in &tasks {
+        if station != Some(&task.station) {
+            println!("\n{}", task.station);
+            station = Some(&task.station);
+            train = None;
+        }
+        if train != Some((task.train_line, task.kind)) {
+            println!(
+                "  {} train {} at {}",
+                task.kind,
+                task.train_line,
+                task.time.format("%H:%M")
+            );
+            train = Some((task.train_line, task.kind));
+        }
+        println!(
+            "    Seat {}: {} ({}) - {}",
+            task.seat,
+            task.passenger_name,
+            task.booking_id,
+            format_needs(&task.needs)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::add_booking;
+    use crate::minors::Supervision;
+    use crate::rules::RulePipeline;
+    use crate::test_support::{adult, express, travel_date};
+
+    fn setup() -> (BookingList, PassengerList, TrainList) {
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+        for (id, needs) in [
+            (
+                "P1",
+                vec![AccessibilityNeed::Wheelchair, AccessibilityNeed::BoardingRamp],
+            ),
+            ("P2", vec![AccessibilityNeed::Wheelchair]),
+            ("P3", vec![AccessibilityNeed::SightAssistance]),
+            ("P4", vec![]),
+        ] {
+            let mut passenger = adult(id, &format!("Passenger {}", id));
+            passenger.accessibility = needs;
+            passengers.insert(id.to_string(), passenger);
+        }
+        trains.insert(1, express());
+        trains.get_mut(&1).unwrap().accessible_spaces = 1;
+        (BookingList::new(), passengers, trains)
+    }
+
+    fn book(
+        bookings: &mut BookingList,
+        passengers: &mut PassengerList,
+        trains: &mut TrainList,
+        passenger_id: &str,
+    ) -> Result<String> {
+        add_booking(
+            bookings,
+            passengers,
+            trains,
+            &RulePipeline::default(),
+            passenger_id.to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+    }
+
+    #[test]
+    fn test_accessible_spaces() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+
+        let first = book(&mut bookings, &mut passengers, &mut trains, "P1").unwrap();
+        assert_eq!(
+            bookings[&first].assistance,
+            vec![AccessibilityNeed::Wheelchair, AccessibilityNeed::BoardingRamp]
+        );
+        assert_eq!(
+            book(&mut bookings, &mut passengers, &mut trains, "P2")
+                .unwrap_err()
+                .to_string(),
+            "No wheelchair spaces left on train 1 on 2024-06-10"
+        );
+        // Other needs and other passengers are not limited by the spaces
+        book(&mut bookings, &mut passengers, &mut trains, "P3").unwrap();
+        book(&mut bookings, &mut passengers, &mut trains, "P4").unwrap();
+    }
+
+    #[test]
+    fn test_assistance_report() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        for id in ["P1", "P3", "P4"] {
+            book(&mut bookings, &mut passengers, &mut trains, id).unwrap();
+        }
+
+        let tasks = assistance_report(&bookings, &passengers, &trains, travel_date());
+        let summary: Vec<(&str, TaskKind, &str)> = tasks
+            .iter()
+            .map(|task| (task.station.as_str(), task.kind, task.booking_id.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Hamilton", TaskKind::Alighting, "P1_1_20240610"),
+                ("Hamilton", TaskKind::Alighting, "P3_1_20240610"),
+                ("Toronto", TaskKind::Boarding, "P1_1_20240610"),
+                ("Toronto", TaskKind::Boarding, "P3_1_20240610"),
+            ]
+        );
+        assert_eq!(tasks[0].time, travel_date().and_hms_opt(12, 0, 0).unwrap());
+
+        bookings.get_mut("P3_1_20240610").unwrap().status = BookingStatus::NoShow;
+        assert_eq!(
+            assistance_report(&bookings, &passengers, &trains, travel_date()).len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use anyhow::{anyhow, Result};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use requestty::{self, Question};
+use std::path::Path;
+
+use crate::bookings::{record_event, Booking, BookingHistory, BookingList, BookingStatus, EventKind};
+use crate::disruptions::expected_times;
+use crate::helper::{clear_screen, continue_prompt, parse_date};
+use crate::passengers::PassengerList;
+use crate::passes::check_pass_at_boarding;
+use crate::signing::{load_verifying_key, verify_ticket, VERIFYING_KEY_PATH};
+use crate::trains::TrainList;
+
+/// Marks a booking as boarded from either its booking reference or a scanned signed ticket code.
+/// Trips on a pass are only boarded while the pass is still valid.
+pub fn board(
+    bookings: &mut BookingList,
+    passengers: &PassengerList,
+    train_line: u32,
+    travel_date: NaiveDate,
+    reference: &str,
+) -> Result<String> {
+    let reference = reference.trim();
+    let ticket = if reference.contains('.') {
+        Some(verify_ticket(
+            &load_verifying_key(Path::new(VERIFYING_KEY_PATH))?,
+            reference,
+        )?)
+    } else {
+        None
+    };
+    let booking_id = ticket
+        .as_ref()
+        .map_or_else(|| reference.to_string(), |ticket| ticket.booking_id.clone());
+
+    let booking = bookings
+        .get_mut(&booking_id)
+        .ok_or_else(|| anyhow!("Booking {} not found", booking_id))?;
+    if booking.train_line != train_line || booking.travel_date != travel_date {
+        return Err(anyhow!(
+            "Booking {} is for train {} on {}",
+            booking_id,
+            booking.train_line,
+            booking.travel_date
+        ));
+    }
+    // A ticket issued before the booking was changed must not board
+    if let Some(ticket) = &ticket {
+        ticket.check_against(booking)?;
+    }
+    match booking.status {
+        BookingStatus::Booked if booking.held_until.is_some() => {
+            Err(anyhow!("Booking {} is held and has not been paid", booking_id))
+        }
+        BookingStatus::Booked => {
+            check_pass_at_boarding(passengers, booking)?;
+            booking.status = BookingStatus::Boarded;
+            Ok(booking_id)
+        }
+        BookingStatus::Boarded => Err(anyhow!("Booking {} has already boarded", booking_id)),
+        BookingStatus::NoShow => Err(anyhow!("Booking {} was marked as a no-show", booking_id)),
+        BookingStatus::Completed => Err(anyhow!("Booking {} has already travelled", booking_id)),
+    }
+}
+
+/// Bookings on the train that have not boarded yet, in seat order.
+pub fn outstanding(
+    bookings: &BookingList,
+    train_line: u32,
+    travel_date: NaiveDate,
+) -> Vec<&Booking> {
+    let mut outstanding: Vec<&Booking> = bookings
+        .values()
+        .filter(|booking| booking.train_line == train_line && booking.travel_date == travel_date)
+        .filter(|booking| booking.status == BookingStatus::Booked)
+        .collect();
+    outstanding.sort_by_key(|booking| booking.seat);
+    outstanding
+}
+
+/// Once the train has departed, allowing for any delay, marks every booking that did not board as a no-show.
+pub fn mark_no_shows(
+    bookings: &mut BookingList,
+    trains: &TrainList,
+    train_line: u32,
+    travel_date: NaiveDate,
+    now: NaiveDateTime,
+) -> Result<Vec<String>> {
+    let train = trains.get(&train_line).ok_or_else(|| anyhow!("Train not found"))?;
+    let (departure, _) = expected_times(train, travel_date)
+        .ok_or_else(|| anyhow!("Train {} is cancelled on {}", train_line, travel_date))?;
+    if now < departure {
+        return Err(anyhow!("Train {} has not departed yet", train_line));
+    }
+
+    let booking_ids: Vec<String> = outstanding(bookings, train_line, travel_date)
+        .iter()
+        .map(|booking| booking.id.clone())
+        .collect();
+    for booking_id in &booking_ids {
+        bookings.get_mut(booking_id).unwrap().status = BookingStatus::NoShow;
+    }
+    Ok(booking_ids)
+}
+
+pub fn manage_boarding(
+    bookings: &mut BookingList,
+    passengers: &PassengerList,
+    trains: &TrainList,
+    history: &mut BookingHistory,
+) -> Result<()> {
+    if trains.is_empty() {
+        println!("No trains found");
+        continue_prompt();
+        return Ok(());
+    }
+
+    let train_choices: Vec<String> = trains
+        .values()
+        .map(|train| format!("{}, {}", train.line, train.name))
+        .collect();
+    let answers = requestty::prompt(vec![
+        Question::select("train")
+            .message("Select train to board")
+            .choices(train_choices)
+            .build(),
+        Question::input("travel_date")
+            .message("Enter the travel date (YYYY-MM-DD)")
+            .default(Local::now().date_naive().to_string())
+            .build(),
+    ])?;
+    let train_line: u32 = answers["train"]
+        .as_list_item()
+        .unwrap()
+        .text
+        .split(',')
+        .next()
+        .unwrap()
+        .trim()
+        .parse()?;
+    let travel_date = match parse_date(answers["travel_date"].as_string().unwrap()) {
+        Ok(date) => date,
+        Err(e) => {
+            println!("Error: {}", e);
+            continue_prompt();
+            return Ok(());
+        }
+    };
+
+    loop {
+        clear_screen();
+        let action = requestty::prompt_one(
+            Question::select("action")
+                .message(format!("Boarding train {} on {}", train_line, travel_date))
+                .choice("Scan or enter reference")
+                .choice("List outstanding passengers")
+                .choice("Mark no-shows")
+                .choice("Back")
+                .build(),
+        )?;
+
+        match action.as_list_item().unwrap().index {
+            0 => {
+                let reference = requestty::prompt_one(
+                    Question::input("reference")
+                        .message("Scan the ticket or enter the booking reference")
+                        .build(),
+                )?;
+                match board(
+                    bookings,
+                    passengers,
+                    train_line,
+                    travel_date,
+                    reference.as_string().unwrap(),
+                ) {
+                    Ok(booking_id) => {
+                        let booking = &bookings[&booking_id];
+                        println!(
+                            "Boarded {} in seat {}",
+                            passengers
+                                .get(&booking.passenger_id)
+                                .map_or(booking.passenger_id.as_str(), |passenger| passenger.name.as_str()),
+                            booking.seat
+                        );
+                        record_event(
+                            history,
+                            booking,
+                            Local::now().naive_local(),
+                            EventKind::Boarded,
+                            "Boarded".to_string(),
+                        );
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            1 => {
+                let outstanding = outstanding(bookings, train_line, travel_date);
+                if outstanding.is_empty() {
+                    println!("All passengers have boarded");
+                }
+                for booking in outstanding {
+                    println!(
+                        "Seat {}: {} ({}), booking {}",
+                        booking.seat,
+                        passengers
+                            .get(&booking.passenger_id)
+                            .map_or("Unknown passenger", |passenger| passenger.name.as_str()),
+                        booking.passenger_id,
+                        booking.id
+                    );
+                }
+            }
+            2 => {
+                let now = Local::now().naive_local();
+                match mark_no_shows(bookings, trains, train_line, travel_date, now) {
+                    Ok(booking_ids) => {
+                        for booking_id in &booking_ids {
+                            record_event(
+                                history,
+                                &bookings[booking_id],
+                                now,
+                                EventKind::NoShow,
+                                "No-show".to_string(),
+                            );
+                        }
+                        println!("Marked {} bookings as no-shows", booking_ids.len());
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            3 => return Ok(()),
+            _ => unreachable!(),
+        }
+        continue_prompt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::edit_booking;
+    use crate::rules::RulePipeline;
+    use crate::signing::TicketPayload;
+    use crate::test_support::{adult, book, express, travel_date};
+
+    fn setup() -> (BookingList, PassengerList, TrainList) {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+
+        for (id, name) in [("P1", "John Doe"), ("P2", "Jane Doe")] {
+            passengers.insert(id.to_string(), adult(id, name));
+        }
+        trains.insert(1, express());
+        for id in ["P1", "P2"] {
+            book(&mut bookings, &mut passengers, &mut trains, id, 1).unwrap();
+        }
+
+        (bookings, passengers, trains)
+    }
+
+    #[test]
+    fn test_board_by_reference() {
+        let (mut bookings, passengers, _) = setup();
+
+        assert_eq!(
+            board(&mut bookings, &passengers, 1, travel_date(), " P1_1_20240610 ").unwrap(),
+            "P1_1_20240610"
+        );
+        assert_eq!(bookings["P1_1_20240610"].status, BookingStatus::Boarded);
+        assert!(board(&mut bookings, &passengers, 1, travel_date(), "P1_1_20240610").is_err());
+        assert!(board(&mut bookings, &passengers, 2, travel_date(), "P2_1_20240610").is_err());
+        assert!(board(&mut bookings, &passengers, 1, travel_date(), "P9_1_20240610").is_err());
+
+        let outstanding = outstanding(&bookings, 1, travel_date());
+        assert_eq!(outstanding.len(), 1);
+        assert_eq!(outstanding[0].id, "P2_1_20240610");
+    }
+
+    #[test]
+    fn test_mark_no_shows_after_departure() {
+        let (mut bookings, passengers, trains) = setup();
+        board(&mut bookings, &passengers, 1, travel_date(), "P1_1_20240610").unwrap();
+
+        let before = travel_date().and_hms_opt(10, 59, 0).unwrap();
+        assert!(mark_no_shows(&mut bookings, &trains, 1, travel_date(), before).is_err());
+
+        let after = travel_date().and_hms_opt(11, 5, 0).unwrap();
+        let no_shows = mark_no_shows(&mut bookings, &trains, 1, travel_date(), after).unwrap();
+        assert_eq!(no_shows, vec!["P2_1_20240610".to_string()]);
+        assert_eq!(bookings["P1_1_20240610"].status, BookingStatus::Boarded);
+        assert_eq!(bookings["P2_1_20240610"].status, BookingStatus::NoShow);
+        assert!(board(&mut bookings, &passengers, 1, travel_date(), "P2_1_20240610").is_err());
+    }
+
+    #[test]
+    fn test_changed_booking_rejects_old_ticket() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let ticket = TicketPayload::from_booking(&bookings["P1_1_20240610"]);
+        assert!(ticket.check_against(&bookings["P1_1_20240610"]).is_ok());
+
+        // Moving to the next day keeps the booking ID but the old ticket no longer matches
+        let next_day = travel_date().succ_opt().unwrap();
+        edit_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1_1_20240610".to_string(),
+            1,
+            next_day,
+        )
+        .unwrap();
+        assert_eq!(
+            ticket.check_against(&bookings["P1_1_20240610"]).unwrap_err().to_string(),
+            "Ticket for booking P1_1_20240610 shows train 1 on 2024-06-10 seat 1, but the booking is now train 1 on 2024-06-11 seat 1"
+        );
+
+        let mut moved_seat = TicketPayload::from_booking(&bookings["P2_1_20240610"]);
+        moved_seat.seat += 1;
+        assert!(moved_seat.check_against(&bookings["P2_1_20240610"]).is_err());
+    }
+}
@@ -1,16 +1,78 @@
 use anyhow::{Context, Result};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
 use requestty::{self, Question};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
 
 use crate::actions::{add_remove_prompt, Action};
-use crate::helper::{clear_screen, continue_prompt, parse_number_answer, parse_string_answer};
-use crate::passengers::{Passenger, PassengerList};
+use crate::ancillaries::{add_ancillaries, format_ancillaries, prompt_ancillaries, reserve_ancillaries, Ancillary};
+use crate::assistance::{check_accessible_space, print_assistance_report, AccessibilityNeed};
+use crate::corporate::{account_for, apply_corporate_rate, AccountList};
+use crate::disruptions::is_cancelled;
+use crate::fares::{quote_fare, release_bucket, reserve_bucket, AppliedLoadBand, FareQuote};
+use crate::helper::{
+    clear_screen, continue_prompt, format_money, parse_date, parse_number_answer, parse_string_answer,
+};
+use crate::invoices::Invoice;
+use crate::loyalty::LoyaltyProgramme;
+use crate::manifest::{Manifest, ManifestSort};
+use crate::minors::{prompt_supervision, Supervision};
+use crate::notifications::{notify, Notifier};
+use crate::passengers::PassengerList;
+use crate::passes::{find_pass, release_pass, use_pass};
+use crate::payments::{list_ledger, Ledger, PaymentMethod};
+use crate::policies::FeePolicy;
+use crate::promotions::{check_promotion, redeem_promotion, release_promotion, PromotionList};
+use crate::rules::{BookingRequest, RulePipeline};
+use crate::signing::{load_or_create_signing_key, SIGNING_KEY_PATH, VERIFYING_KEY_PATH};
+use crate::tickets::Ticket;
 use crate::trains::{Train, TrainList};
+use crate::vouchers::CREDIT_VALID_MONTHS;
+
+pub const HOLD_MINUTES: i64 = 30; // How long an unpaid seat is held before the scheduler releases it
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BookingStatus {
+    Booked,
+    Boarded,
+    NoShow,
+    Completed,
+}
+
+impl std::fmt::Display for BookingStatus {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            BookingStatus::Booked => write!(f, "Booked"),
+            BookingStatus::Boarded => write!(f, "Boarded"),
+            BookingStatus::NoShow => write!(f, "No-show"),
+            BookingStatus::Completed => write!(f, "Completed"),
+        }
+    }
+}
 
 pub struct Booking {
-    id: String,
-    passenger_id: String,
-    train_line: u32,
+    pub id: String,
+    pub passenger_id: String,
+    pub train_line: u32,
+    pub travel_date: NaiveDate,
+    pub seat: u32,
+    pub fare: u32,                   // Fare in cents before discounts
+    pub discount: u32,               // Discount in cents
+    pub promo_code: Option<String>,  // Promo code the discount came from
+    pub fare_bucket: Option<String>, // Fare bucket the seat was sold from
+    pub load_band: Option<AppliedLoadBand>,
+    pub status: BookingStatus,
+    pub supervision: Supervision,           // How a minor is looked after on the trip
+    pub warnings: Vec<String>,              // Rule warnings raised when the booking was made or last moved
+    pub assistance: Vec<AccessibilityNeed>, // Passenger's accessibility needs when the trip was booked
+    pub ancillaries: Vec<Ancillary>,        // Extras such as bike spaces and meals, at the price paid
+    pub pass: Option<String>,               // Pass covering the trip instead of a fare
+    pub account: Option<String>,            // Corporate account the trip was booked under
+    pub held_until: Option<NaiveDateTime>,  // Unpaid seat, released by the scheduler once this passes
 }
 
 impl Booking {
@@ -18,113 +80,150 @@ impl Booking {
         id: String,
         passenger_id: String,
         train_line: u32,
+        travel_date: NaiveDate,
+        seat: u32,
+        quote: FareQuote,
     ) -> Self {
         Self {
             id,
             passenger_id,
             train_line,
+            travel_date,
+            seat,
+            fare: quote.fare,
+            discount: 0,
+            promo_code: None,
+            fare_bucket: quote.fare_bucket,
+            load_band: quote.load_band,
+            status: BookingStatus::Booked,
+            supervision: Supervision::Alone,
+            warnings: Vec::new(),
+            assistance: Vec::new(),
+            ancillaries: Vec::new(),
+            pass: None,
+            account: None,
+            held_until: None,
         }
     }
+
+    /// Amount due for the trip, with promo and corporate discounts taken off the fare only.
+    pub fn total(&self) -> u32 {
+        self.fare.saturating_sub(self.discount) + self.ancillary_total()
+    }
+
+    pub fn ancillary_total(&self) -> u32 {
+        self.ancillaries.iter().map(|ancillary| ancillary.price).sum()
+    }
+
+    pub fn departure(
+        &self,
+        train: &Train,
+    ) -> NaiveDateTime {
+        self.travel_date.and_time(train.departure)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+    Booked,
+    Changed,
+    Cancelled,
+    Boarded,
+    NoShow,
+    Disrupted,
+    Rebooked,
+    Reminder,
+    Completed,
+}
+
+pub struct BookingEvent {
+    pub booking_id: String,
+    pub passenger_id: String,
+    pub timestamp: NaiveDateTime,
+    pub kind: EventKind,
+    pub description: String,
+}
+
+/// Log of everything that happened to each booking, kept after a booking is cancelled.
+pub type BookingHistory = Vec<BookingEvent>;
+
+pub fn record_event(
+    history: &mut BookingHistory,
+    booking: &Booking,
+    timestamp: NaiveDateTime,
+    kind: EventKind,
+    description: String,
+) {
+    history.push(BookingEvent {
+        booking_id: booking.id.clone(),
+        passenger_id: booking.passenger_id.clone(),
+        timestamp,
+        kind,
+        description,
+    });
 }
 
 enum BookingListType {
     All,
     Passenger,
     Train,
+    Ledger,
+    History,
+    Invoice,
+    Ticket,
+    Assistance,
 }
 
 pub type BookingList = HashMap<String, Booking>;
 
+#[allow(clippy::too_many_arguments)]
 pub fn manage_bookings(
     bookings: &mut BookingList,
     passengers: &mut PassengerList,
     trains: &mut TrainList,
+    rules: &RulePipeline,
+    promotions: &mut PromotionList,
+    accounts: &AccountList,
+    ledger: &mut Ledger,
+    loyalty: &mut LoyaltyProgramme,
+    policy: &FeePolicy,
+    history: &mut BookingHistory,
+    notifier: &mut Notifier,
 ) -> Result<()> {
     loop {
+        notify(notifier, history, bookings, passengers, trains);
         clear_screen();
         match add_remove_prompt()? {
             Action::List => {
                 let list_type = list_bookings()?;
-                manage_list_bookings(bookings, passengers, trains, list_type)?;
+                manage_list_bookings(bookings, passengers, trains, ledger, history, list_type)?;
                 continue_prompt();
             }
             Action::Add => {
-                let passenger_choices: Vec<String> = passengers
-                    .values()
-                    .map(|passenger| format!("{}, {}", passenger.id, passenger.name))
-                    .collect();
-                let train_choices: Vec<String> = trains
-                    .values()
-                    .map(|train| format!("{}, {}", train.line, train.name))
-                    .collect();
-
-                let questions: Vec<Question> = vec![
-                    Question::select("passenger")
-                        .message("Select passenger")
-                        .choices(passenger_choices)
-                        .build(),
-                    Question::select("train")
-                        .message("Select train")
-                        .choices(train_choices)
-                        .build(),
-                ];
-
-                let answers = requestty::prompt(questions)?;
-
-                let passenger_id = parse_string_answer(&answers, "passenger", ",");
-                let train_line = parse_number_answer(answers, "train", ",")?;
-                add_booking(bookings, passengers, trains, passenger_id, train_line)?;
+                if let Err(e) = book_trip(
+                    bookings, passengers, trains, rules, promotions, accounts, ledger, loyalty, history,
+                ) {
+                    println!("Error: {}", e);
+                }
+                continue_prompt();
             }
             Action::Remove => {
                 if bookings.is_empty() {
                     println!("No bookings to remove");
-                    continue_prompt();
-                } else {
-                    let booking_choices: Vec<String> = bookings
-                        .values()
-                        .map(|booking| format!("{}, {}", booking.id, booking.train_line))
-                        .collect();
-                    let question = Question::select("booking_list")
-                        .message("Select booking to delete")
-                        .choices(booking_choices)
-                        .build();
-                    let selection = requestty::prompt_one(question)?;
-                    let selected_booking = selection.as_list_item().unwrap().text.clone();
-                    remove_booking(bookings, passengers, trains, selected_booking)?;
+                } else if let Err(e) = cancel_trip(bookings, passengers, trains, ledger, loyalty, policy, history) {
+                    println!("Error: {}", e);
                 }
+                continue_prompt();
             }
             Action::Edit => {
                 if bookings.is_empty() {
                     println!("No bookings to edit");
-                    continue_prompt();
-                } else {
-                    let booking_choices: Vec<String> = bookings
-                        .values()
-                        .map(|booking| format!("{}, {}", booking.id, booking.train_line))
-                        .collect();
-                    let question = Question::select("booking_list")
-                        .message("Select booking to edit")
-                        .choices(booking_choices)
-                        .build();
-                    let selection = requestty::prompt_one(question)?;
-                    let selected_booking = selection.as_list_item().unwrap().text.clone();
-                    let booking_id = selected_booking.split(',').next().unwrap().trim().to_string();
-
-                    let questions: Vec<Question> = vec![Question::select("train")
-                        .message("Select new train")
-                        .choices(
-                            trains
-                                .values()
-                                .map(|train| requestty::Choice(format!("{}, {}", train.line, train.name)))
-                                .collect::<Vec<_>>(),
-                        )
-                        .build()];
-
-                    let answers = requestty::prompt(questions)?;
-
-                    let train_line = parse_number_answer(answers, "train", ",")?;
-                    edit_booking(bookings, passengers, trains, booking_id, train_line)?;
+                } else if let Err(e) = change_trip(
+                    bookings, passengers, trains, rules, accounts, ledger, loyalty, policy, history,
+                ) {
+                    println!("Error: {}", e);
                 }
+                continue_prompt();
             }
             Action::Back => {
                 break;
@@ -135,12 +234,576 @@ pub fn manage_bookings(
     Ok(())
 }
 
+/// Prompts for a new booking, applies any promo code and charges the fare, partly or wholly from
+/// a voucher. The booking is only kept once the payment goes through.
+#[allow(clippy::too_many_arguments)]
+fn book_trip(
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    trains: &mut TrainList,
+    rules: &RulePipeline,
+    promotions: &mut PromotionList,
+    accounts: &AccountList,
+    ledger: &mut Ledger,
+    loyalty: &mut LoyaltyProgramme,
+    history: &mut BookingHistory,
+) -> Result<()> {
+    let passenger_choices: Vec<String> = passengers
+        .values()
+        .map(|passenger| format!("{}, {}", passenger.id, passenger.name))
+        .collect();
+    let train_choices: Vec<String> = trains
+        .values()
+        .map(|train| format!("{}, {}", train.line, train.name))
+        .collect();
+
+    let questions: Vec<Question> = vec![
+        Question::select("passenger")
+            .message("Select passenger")
+            .choices(passenger_choices)
+            .build(),
+        Question::select("train")
+            .message("Select train")
+            .choices(train_choices)
+            .build(),
+        Question::input("travel_date")
+            .message("Enter the travel date (YYYY-MM-DD)")
+            .default(Local::now().date_naive().to_string())
+            .build(),
+        Question::input("promo_code")
+            .message("Enter a promo code (blank for none)")
+            .build(),
+    ];
+
+    let answers = requestty::prompt(questions)?;
+
+    let passenger_id = parse_string_answer(&answers, "passenger", ",");
+    let promo_code = answers
+        .get("promo_code")
+        .unwrap()
+        .as_string()
+        .unwrap()
+        .trim()
+        .to_uppercase();
+    let travel_date = parse_date(answers.get("travel_date").unwrap().as_string().unwrap())?;
+    let train_line = parse_number_answer(answers, "train", ",")?;
+    let today = Local::now().date_naive();
+
+    let account = account_for(accounts, &passenger_id);
+    if let Some(account) = account.filter(|_| !promo_code.is_empty()) {
+        return Err(anyhow::anyhow!(
+            "Promo codes cannot be combined with the corporate rate of {} ({})",
+            account.name,
+            account.id
+        ));
+    }
+    if !promo_code.is_empty() {
+        check_promotion(promotions, &promo_code, &trains[&train_line], today)?;
+    }
+
+    let supervision = prompt_supervision(
+        bookings,
+        passengers,
+        passengers
+            .get(&passenger_id)
+            .ok_or_else(|| anyhow::anyhow!("Passenger not found"))?,
+        &trains[&train_line],
+        travel_date,
+    )?;
+    let extras = prompt_ancillaries(bookings, &trains[&train_line], travel_date)?;
+    let booking_id = add_booking(
+        bookings,
+        passengers,
+        trains,
+        rules,
+        passenger_id,
+        train_line,
+        travel_date,
+        supervision,
+    )?;
+    if let Err(e) = add_ancillaries(bookings, trains, &booking_id, &extras) {
+        return abandon_booking(bookings, passengers, trains, promotions, booking_id, e);
+    }
+    let use_pass_answer = match offer_pass(bookings, passengers, trains, &booking_id) {
+        Ok(used) => used,
+        Err(e) => return abandon_booking(bookings, passengers, trains, promotions, booking_id, e),
+    };
+    let priced = match account {
+        Some(account) => apply_corporate_rate(bookings, account, &booking_id),
+        None if !promo_code.is_empty() && !use_pass_answer => {
+            apply_promotion(bookings, promotions, trains, &booking_id, &promo_code, today)
+        }
+        None => Ok(()),
+    };
+    if let Err(e) = priced {
+        return abandon_booking(bookings, passengers, trains, promotions, booking_id, e);
+    }
+    let total = bookings[&booking_id].total();
+
+    let now = Local::now().naive_local();
+    let mut payment = Vec::new();
+    if let Some(pass_id) = &bookings[&booking_id].pass {
+        payment.push(format!("covered by pass {}", pass_id));
+    }
+    let hold = match total {
+        0 => false,
+        _ => match prompt_hold() {
+            Ok(hold) => hold,
+            Err(e) => return abandon_booking(bookings, passengers, trains, promotions, booking_id, e),
+        },
+    };
+    if hold {
+        let held_until = now + Duration::minutes(HOLD_MINUTES);
+        bookings.get_mut(&booking_id).unwrap().held_until = Some(held_until);
+        payment.push(format!("held unpaid until {}", held_until.format("%H:%M")));
+    } else if total > 0 {
+        match collect_payment(accounts, ledger, loyalty, &bookings[&booking_id], total, now) {
+            Ok(charges) => payment.extend(charges),
+            Err(e) => return abandon_booking(bookings, passengers, trains, promotions, booking_id, e),
+        }
+    }
+    let payment = if payment.is_empty() {
+        "nothing to pay".to_string()
+    } else {
+        payment.join(", ")
+    };
+
+    record_event(
+        history,
+        &bookings[&booking_id],
+        now,
+        EventKind::Booked,
+        format!("Booked train {} on {}, {}", train_line, travel_date, payment),
+    );
+    println!("Booking {} confirmed, {}", booking_id, payment);
+    for warning in &bookings[&booking_id].warnings {
+        println!("Warning: {}", warning);
+    }
+    print_ticket(bookings, passengers, trains, &booking_id)
+}
+
+fn prompt_hold() -> Result<bool> {
+    let answer = requestty::prompt_one(
+        Question::confirm("hold")
+            .message(format!("Hold the seat for {} minutes and pay later?", HOLD_MINUTES))
+            .default(false)
+            .build(),
+    )?;
+    Ok(answer.as_bool().unwrap())
+}
+
+/// Offers to cover a new booking with one of the passenger's passes and returns whether it was used.
+fn offer_pass(
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    trains: &mut TrainList,
+    booking_id: &str,
+) -> Result<bool> {
+    let booking = &bookings[booking_id];
+    let Some(pass_id) = find_pass(
+        &passengers[&booking.passenger_id],
+        &trains[&booking.train_line],
+        booking.travel_date,
+    )
+    .map(|pass| pass.id.clone()) else {
+        return Ok(false);
+    };
+    let used = requestty::prompt_one(
+        Question::confirm("use_pass")
+            .message(format!("Use pass {} instead of paying the fare?", pass_id))
+            .default(true)
+            .build(),
+    )?
+    .as_bool()
+    .unwrap();
+    if used {
+        use_pass(bookings, passengers, trains, booking_id, &pass_id)?;
+    }
+    Ok(used)
+}
+
+/// Undoes a new booking that could not be completed, giving back its seat, pass ride and promo
+/// code use, and returns the error that stopped it.
+fn abandon_booking(
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    trains: &mut TrainList,
+    promotions: &mut PromotionList,
+    booking_id: String,
+    error: anyhow::Error,
+) -> Result<()> {
+    if let Some(code) = &bookings[&booking_id].promo_code {
+        release_promotion(promotions, code);
+    }
+    remove_booking(bookings, passengers, trains, booking_id)?;
+    Err(error)
+}
+
+/// Ways a booking can be paid. Only trips booked under a corporate account can be billed to it.
+fn payment_methods(booking: &Booking) -> Vec<PaymentMethod> {
+    PaymentMethod::ALL
+        .into_iter()
+        .filter(|method| *method != PaymentMethod::Account || booking.account.is_some())
+        .collect()
+}
+
+/// Prompts for how to pay for a booking and charges it. A voucher pays what its balance covers
+/// and the rest is charged another way; if that fails the voucher is credited back.
+fn collect_payment(
+    accounts: &AccountList,
+    ledger: &mut Ledger,
+    loyalty: &mut LoyaltyProgramme,
+    booking: &Booking,
+    total: u32,
+    now: NaiveDateTime,
+) -> Result<Vec<String>> {
+    let methods = payment_methods(booking);
+    let method = requestty::prompt_one(
+        Question::select("payment_method")
+            .message(format!("Select payment method for {}", format_money(total)))
+            .choices(methods.iter().map(|method| method.to_string()))
+            .build(),
+    )?;
+    let mut method = methods[method.as_list_item().unwrap().index];
+    let mut charges = Vec::new();
+    let mut remaining = total;
+
+    if method == PaymentMethod::Voucher {
+        let code = requestty::prompt_one(
+            Question::input("voucher_code")
+                .message("Enter the voucher code")
+                .build(),
+        )?;
+        let code = code.as_string().unwrap().trim().to_uppercase();
+        let taken = ledger.pay_with_voucher(&booking.id, &code, total, now)?;
+        charges.push(format!("{} from voucher {}", format_money(taken), code));
+        remaining -= taken;
+        if remaining == 0 {
+            return Ok(charges);
+        }
+
+        let methods: Vec<PaymentMethod> = payment_methods(booking)
+            .into_iter()
+            .filter(|method| *method != PaymentMethod::Voucher)
+            .collect();
+        let rest = requestty::prompt_one(
+            Question::select("payment_method")
+                .message(format!(
+                    "Select payment method for the remaining {}",
+                    format_money(remaining)
+                ))
+                .choices(methods.iter().map(|method| method.to_string()))
+                .build(),
+        );
+        let rest = match rest {
+            Ok(rest) => rest,
+            Err(e) => {
+                ledger.refund(&booking.id, taken, now)?;
+                return Err(e.into());
+            }
+        };
+        method = methods[rest.as_list_item().unwrap().index];
+        if let Err(e) = take_payment(accounts, ledger, loyalty, booking, remaining, method, now) {
+            ledger.refund(&booking.id, taken, now)?;
+            return Err(e);
+        }
+    } else {
+        take_payment(accounts, ledger, loyalty, booking, remaining, method, now)?;
+    }
+    charges.push(format!("charged {} by {}", format_money(remaining), method));
+    Ok(charges)
+}
+
+/// Charges a booking, taking the points from the passenger's loyalty account first when paying
+/// with points, or billing the corporate account the trip was booked under.
+fn take_payment(
+    accounts: &AccountList,
+    ledger: &mut Ledger,
+    loyalty: &mut LoyaltyProgramme,
+    booking: &Booking,
+    amount: u32,
+    method: PaymentMethod,
+    now: NaiveDateTime,
+) -> Result<()> {
+    match method {
+        PaymentMethod::Points => {
+            loyalty.redeem(&booking.passenger_id, &booking.id, amount, now)?;
+        }
+        PaymentMethod::Account => {
+            let account = booking
+                .account
+                .as_ref()
+                .and_then(|account_id| accounts.get(account_id))
+                .ok_or_else(|| anyhow::anyhow!("Booking {} is not on a corporate account", booking.id))?;
+            return ledger.bill_account(&booking.id, account, amount, now);
+        }
+        _ => {}
+    }
+    ledger.charge(&booking.id, amount, method, now)
+}
+
+/// Refunds a booking, giving points back for the part that was paid with points.
+fn give_refund(
+    ledger: &mut Ledger,
+    loyalty: &mut LoyaltyProgramme,
+    booking: &Booking,
+    amount: u32,
+    now: NaiveDateTime,
+) -> Result<()> {
+    for (method, part) in ledger.refund(&booking.id, amount, now)? {
+        if method == PaymentMethod::Points {
+            loyalty.refund(&booking.passenger_id, &booking.id, part, now)?;
+        }
+    }
+    Ok(())
+}
+
+fn select_booking(
+    bookings: &BookingList,
+    message: &str,
+) -> Result<String> {
+    let booking_choices: Vec<String> = bookings
+        .values()
+        .map(|booking| format!("{}, {}, {}", booking.id, booking.train_line, booking.travel_date))
+        .collect();
+    let question = Question::select("booking_list")
+        .message(message)
+        .choices(booking_choices)
+        .build();
+    let selection = requestty::prompt_one(question)?;
+    let selected_booking = selection.as_list_item().unwrap().text.clone();
+    Ok(selected_booking.split(',').next().unwrap().trim().to_string())
+}
+
+/// Shows the refund the fee policy allows for a cancellation and, once the agent confirms,
+/// refunds it and removes the booking.
+fn cancel_trip(
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    trains: &mut TrainList,
+    ledger: &mut Ledger,
+    loyalty: &mut LoyaltyProgramme,
+    policy: &FeePolicy,
+    history: &mut BookingHistory,
+) -> Result<()> {
+    let booking_id = select_booking(bookings, "Select booking to delete")?;
+    let booking = &bookings[&booking_id];
+    if booking.status != BookingStatus::Booked {
+        return Err(anyhow::anyhow!("Booking {} is {}", booking_id, booking.status));
+    }
+    let now = Local::now().naive_local();
+    let paid = ledger.balance(&booking_id).max(0) as u32;
+    let outcome = policy.cancellation(
+        booking.fare_bucket.as_deref(),
+        paid,
+        booking.departure(&trains[&booking.train_line]),
+        now,
+    )?;
+
+    println!("Cancelling booking {}: {}", booking_id, outcome);
+    let confirmed = requestty::prompt_one(Question::confirm("confirm").message("Cancel this booking?").build())?;
+    if !confirmed.as_bool().unwrap() {
+        return Ok(());
+    }
+
+    let mut description = format!("Cancelled, {}", outcome);
+    if outcome.refund > 0 {
+        let as_credit = requestty::prompt_one(
+            Question::select("refund_to")
+                .message(format!("Refund {}", format_money(outcome.refund)))
+                .choice("To the original payment")
+                .choice(format!("As travel credit, valid for {} months", CREDIT_VALID_MONTHS))
+                .build(),
+        )?
+        .as_list_item()
+        .unwrap()
+        .index
+            == 1;
+        if as_credit {
+            let booking = &bookings[&booking_id];
+            let code = ledger.refund_as_credit(&booking_id, &booking.passenger_id, outcome.refund, now)?;
+            println!("Issued travel credit {} for {}", code, format_money(outcome.refund));
+            description.push_str(&format!(", refunded as travel credit {}", code));
+        } else {
+            give_refund(ledger, loyalty, &bookings[&booking_id], outcome.refund, now)?;
+        }
+    }
+    record_event(history, &bookings[&booking_id], now, EventKind::Cancelled, description);
+    remove_booking(bookings, passengers, trains, booking_id)?;
+
+    Ok(())
+}
+
+/// Moves a booking to another train or date, charging the change fee from the fee policy plus
+/// any increase in fare. Cheaper fares are not refunded.
+#[allow(clippy::too_many_arguments)]
+fn change_trip(
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    trains: &mut TrainList,
+    rules: &RulePipeline,
+    accounts: &AccountList,
+    ledger: &mut Ledger,
+    loyalty: &mut LoyaltyProgramme,
+    policy: &FeePolicy,
+    history: &mut BookingHistory,
+) -> Result<()> {
+    let booking_id = select_booking(bookings, "Select booking to edit")?;
+    let booking = &bookings[&booking_id];
+    if booking.status != BookingStatus::Booked {
+        return Err(anyhow::anyhow!("Booking {} is {}", booking_id, booking.status));
+    }
+    if let Some(held_until) = booking.held_until {
+        return pay_held_booking(bookings, accounts, ledger, loyalty, history, &booking_id, held_until);
+    }
+
+    let questions: Vec<Question> = vec![
+        Question::select("train")
+            .message("Select new train")
+            .choices(
+                trains
+                    .values()
+                    .map(|train| requestty::Choice(format!("{}, {}", train.line, train.name)))
+                    .collect::<Vec<_>>(),
+            )
+            .build(),
+        Question::input("travel_date")
+            .message("Enter the new travel date (YYYY-MM-DD)")
+            .default(booking.travel_date.to_string())
+            .build(),
+    ];
+
+    let answers = requestty::prompt(questions)?;
+
+    let travel_date = parse_date(answers.get("travel_date").unwrap().as_string().unwrap())?;
+    let train_line = parse_number_answer(answers, "train", ",")?;
+    let now = Local::now().naive_local();
+    let outcome = policy.change(
+        booking.fare_bucket.as_deref(),
+        booking.departure(&trains[&booking.train_line]),
+        now,
+    )?;
+    let new_fare = match booking.pass {
+        Some(_) => 0,
+        None => quote_fare(bookings, &trains[&train_line], travel_date, Some(&booking_id))?.fare,
+    };
+    let new_total = new_fare.saturating_sub(booking.discount) + booking.ancillary_total();
+    let fare_difference = new_total.saturating_sub(booking.total());
+    let amount_due = outcome.fee + fare_difference;
+
+    println!(
+        "Changing booking {} to train {} on {}: {}, fare difference {}",
+        booking_id,
+        train_line,
+        travel_date,
+        outcome,
+        format_money(fare_difference)
+    );
+    let confirmed = requestty::prompt_one(
+        Question::confirm("confirm")
+            .message(format!("Charge {} and change this booking?", format_money(amount_due)))
+            .build(),
+    )?;
+    if !confirmed.as_bool().unwrap() {
+        return Ok(());
+    }
+
+    if amount_due > 0 {
+        let method = ledger
+            .charge_method(&booking_id)
+            .filter(|method| *method != PaymentMethod::Voucher)
+            .unwrap_or(PaymentMethod::Card);
+        take_payment(
+            accounts,
+            ledger,
+            loyalty,
+            &bookings[&booking_id],
+            amount_due,
+            method,
+            now,
+        )?;
+    }
+    if let Err(e) = edit_booking(
+        bookings,
+        passengers,
+        trains,
+        rules,
+        booking_id.clone(),
+        train_line,
+        travel_date,
+    ) {
+        if amount_due > 0 {
+            give_refund(ledger, loyalty, &bookings[&booking_id], amount_due, now)?;
+        }
+        return Err(e);
+    }
+    record_event(
+        history,
+        &bookings[&booking_id],
+        now,
+        EventKind::Changed,
+        format!(
+            "Changed to train {} on {}, {}, charged {}",
+            train_line,
+            travel_date,
+            outcome,
+            format_money(amount_due)
+        ),
+    );
+    for warning in &bookings[&booking_id].warnings {
+        println!("Warning: {}", warning);
+    }
+
+    Ok(())
+}
+
+// A held booking has to be paid before it can be changed
+fn pay_held_booking(
+    bookings: &mut BookingList,
+    accounts: &AccountList,
+    ledger: &mut Ledger,
+    loyalty: &mut LoyaltyProgramme,
+    history: &mut BookingHistory,
+    booking_id: &str,
+    held_until: NaiveDateTime,
+) -> Result<()> {
+    let now = Local::now().naive_local();
+    if now >= held_until {
+        return Err(anyhow::anyhow!(
+            "The hold on booking {} expired at {}",
+            booking_id,
+            held_until.format("%H:%M")
+        ));
+    }
+    println!(
+        "Booking {} is held unpaid until {}",
+        booking_id,
+        held_until.format("%H:%M")
+    );
+    let booking = &bookings[booking_id];
+    let charges = collect_payment(accounts, ledger, loyalty, booking, booking.total(), now)?;
+    bookings.get_mut(booking_id).unwrap().held_until = None;
+    record_event(
+        history,
+        &bookings[booking_id],
+        now,
+        EventKind::Changed,
+        format!("Paid for held seat, {}", charges.join(", ")),
+    );
+    Ok(())
+}
+
 fn list_bookings() -> Result<BookingListType> {
     let questions = vec![Question::select("list_type")
         .message("Select list type")
         .choice("All bookings")
         .choice("Bookings for passenger")
         .choice("Passengers on train")
+        .choice("Payment ledger")
+        .choice("Booking history")
+        .choice("Print invoice")
+        .choice("Print ticket")
+        .choice("Assistance report")
         .build()];
 
     let answers = requestty::prompt(questions)?;
@@ -149,6 +812,11 @@ fn list_bookings() -> Result<BookingListType> {
         0 => Ok(BookingListType::All),
         1 => Ok(BookingListType::Passenger),
         2 => Ok(BookingListType::Train),
+        3 => Ok(BookingListType::Ledger),
+        4 => Ok(BookingListType::History),
+        5 => Ok(BookingListType::Invoice),
+        6 => Ok(BookingListType::Ticket),
+        7 => Ok(BookingListType::Assistance),
         _ => unreachable!(),
     }
 }
@@ -157,6 +825,8 @@ fn manage_list_bookings(
     bookings: &BookingList,
     passengers: &PassengerList,
     trains: &TrainList,
+    ledger: &mut Ledger,
+    history: &BookingHistory,
     list_type: BookingListType,
 ) -> Result<()> {
     match list_type {
@@ -171,13 +841,70 @@ fn manage_list_bookings(
             list_bookings_for_passenger(passengers, passenger_id)?;
         }
         BookingListType::Train => {
-            let train_line = requestty::prompt_one(Question::input("train_line").message("Enter train line"))?
+            let answers = requestty::prompt(vec![
+                Question::input("train_line").message("Enter train line").build(),
+                Question::input("travel_date")
+                    .message("Enter the travel date (YYYY-MM-DD)")
+                    .default(Local::now().date_naive().to_string())
+                    .build(),
+                Question::select("sort")
+                    .message("Sort manifest by")
+                    .choice("Seat")
+                    .choice("Name")
+                    .build(),
+            ])?;
+            let train_line = answers["train_line"]
                 .as_string()
                 .unwrap()
+                .trim()
                 .parse::<u32>()
                 .context("Invalid train line")?;
+            let travel_date = parse_date(answers["travel_date"].as_string().unwrap())?;
+            let sort = match answers["sort"].as_list_item().unwrap().index {
+                0 => ManifestSort::Seat,
+                _ => ManifestSort::Name,
+            };
 
-            list_passengers_on_train(trains, train_line)?;
+            list_passengers_on_train(bookings, passengers, trains, train_line, travel_date, sort)?;
+            if let Some(train) = trains.get(&train_line) {
+                export_manifest(bookings, passengers, train, travel_date, sort)?;
+            }
+        }
+        BookingListType::Ledger => {
+            list_ledger(ledger);
+        }
+        BookingListType::History => {
+            let booking_id = requestty::prompt_one(Question::input("booking_id").message("Enter booking ID"))?;
+            list_booking_history(history, booking_id.as_string().unwrap().trim());
+        }
+        BookingListType::Invoice => {
+            if bookings.is_empty() {
+                println!("No bookings found");
+            } else {
+                let booking_id = select_booking(bookings, "Select booking to invoice")?;
+                print_invoice(bookings, passengers, trains, ledger, &booking_id)?;
+            }
+        }
+        BookingListType::Ticket => {
+            if bookings.is_empty() {
+                println!("No bookings found");
+            } else {
+                let booking_id = select_booking(bookings, "Select booking to print a ticket for")?;
+                print_ticket(bookings, passengers, trains, &booking_id)?;
+            }
+        }
+        BookingListType::Assistance => {
+            let travel_date = requestty::prompt_one(
+                Question::input("travel_date")
+                    .message("Enter the travel date (YYYY-MM-DD)")
+                    .default(Local::now().date_naive().to_string()),
+            )?;
+            print_assistance_report(
+                bookings,
+                passengers,
+                trains,
+                parse_date(travel_date.as_string().unwrap())?,
+            );
         }
     }
 
@@ -190,42 +917,84 @@ pub fn list_all_bookings(bookings: &BookingList) -> Result<()> {
     } else {
         for (id, booking) in bookings {
             println!(
-                "ID: {}, Passenger ID: {}, Train Line: {}",
-                id, booking.passenger_id, booking.train_line
+                "ID: {}, Passenger ID: {}, Train Line: {}, Date: {}, Seat: {}, Status: {}, Fare: {}{}{}{}{}{}{}",
+                id,
+                booking.passenger_id,
+                booking.train_line,
+                booking.travel_date,
+                booking.seat,
+                booking.status,
+                format_money(booking.total()),
+                booking
+                    .fare_bucket
+                    .as_ref()
+                    .map(|code| format!(" (bucket {})", code))
+                    .unwrap_or_default(),
+                booking
+                    .load_band
+                    .map(|applied| format!(" (load {}%, band {})", applied.load_factor, applied.band))
+                    .unwrap_or_default(),
+                booking
+                    .promo_code
+                    .as_ref()
+                    .map(|code| format!(" (promo {})", code))
+                    .unwrap_or_default(),
+                booking
+                    .pass
+                    .as_ref()
+                    .map(|pass_id| format!(" (pass {})", pass_id))
+                    .unwrap_or_default(),
+                booking
+                    .account
+                    .as_ref()
+                    .map(|account_id| format!(" (corporate account {})", account_id))
+                    .unwrap_or_default(),
+                if booking.ancillaries.is_empty() {
+                    String::new()
+                } else {
+                    format!(", Extras: {}", format_ancillaries(&booking.ancillaries))
+                }
             );
+            for warning in &booking.warnings {
+                println!("    Warning: {}", warning);
+            }
         }
     }
     Ok(())
 }
 
-fn check_for_overlap(
-    passenger: &Passenger,
-    train: &Train,
-    train_line: u32,
-    trains: &TrainList,
+/// Finds the lowest seat number not yet taken on the train for the travel date.
+fn allocate_seat(
     bookings: &BookingList,
-) -> Result<()> {
-    for booking_id in &passenger.bookings {
-        let booking = bookings.get(booking_id).unwrap();
-        let booked_train = trains.get(&booking.train_line).unwrap();
-        if booking.train_line == train_line
-            || (train.departure < booked_train.arrival && train.arrival > booked_train.departure)
-        {
-            return Err(anyhow::anyhow!(
-                "Passenger already has a booking for this train or overlapping travel times"
-            ));
-        }
-    }
-    Ok(())
+    train: &Train,
+    travel_date: NaiveDate,
+    excluding: Option<&str>,
+) -> Result<u32> {
+    let taken: HashSet<u32> = bookings
+        .values()
+        .filter(|booking| booking.train_line == train.line && booking.travel_date == travel_date)
+        .filter(|booking| Some(booking.id.as_str()) != excluding)
+        .map(|booking| booking.seat)
+        .collect();
+
+    (1..=train.capacity)
+        .find(|seat| !taken.contains(seat))
+        .ok_or_else(|| anyhow::anyhow!("No seats left on train {} on {}", train.line, travel_date))
 }
 
+/// Books a passenger on a train after the rule pipeline has passed. Warnings from the pipeline are
+/// kept on the booking.
+#[allow(clippy::too_many_arguments)]
 pub fn add_booking(
     bookings: &mut BookingList,
     passengers: &mut PassengerList,
     trains: &mut TrainList,
+    rules: &RulePipeline,
     passenger_id: String,
     train_line: u32,
-) -> Result<()> {
+    travel_date: NaiveDate,
+    supervision: Supervision,
+) -> Result<String> {
     let passenger = passengers
         .get(&passenger_id)
         .ok_or_else(|| anyhow::anyhow!("Passenger not found"))?;
@@ -234,21 +1003,74 @@ pub fn add_booking(
         .get(&train_line)
         .ok_or_else(|| anyhow::anyhow!("Train not found"))?;
 
-    check_for_overlap(passenger, train, train_line, trains, bookings)?;
+    if is_cancelled(train, travel_date) {
+        return Err(anyhow::anyhow!("Train {} is cancelled on {}", train_line, travel_date));
+    }
+    let warnings = rules.run(&BookingRequest {
+        bookings,
+        passengers,
+        trains,
+        passenger,
+        train,
+        travel_date,
+        supervision: &supervision,
+        excluding: None,
+    })?;
 
-    let booking_id = format!("{}_{}", passenger_id, train_line);
-    let booking = Booking::new(booking_id.clone(), passenger_id.clone(), train_line);
+    let seat = allocate_seat(bookings, train, travel_date, None)?;
+    check_accessible_space(bookings, train, travel_date, &passenger.accessibility, None)?;
+    let assistance = passenger.accessibility.clone();
+    let quote = quote_fare(bookings, train, travel_date, None)?;
+    let booking_id = passenger.next_booking_id(format!(
+        "{}_{}_{}",
+        passenger_id,
+        train_line,
+        travel_date.format("%Y%m%d")
+    ));
+    let mut booking = Booking::new(
+        booking_id.clone(),
+        passenger_id.clone(),
+        train_line,
+        travel_date,
+        seat,
+        quote,
+    );
+    booking.supervision = supervision;
+    booking.warnings = warnings;
+    booking.assistance = assistance;
 
-    bookings.insert(booking_id.clone(), booking);
     passengers
         .get_mut(&passenger_id)
         .unwrap()
         .add_booking(booking_id.clone());
-    trains
-        .get_mut(&train_line)
-        .unwrap()
-        .passengers
-        .insert(passenger_id.to_string());
+    let train = trains.get_mut(&train_line).unwrap();
+    train.passengers.insert(passenger_id.to_string());
+    if let Some(code) = &booking.fare_bucket {
+        reserve_bucket(train, code, travel_date);
+    }
+    bookings.insert(booking_id.clone(), booking);
+
+    Ok(booking_id)
+}
+
+/// Redeems a promo code against an existing booking, replacing any discount already applied.
+pub fn apply_promotion(
+    bookings: &mut BookingList,
+    promotions: &mut PromotionList,
+    trains: &TrainList,
+    booking_id: &str,
+    code: &str,
+    today: NaiveDate,
+) -> Result<()> {
+    let booking = bookings
+        .get_mut(booking_id)
+        .ok_or_else(|| anyhow::anyhow!("Booking not found"))?;
+    let train = trains
+        .get(&booking.train_line)
+        .ok_or_else(|| anyhow::anyhow!("Train not found"))?;
+
+    booking.discount = redeem_promotion(promotions, code, train, booking.fare, today)?;
+    booking.promo_code = Some(code.to_string());
 
     Ok(())
 }
@@ -257,18 +1079,74 @@ pub fn edit_booking(
     bookings: &mut BookingList,
     passengers: &mut PassengerList,
     trains: &mut TrainList,
+    rules: &RulePipeline,
     booking_id: String,
     train_line: u32,
+    travel_date: NaiveDate,
 ) -> Result<()> {
     let booking = bookings.get(&booking_id).unwrap();
     let passenger = passengers.get(&booking.passenger_id).unwrap();
     let train = trains.get(&train_line).unwrap();
 
-    // Check for overlapping travel times
-    check_for_overlap(passenger, train, train_line, trains, bookings)?;
+    if is_cancelled(train, travel_date) {
+        return Err(anyhow::anyhow!("Train {} is cancelled on {}", train_line, travel_date));
+    }
+
+    let warnings = rules.run(&BookingRequest {
+        bookings,
+        passengers,
+        trains,
+        passenger,
+        train,
+        travel_date,
+        supervision: &booking.supervision,
+        excluding: Some(&booking_id),
+    })?;
+    let seat = allocate_seat(bookings, train, travel_date, Some(&booking_id))?;
+    check_accessible_space(bookings, train, travel_date, &booking.assistance, Some(&booking_id))?;
+    // Extras must be available on the new train but keep the price already paid
+    let extras: Vec<_> = booking.ancillaries.iter().map(|ancillary| ancillary.kind).collect();
+    reserve_ancillaries(bookings, train, travel_date, &extras, Some(&booking_id))?;
+    // A trip on a pass stays on the pass, which must cover the new train and date
+    let quote = match &booking.pass {
+        Some(pass_id) => {
+            passenger
+                .passes
+                .iter()
+                .find(|pass| &pass.id == pass_id)
+                .ok_or_else(|| anyhow::anyhow!("Pass {} not found", pass_id))?
+                .check(train, travel_date)?;
+            FareQuote {
+                fare: 0,
+                fare_bucket: None,
+                load_band: None,
+            }
+        }
+        None => quote_fare(bookings, train, travel_date, Some(&booking_id))?,
+    };
+
+    // Give up the seat on the old train
+    let booking = &bookings[&booking_id];
+    let old_train = trains.get_mut(&booking.train_line).unwrap();
+    release_passenger(bookings, old_train, &booking.passenger_id, &booking_id);
+    let booking = bookings.get_mut(&booking_id).unwrap();
+    if let Some(code) = &booking.fare_bucket {
+        release_bucket(old_train, code, booking.travel_date);
+    }
 
     // Update booking
-    bookings.get_mut(&booking_id).unwrap().train_line = train_line;
+    let train = trains.get_mut(&train_line).unwrap();
+    train.passengers.insert(booking.passenger_id.clone());
+    if let Some(code) = &quote.fare_bucket {
+        reserve_bucket(train, code, travel_date);
+    }
+    booking.train_line = train_line;
+    booking.travel_date = travel_date;
+    booking.seat = seat;
+    booking.fare = quote.fare;
+    booking.fare_bucket = quote.fare_bucket;
+    booking.load_band = quote.load_band;
+    booking.warnings = warnings;
 
     Ok(())
 }
@@ -282,30 +1160,49 @@ pub fn remove_booking(
     let booking_id = selected_booking.split(',').next().unwrap().trim().to_string();
 
     let booking = bookings.remove(&booking_id).unwrap();
+    release_pass(passengers, &booking);
     passengers
         .get_mut(&booking.passenger_id)
         .unwrap()
         .remove_booking(&booking_id);
-    trains
-        .get_mut(&booking.train_line)
-        .unwrap()
-        .passengers
-        .remove(&booking.passenger_id);
+    let train = trains.get_mut(&booking.train_line).unwrap();
+    release_passenger(bookings, train, &booking.passenger_id, &booking_id);
+    if let Some(code) = &booking.fare_bucket {
+        release_bucket(train, code, booking.travel_date);
+    }
 
     Ok(())
 }
 
+/// Takes the passenger off the train unless another of their bookings is still on it.
+pub fn release_passenger(
+    bookings: &BookingList,
+    train: &mut Train,
+    passenger_id: &str,
+    booking_id: &str,
+) {
+    let still_booked = bookings.values().any(|booking| {
+        booking.id != booking_id && booking.passenger_id == passenger_id && booking.train_line == train.line
+    });
+    if !still_booked {
+        train.passengers.remove(passenger_id);
+    }
+}
+
 pub fn list_passengers_on_train(
+    bookings: &BookingList,
+    passengers: &PassengerList,
     trains: &TrainList,
     train_line: u32,
+    travel_date: NaiveDate,
+    sort: ManifestSort,
 ) -> Result<()> {
     if let Some(train) = trains.get(&train_line) {
-        if train.passengers.is_empty() {
+        let manifest = Manifest::new(bookings, passengers, train, travel_date, sort);
+        if manifest.entries.is_empty() {
             println!("No passengers found on this train");
         } else {
-            for passenger_id in &train.passengers {
-                println!("Passenger ID: {}", passenger_id);
-            }
+            println!("{}", manifest.to_text());
         }
     } else {
         println!("Train not found");
@@ -314,6 +1211,141 @@ pub fn list_passengers_on_train(
     Ok(())
 }
 
+/// Writes the train's manifest for the date to a CSV or PDF file in the working directory.
+fn export_manifest(
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    train: &Train,
+    travel_date: NaiveDate,
+    sort: ManifestSort,
+) -> Result<()> {
+    let export = requestty::prompt_one(
+        Question::select("export")
+            .message("Export manifest")
+            .choice("No")
+            .choice("CSV")
+            .choice("PDF")
+            .build(),
+    )?;
+
+    let manifest = Manifest::new(bookings, passengers, train, travel_date, sort);
+    let path = format!("manifest-{}-{}", train.line, travel_date);
+    match export.as_list_item().unwrap().index {
+        0 => {}
+        1 => {
+            let path = format!("{}.csv", path);
+            manifest.write_csv(Path::new(&path))?;
+            println!("Manifest written to {}", path);
+        }
+        2 => {
+            let path = format!("{}.pdf", path);
+            manifest.write_pdf(Path::new(&path))?;
+            println!("Manifest written to {}", path);
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Prints the booking's invoice as text or writes it to an HTML file in the working directory.
+fn print_invoice(
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    trains: &TrainList,
+    ledger: &mut Ledger,
+    booking_id: &str,
+) -> Result<()> {
+    let format = requestty::prompt_one(
+        Question::select("format")
+            .message("Select invoice format")
+            .choice("Plain text")
+            .choice("HTML")
+            .build(),
+    )?;
+
+    let booking = &bookings[booking_id];
+    let number = ledger.invoice_number(booking_id);
+    let invoice = Invoice::new(
+        number,
+        Local::now().date_naive(),
+        booking,
+        &passengers[&booking.passenger_id],
+        &trains[&booking.train_line],
+        ledger,
+    );
+
+    match format.as_list_item().unwrap().index {
+        0 => println!("{}", invoice.to_text()),
+        1 => {
+            let path = format!("invoice-{:06}.html", number);
+            fs::write(&path, invoice.to_html()).with_context(|| format!("Could not write {}", path))?;
+            println!("Invoice written to {}", path);
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Prints the booking's ticket with its QR code and offers to export the code as PNG or SVG.
+fn print_ticket(
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    trains: &TrainList,
+    booking_id: &str,
+) -> Result<()> {
+    let booking = &bookings[booking_id];
+    let key = load_or_create_signing_key(Path::new(SIGNING_KEY_PATH), Path::new(VERIFYING_KEY_PATH))?;
+    let ticket = Ticket::new(
+        booking,
+        &passengers[&booking.passenger_id],
+        &trains[&booking.train_line],
+        &key,
+    );
+    println!("{}\n{}", ticket.to_text(), ticket.qr_terminal()?);
+
+    let export = requestty::prompt_one(
+        Question::select("export")
+            .message("Export the QR code?")
+            .choice("No")
+            .choice("PNG")
+            .choice("SVG")
+            .build(),
+    )?;
+    let path = match export.as_list_item().unwrap().index {
+        0 => return Ok(()),
+        1 => {
+            let path = format!("ticket-{}.png", booking_id);
+            ticket.write_qr_png(Path::new(&path))?;
+            path
+        }
+        2 => {
+            let path = format!("ticket-{}.svg", booking_id);
+            fs::write(&path, ticket.qr_svg()?).with_context(|| format!("Could not write {}", path))?;
+            path
+        }
+        _ => unreachable!(),
+    };
+    println!("QR code written to {}", path);
+
+    Ok(())
+}
+
+pub fn list_booking_history(
+    history: &BookingHistory,
+    booking_id: &str,
+) {
+    let mut found = false;
+    for event in history.iter().filter(|event| event.booking_id == booking_id) {
+        println!("{} {}", event.timestamp.format("%Y-%m-%d %H:%M"), event.description);
+        found = true;
+    }
+    if !found {
+        println!("No history found for this booking");
+    }
+}
+
 pub fn list_bookings_for_passenger(
     passengers: &PassengerList,
     passenger_id: &str,
@@ -336,7 +1368,13 @@ pub fn list_bookings_for_passenger(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::passengers::Passenger;
+    use crate::assistance::DEFAULT_ACCESSIBLE_SPACES;
+    use crate::fares::{parse_fare_buckets, PricingMode, DEFAULT_LOAD_BANDS};
+    use crate::loyalty::{PointsEntry, PointsKind};
+    use crate::minors::default_minor_rules;
+    use crate::passengers::{approximate_date_of_birth, Passenger};
+    use crate::payments::MockProcessor;
+    use crate::test_support::travel_date;
     use crate::trains::Train;
     use chrono::NaiveTime;
     use std::collections::HashSet;
@@ -351,8 +1389,17 @@ mod tests {
             Passenger {
                 id: "P1".to_string(),
                 name: "John Doe".to_string(),
-                age: 30,
+                date_of_birth: NaiveDate::from_ymd_opt(1994, 1, 15).unwrap(),
+                date_of_birth_approximate: false,
+                email: String::new(),
+                phone: String::new(),
+                postal_address: String::new(),
+                emergency_contact: None,
+                special_requirements: String::new(),
+                accessibility: Vec::new(),
+                passes: Vec::new(),
                 bookings: HashSet::new(),
+                past_bookings: HashSet::new(),
             },
         );
 
@@ -362,11 +1409,19 @@ mod tests {
                 line: 1,
                 name: "Express".to_string(),
                 capacity: 100,
+                distance_km: 0,
+                accessible_spaces: DEFAULT_ACCESSIBLE_SPACES,
                 origin: "Toronto".to_string(),
                 destination: "Hamilton".to_string(),
                 departure: NaiveTime::parse_from_str("11:00", "%H:%M").unwrap(),
                 arrival: NaiveTime::parse_from_str("12:00", "%H:%M").unwrap(),
+                fare: 2500,
+                pricing: PricingMode::Fixed,
+                fare_buckets: Vec::new(),
                 passengers: HashSet::new(),
+                minor_rules: default_minor_rules(),
+                ancillaries: Vec::new(),
+                disruptions: HashMap::new(),
             },
         );
 
@@ -376,28 +1431,166 @@ mod tests {
     #[test]
     fn test_add_booking() {
         let (mut bookings, mut passengers, mut trains) = setup();
-        let result = add_booking(&mut bookings, &mut passengers, &mut trains, "P1".to_string(), 1);
+        let result = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        );
         assert!(result.is_ok());
         assert_eq!(bookings.len(), 1);
         assert_eq!(passengers.get("P1").unwrap().bookings.len(), 1);
         assert_eq!(trains.get(&1).unwrap().passengers.len(), 1);
     }
 
+    #[test]
+    fn test_same_train_on_two_dates() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let mut book = |date: NaiveDate| {
+            add_booking(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &RulePipeline::default(),
+                "P1".to_string(),
+                1,
+                date,
+                Supervision::Alone,
+            )
+        };
+        let next_day = travel_date().succ_opt().unwrap();
+
+        assert_eq!(book(travel_date()).unwrap(), "P1_1_20240610");
+        assert_eq!(book(next_day).unwrap(), "P1_1_20240611");
+        assert!(book(next_day).is_err());
+        assert_eq!(bookings.len(), 2);
+
+        // The passenger stays on the train until their last booking on it goes
+        remove_booking(&mut bookings, &mut passengers, &mut trains, "P1_1_20240610".to_string()).unwrap();
+        assert!(trains[&1].passengers.contains("P1"));
+        remove_booking(&mut bookings, &mut passengers, &mut trains, "P1_1_20240611".to_string()).unwrap();
+        assert!(trains[&1].passengers.is_empty());
+    }
+
     #[test]
     fn test_remove_booking() {
         let (mut bookings, mut passengers, mut trains) = setup();
-        add_booking(&mut bookings, &mut passengers, &mut trains, "P1".to_string(), 1).unwrap();
-        let result = remove_booking(&mut bookings, &mut passengers, &mut trains, "P1_1".to_string());
+        add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
+        let result = remove_booking(&mut bookings, &mut passengers, &mut trains, "P1_1_20240610".to_string());
         assert!(result.is_ok());
         assert!(bookings.is_empty());
         assert!(passengers.get("P1").unwrap().bookings.is_empty());
         assert!(trains.get(&1).unwrap().passengers.is_empty());
     }
 
+    #[test]
+    fn test_rebooking_after_removal_gets_a_new_id() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let book = |bookings: &mut BookingList, passengers: &mut PassengerList, trains: &mut TrainList| {
+            add_booking(
+                bookings,
+                passengers,
+                trains,
+                &RulePipeline::default(),
+                "P1".to_string(),
+                1,
+                travel_date(),
+                Supervision::Alone,
+            )
+            .unwrap()
+        };
+        let first = book(&mut bookings, &mut passengers, &mut trains);
+        remove_booking(&mut bookings, &mut passengers, &mut trains, first.clone()).unwrap();
+
+        // Ledger entries and history for the removed booking must not carry over
+        let second = book(&mut bookings, &mut passengers, &mut trains);
+        assert_ne!(first, second);
+        assert!(passengers["P1"].past_bookings.contains(&first));
+    }
+
+    #[test]
+    fn test_refund_gives_back_only_the_points_paid() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let booking_id = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
+        let now = travel_date().and_hms_opt(9, 0, 0).unwrap();
+        let mut ledger = Ledger::new(Box::new(MockProcessor::new()));
+        let mut loyalty = LoyaltyProgramme::default();
+        loyalty.enrol("P1").unwrap();
+        loyalty.accounts.get_mut("P1").unwrap().entries.push(PointsEntry {
+            timestamp: now,
+            booking_id: "P1_1_20240601".to_string(),
+            kind: PointsKind::Earned,
+            points: 3000,
+            description: "Earlier trip".to_string(),
+        });
+
+        // Part paid by card, the rest with points
+        let booking = &bookings[&booking_id];
+        take_payment(
+            &AccountList::new(),
+            &mut ledger,
+            &mut loyalty,
+            booking,
+            1500,
+            PaymentMethod::Card,
+            now,
+        )
+        .unwrap();
+        take_payment(
+            &AccountList::new(),
+            &mut ledger,
+            &mut loyalty,
+            booking,
+            1000,
+            PaymentMethod::Points,
+            now,
+        )
+        .unwrap();
+        assert_eq!(loyalty.accounts["P1"].balance(), 2000);
+
+        give_refund(&mut ledger, &mut loyalty, booking, 2000, now).unwrap();
+        assert_eq!(loyalty.accounts["P1"].balance(), 3000);
+        assert_eq!(ledger.balance(&booking_id), 500);
+    }
+
     #[test]
     fn test_list_all_bookings() {
         let (mut bookings, mut passengers, mut trains) = setup();
-        add_booking(&mut bookings, &mut passengers, &mut trains, "P1".to_string(), 1).unwrap();
+        add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
         let result = list_all_bookings(&bookings);
         assert!(result.is_ok());
     }
@@ -405,7 +1598,17 @@ mod tests {
     #[test]
     fn test_list_bookings_for_passenger() {
         let (mut bookings, mut passengers, mut trains) = setup();
-        add_booking(&mut bookings, &mut passengers, &mut trains, "P1".to_string(), 1).unwrap();
+        add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
         let result = list_bookings_for_passenger(&passengers, "P1");
         assert!(result.is_ok());
     }
@@ -413,8 +1616,18 @@ mod tests {
     #[test]
     fn test_list_passengers_on_train() {
         let (mut bookings, mut passengers, mut trains) = setup();
-        add_booking(&mut bookings, &mut passengers, &mut trains, "P1".to_string(), 1).unwrap();
-        let result = list_passengers_on_train(&trains, 1);
+        add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
+        let result = list_passengers_on_train(&bookings, &passengers, &trains, 1, travel_date(), ManifestSort::Seat);
         assert!(result.is_ok());
     }
 
@@ -423,7 +1636,17 @@ mod tests {
         let (mut bookings, mut passengers, mut trains) = setup();
 
         // Setup initial booking
-        add_booking(&mut bookings, &mut passengers, &mut trains, "P1".to_string(), 1).unwrap();
+        add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
 
         // Add second train
         trains.insert(
@@ -432,18 +1655,240 @@ mod tests {
                 line: 2,
                 name: "Local".to_string(),
                 capacity: 100,
+                distance_km: 0,
+                accessible_spaces: DEFAULT_ACCESSIBLE_SPACES,
                 origin: "Toronto".to_string(),
                 destination: "Hamilton".to_string(),
                 departure: NaiveTime::parse_from_str("13:00", "%H:%M").unwrap(),
                 arrival: NaiveTime::parse_from_str("15:00", "%H:%M").unwrap(),
+                fare: 1800,
+                pricing: PricingMode::Fixed,
+                fare_buckets: Vec::new(),
                 passengers: HashSet::new(),
+                minor_rules: default_minor_rules(),
+                ancillaries: Vec::new(),
+                disruptions: HashMap::new(),
             },
         );
 
         // Test core edit booking function
-        let result = edit_booking(&mut bookings, &mut passengers, &mut trains, "P1_1".to_string(), 2);
+        let result = edit_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1_1_20240610".to_string(),
+            2,
+            travel_date(),
+        );
 
         assert!(result.is_ok());
-        assert_eq!(bookings.get("P1_1").unwrap().train_line, 2);
+        assert_eq!(bookings.get("P1_1_20240610").unwrap().train_line, 2);
+    }
+
+    #[test]
+    fn test_seats_are_allocated_per_train_and_date() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        passengers.insert(
+            "P2".to_string(),
+            Passenger::new(
+                "P2".to_string(),
+                "Jane Doe".to_string(),
+                approximate_date_of_birth(28, travel_date()),
+            ),
+        );
+        passengers.insert(
+            "P3".to_string(),
+            Passenger::new(
+                "P3".to_string(),
+                "Jim Doe".to_string(),
+                approximate_date_of_birth(5, travel_date()),
+            ),
+        );
+        trains.get_mut(&1).unwrap().capacity = 2;
+
+        let first = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
+        let second = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P2".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
+        assert_eq!(bookings[&first].seat, 1);
+        assert_eq!(bookings[&second].seat, 2);
+        assert!(add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P3".to_string(),
+            1,
+            travel_date(),
+            Supervision::Adult(second.clone())
+        )
+        .is_err());
+
+        remove_booking(&mut bookings, &mut passengers, &mut trains, first).unwrap();
+        let third = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P3".to_string(),
+            1,
+            travel_date(),
+            Supervision::Adult(second),
+        )
+        .unwrap();
+        assert_eq!(bookings[&third].seat, 1);
+    }
+
+    #[test]
+    fn test_add_booking_records_load_band() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let train = trains.get_mut(&1).unwrap();
+        train.capacity = 2;
+        train.pricing = PricingMode::LoadBased(DEFAULT_LOAD_BANDS.to_vec());
+        for (id, date) in [("P2", travel_date()), ("P3", travel_date().succ_opt().unwrap())] {
+            passengers.insert(
+                id.to_string(),
+                Passenger::new(id.to_string(), id.to_string(), approximate_date_of_birth(30, date)),
+            );
+            add_booking(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &RulePipeline::default(),
+                id.to_string(),
+                1,
+                date,
+                Supervision::Alone,
+            )
+            .unwrap();
+        }
+
+        let booking_id = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
+
+        let booking = &bookings[&booking_id];
+        assert_eq!(booking.fare, 3125);
+        assert_eq!(booking.load_band.unwrap().load_factor, 50);
+        assert_eq!(booking.load_band.unwrap().band, DEFAULT_LOAD_BANDS[1]);
+    }
+
+    #[test]
+    fn test_fare_buckets_close_and_reopen() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        passengers.insert(
+            "P2".to_string(),
+            Passenger::new(
+                "P2".to_string(),
+                "Jane Doe".to_string(),
+                approximate_date_of_birth(28, travel_date()),
+            ),
+        );
+        trains.get_mut(&1).unwrap().fare_buckets = parse_fare_buckets("Q:10.00:1, Y:30.00:100", &[]).unwrap();
+
+        let first = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
+        let second = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P2".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
+        assert_eq!(bookings[&first].fare_bucket.as_deref(), Some("Q"));
+        assert_eq!(bookings[&first].fare, 1000);
+        assert_eq!(bookings[&second].fare_bucket.as_deref(), Some("Y"));
+        assert_eq!(bookings[&second].fare, 3000);
+
+        remove_booking(&mut bookings, &mut passengers, &mut trains, first).unwrap();
+        assert_eq!(trains[&1].fare_buckets[0].sold_on(travel_date()), 0);
+        assert_eq!(trains[&1].fare_buckets[1].sold_on(travel_date()), 1);
+    }
+
+    #[test]
+    fn test_apply_promotion() {
+        use crate::promotions::{Discount, Promotion};
+
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let today = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let mut promotions = PromotionList::new();
+        promotions.insert(
+            "SAVE5".to_string(),
+            Promotion::new("SAVE5".to_string(), Discount::Fixed(500), today, today),
+        );
+
+        let booking_id = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
+        assert_eq!(bookings[&booking_id].total(), 2500);
+
+        apply_promotion(&mut bookings, &mut promotions, &trains, &booking_id, "SAVE5", today).unwrap();
+
+        assert_eq!(bookings[&booking_id].total(), 2000);
+        assert_eq!(bookings[&booking_id].promo_code.as_deref(), Some("SAVE5"));
+        assert_eq!(promotions["SAVE5"].redemptions, 1);
+
+        // A booking that cannot be completed gives its seat and promo code use back
+        let error = abandon_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &mut promotions,
+            booking_id,
+            anyhow::anyhow!("Prompt cancelled"),
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Prompt cancelled");
+        assert!(bookings.is_empty());
+        assert!(trains[&1].passengers.is_empty());
+        assert_eq!(promotions["SAVE5"].redemptions, 0);
     }
 }
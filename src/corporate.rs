@@ -0,0 +1,606 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, Local, Months, NaiveDate};
+use requestty::{self, Answers, Question};
+use std::collections::HashMap;
+use std::fs;
+
+use crate::actions::{add_remove_prompt, Action};
+use crate::bookings::{Booking, BookingList};
+use crate::helper::{clear_screen, continue_prompt, format_money, parse_date, parse_money};
+use crate::invoices::AccountInvoice;
+use crate::passengers::PassengerList;
+use crate::payments::Ledger;
+use crate::trains::TrainList;
+
+pub struct CorporateAccount {
+    pub id: String,
+    pub name: String,
+    pub discount_percent: u32,   // Negotiated discount off every fare booked under the account
+    pub billing_contact: String, // Where the monthly invoice is sent
+    pub credit_limit: u32,       // Most that can be billed to the account in a month, in cents, 0 for no limit
+    pub passenger_ids: Vec<String>, // Staff who travel on the account
+}
+
+impl CorporateAccount {
+    pub fn discount(
+        &self,
+        fare: u32,
+    ) -> u32 {
+        fare * self.discount_percent / 100
+    }
+}
+
+pub type AccountList = HashMap<String, CorporateAccount>;
+
+/// The corporate account a passenger travels on, if any.
+pub fn account_for<'a>(
+    accounts: &'a AccountList,
+    passenger_id: &str,
+) -> Option<&'a CorporateAccount> {
+    accounts
+        .values()
+        .find(|account| account.passenger_ids.iter().any(|id| id == passenger_id))
+}
+
+/// Books the trip under the account at its negotiated rate.
+pub fn apply_corporate_rate(
+    bookings: &mut BookingList,
+    account: &CorporateAccount,
+    booking_id: &str,
+) -> Result<()> {
+    let booking = bookings
+        .get_mut(booking_id)
+        .ok_or_else(|| anyhow!("Booking not found"))?;
+    booking.account = Some(account.id.clone());
+    booking.discount = account.discount(booking.fare);
+    Ok(())
+}
+
+/// First day of the month after the given one.
+pub fn next_month(month: NaiveDate) -> NaiveDate {
+    month.with_day(1).unwrap() + Months::new(1)
+}
+
+pub struct SpendLine {
+    pub passenger_id: String,
+    pub name: String,
+    pub trips: u32,
+    pub fares: u32,  // Fares before the corporate rate, in cents
+    pub saved: u32,  // Discount from the corporate rate, in cents
+    pub extras: u32, // Extras such as bike spaces and meals, in cents
+    pub paid: i64,   // Net amount in the ledger however it was paid, in cents
+}
+
+/// Spend per passenger on account trips travelling between the two dates.
+pub fn spend_report(
+    account: &CorporateAccount,
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    ledger: &Ledger,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Vec<SpendLine> {
+    let mut lines: Vec<SpendLine> = Vec::new();
+    let mut trips: Vec<&Booking> = bookings
+        .values()
+        .filter(|booking| booking.account.as_deref() == Some(account.id.as_str()))
+        .filter(|booking| booking.travel_date >= from && booking.travel_date <= to)
+        .collect();
+    trips.sort_by(|a, b| a.passenger_id.cmp(&b.passenger_id).then(a.id.cmp(&b.id)));
+
+    for booking in trips {
+        if lines.last().map(|line| &line.passenger_id) != Some(&booking.passenger_id) {
+            lines.push(SpendLine {
+                passenger_id: booking.passenger_id.clone(),
+                name: passengers
+                    .get(&booking.passenger_id)
+                    .map_or("Unknown passenger".to_string(), |passenger| passenger.name.clone()),
+                trips: 0,
+                fares: 0,
+                saved: 0,
+                extras: 0,
+                paid: 0,
+            });
+        }
+        let line = lines.last_mut().unwrap();
+        line.trips += 1;
+        line.fares += booking.fare;
+        line.saved += booking.discount;
+        line.extras += booking.ancillary_total();
+        line.paid += ledger.balance(&booking.id);
+    }
+    lines
+}
+
+fn print_spend_report(
+    account: &CorporateAccount,
+    lines: &[SpendLine],
+    from: NaiveDate,
+    to: NaiveDate,
+) {
+    println!("Spend for {} ({}) from {} to {}", account.name, account.id, from, to);
+    if lines.is_empty() {
+        println!("No trips booked on the account");
+        return;
+    }
+    println!(
+        "{:<24} {:>5} {:>10} {:>10} {:>10} {:>10}",
+        "Passenger", "Trips", "Fares", "Saved", "Extras", "Paid"
+    );
+    for line in lines {
+        println!(
+            "{:<24} {:>5} {:>10} {:>10} {:>10} {:>10}",
+            format!("{} ({})", line.name, line.passenger_id),
+            line.trips,
+            format_money(line.fares),
+            format_money(line.saved),
+            format_money(line.extras),
+            format_money(line.paid.max(0) as u32)
+        );
+    }
+    println!(
+        "{:<24} {:>5} {:>10} {:>10} {:>10} {:>10}",
+        "Total",
+        lines.iter().map(|line| line.trips).sum::<u32>(),
+        format_money(lines.iter().map(|line| line.fares).sum()),
+        format_money(lines.iter().map(|line| line.saved).sum()),
+        format_money(lines.iter().map(|line| line.extras).sum()),
+        format_money(lines.iter().map(|line| line.paid).sum::<i64>().max(0) as u32)
+    );
+}
+
+fn select_account(
+    accounts: &AccountList,
+    message: &str,
+) -> Result<String> {
+    let mut choices: Vec<String> = accounts
+        .values()
+        .map(|account| format!("{}, {}", account.id, account.name))
+        .collect();
+    choices.sort();
+    let selection = requestty::prompt_one(Question::select("account").message(message).choices(choices).build())?;
+    Ok(selection
+        .as_list_item()
+        .unwrap()
+        .text
+        .split(',')
+        .next()
+        .unwrap()
+        .trim()
+        .to_string())
+}
+
+fn account_questions(
+    current: Option<&CorporateAccount>,
+    passengers: &PassengerList,
+) -> Vec<Question<'static>> {
+    let mut members: Vec<(String, bool)> = passengers
+        .values()
+        .map(|passenger| {
+            let member = current.is_some_and(|account| account.passenger_ids.contains(&passenger.id));
+            (format!("{}, {}", passenger.id, passenger.name), member)
+        })
+        .collect();
+    members.sort();
+
+    vec![
+        Question::input("name")
+            .message("Enter the company name")
+            .default(current.map_or(String::new(), |account| account.name.clone()))
+            .validate(|name, _| {
+                if name.trim().is_empty() {
+                    Err("The company name cannot be empty".to_string())
+                } else {
+                    Ok(())
+                }
+            })
+            .build(),
+        Question::input("discount_percent")
+            .message("Enter the negotiated discount in percent")
+            .default(current.map_or("0".to_string(), |account| account.discount_percent.to_string()))
+            .validate(|percent, _| match percent.trim().parse::<u32>() {
+                Ok(percent) if percent <= 100 => Ok(()),
+                _ => Err("Enter a whole number from 0 to 100".to_string()),
+            })
+            .build(),
+        Question::input("billing_contact")
+            .message("Enter the billing email address")
+            .default(current.map_or(String::new(), |account| account.billing_contact.clone()))
+            .build(),
+        Question::input("credit_limit")
+            .message("Enter the monthly credit limit, 0 for no limit")
+            .default(format_money(current.map_or(0, |account| account.credit_limit)))
+            .validate(|limit, _| parse_money(limit).map(|_| ()).map_err(|e| e.to_string()))
+            .build(),
+        Question::multi_select("passengers")
+            .message("Select the passengers travelling on the account")
+            .choices_with_default(members)
+            .build(),
+    ]
+}
+
+/// Fills an account from the answers, making sure no passenger ends up on two accounts.
+fn apply_account_answers(
+    accounts: &AccountList,
+    account: &mut CorporateAccount,
+    answers: &Answers,
+) -> Result<()> {
+    let passenger_ids: Vec<String> = answers["passengers"]
+        .as_list_items()
+        .unwrap()
+        .iter()
+        .map(|item| item.text.split(',').next().unwrap().trim().to_string())
+        .collect();
+    for passenger_id in &passenger_ids {
+        if let Some(other) = account_for(accounts, passenger_id).filter(|other| other.id != account.id) {
+            return Err(anyhow!(
+                "Passenger {} already travels on account {} ({})",
+                passenger_id,
+                other.id,
+                other.name
+            ));
+        }
+    }
+
+    account.name = answers["name"].as_string().unwrap().trim().to_string();
+    account.discount_percent = answers["discount_percent"].as_string().unwrap().trim().parse()?;
+    account.billing_contact = answers["billing_contact"].as_string().unwrap().trim().to_string();
+    account.credit_limit = parse_money(answers["credit_limit"].as_string().unwrap())?;
+    account.passenger_ids = passenger_ids;
+    Ok(())
+}
+
+fn add_account(
+    accounts: &mut AccountList,
+    passengers: &PassengerList,
+) -> Result<()> {
+    let answers = requestty::prompt(account_questions(None, passengers))?;
+    let id = (1..)
+        .map(|number| format!("C{}", number))
+        .find(|id| !accounts.contains_key(id))
+        .unwrap();
+    let mut account = CorporateAccount {
+        id: id.clone(),
+        name: String::new(),
+        discount_percent: 0,
+        billing_contact: String::new(),
+        credit_limit: 0,
+        passenger_ids: Vec::new(),
+    };
+    apply_account_answers(accounts, &mut account, &answers)?;
+    println!("Opened corporate account {} for {}", id, account.name);
+    accounts.insert(id, account);
+    Ok(())
+}
+
+fn edit_account(
+    accounts: &mut AccountList,
+    passengers: &PassengerList,
+) -> Result<()> {
+    let id = select_account(accounts, "Select account to edit")?;
+    let answers = requestty::prompt(account_questions(Some(&accounts[&id]), passengers))?;
+    let mut account = accounts.remove(&id).unwrap();
+    let result = apply_account_answers(accounts, &mut account, &answers);
+    accounts.insert(id, account);
+    result
+}
+
+/// Prints or writes the consolidated invoice of everything billed to the account in a month.
+fn print_account_invoice(
+    accounts: &AccountList,
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    trains: &TrainList,
+    ledger: &mut Ledger,
+    id: &str,
+) -> Result<()> {
+    let today = Local::now().date_naive();
+    let answers = requestty::prompt(vec![
+        Question::input("month")
+            .message("Enter the month to invoice (YYYY-MM)")
+            .default(today.format("%Y-%m").to_string())
+            .build(),
+        Question::select("format")
+            .message("Select invoice format")
+            .choice("Plain text")
+            .choice("HTML")
+            .build(),
+    ])?;
+    let month = parse_date(&format!("{}-01", answers["month"].as_string().unwrap().trim()))?;
+
+    let account = &accounts[id];
+    let number = ledger.invoice_number(&format!("{}_{}", account.id, month.format("%Y-%m")));
+    let invoice = AccountInvoice::new(number, today, account, month, bookings, passengers, trains, ledger);
+    match answers["format"].as_list_item().unwrap().index {
+        0 => println!("{}", invoice.to_text()),
+        1 => {
+            let path = format!("invoice-{:06}.html", number);
+            fs::write(&path, invoice.to_html()).with_context(|| format!("Could not write {}", path))?;
+            println!("Invoice written to {}", path);
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn report_spend(
+    accounts: &AccountList,
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    ledger: &Ledger,
+    id: &str,
+) -> Result<()> {
+    let today = Local::now().date_naive();
+    let answers = requestty::prompt(vec![
+        Question::input("from")
+            .message("Enter the first travel date (YYYY-MM-DD)")
+            .default(today.with_day(1).unwrap().to_string())
+            .build(),
+        Question::input("to")
+            .message("Enter the last travel date (YYYY-MM-DD)")
+            .default(today.to_string())
+            .build(),
+    ])?;
+    let from = parse_date(answers["from"].as_string().unwrap())?;
+    let to = parse_date(answers["to"].as_string().unwrap())?;
+
+    let account = &accounts[id];
+    let lines = spend_report(account, bookings, passengers, ledger, from, to);
+    print_spend_report(account, &lines, from, to);
+    Ok(())
+}
+
+pub fn manage_corporate_accounts(
+    accounts: &mut AccountList,
+    passengers: &PassengerList,
+    bookings: &BookingList,
+    trains: &TrainList,
+    ledger: &mut Ledger,
+) -> Result<()> {
+    loop {
+        clear_screen();
+        match add_remove_prompt()? {
+            Action::List => {
+                if accounts.is_empty() {
+                    println!("No corporate accounts found");
+                    continue_prompt();
+                    continue;
+                }
+                let mut listed: Vec<&CorporateAccount> = accounts.values().collect();
+                listed.sort_by(|a, b| a.id.cmp(&b.id));
+                for account in listed {
+                    let limit = match account.credit_limit {
+                        0 => "no credit limit".to_string(),
+                        limit => format!("{} a month credit limit", format_money(limit)),
+                    };
+                    println!(
+                        "{}: {}, {}% off fares, billed to {}, {}, {} passengers",
+                        account.id,
+                        account.name,
+                        account.discount_percent,
+                        account.billing_contact,
+                        limit,
+                        account.passenger_ids.len()
+                    );
+                }
+
+                let id = select_account(accounts, "Select account")?;
+                let report = requestty::prompt_one(
+                    Question::select("report")
+                        .message("Select report")
+                        .choice("Monthly invoice")
+                        .choice("Spend report")
+                        .choice("Back")
+                        .build(),
+                )?;
+                let result = match report.as_list_item().unwrap().index {
+                    0 => print_account_invoice(accounts, bookings, passengers, trains, ledger, &id),
+                    1 => report_spend(accounts, bookings, passengers, ledger, &id),
+                    _ => Ok(()),
+                };
+                if let Err(e) = result {
+                    println!("Error: {}", e);
+                }
+                continue_prompt();
+            }
+            Action::Add => {
+                if let Err(e) = add_account(accounts, passengers) {
+                    println!("Error: {}", e);
+                }
+                continue_prompt();
+            }
+            Action::Edit => {
+                if accounts.is_empty() {
+                    println!("No corporate accounts to edit");
+                    continue_prompt();
+                } else if let Err(e) = edit_account(accounts, passengers) {
+                    println!("Error: {}", e);
+                    continue_prompt();
+                }
+            }
+            Action::Remove => {
+                if accounts.is_empty() {
+                    println!("No corporate accounts to close");
+                } else {
+                    let id = select_account(accounts, "Select account to close")?;
+                    let account = accounts.remove(&id).unwrap();
+                    println!("Closed corporate account {} for {}", account.id, account.name);
+                }
+                continue_prompt();
+            }
+            Action::Back => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payments::{MockProcessor, PaymentMethod};
+    use crate::test_support::{adult, book, express, travel_date};
+
+    fn setup() -> (AccountList, BookingList, PassengerList, TrainList, Ledger) {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+        let mut accounts = AccountList::new();
+        let mut ledger = Ledger::new(Box::new(MockProcessor::new()));
+
+        for (id, name) in [("P1", "John Doe"), ("P2", "Jane Doe"), ("P3", "Zoe Smith")] {
+            passengers.insert(id.to_string(), adult(id, name));
+        }
+        let mut train = express();
+        train.fare = 4000;
+        trains.insert(1, train);
+        accounts.insert(
+            "C1".to_string(),
+            CorporateAccount {
+                id: "C1".to_string(),
+                name: "Acme Ltd".to_string(),
+                discount_percent: 15,
+                billing_contact: "travel@acme.example".to_string(),
+                credit_limit: 0,
+                passenger_ids: vec!["P1".to_string(), "P2".to_string()],
+            },
+        );
+
+        let now = travel_date().and_hms_opt(9, 0, 0).unwrap();
+        for id in ["P1", "P2", "P3"] {
+            let booking_id = book(&mut bookings, &mut passengers, &mut trains, id, 1).unwrap();
+            match account_for(&accounts, id) {
+                Some(account) => {
+                    apply_corporate_rate(&mut bookings, account, &booking_id).unwrap();
+                    ledger
+                        .bill_account(&booking_id, account, bookings[&booking_id].total(), now)
+                        .unwrap();
+                }
+                None => ledger
+                    .charge(&booking_id, bookings[&booking_id].total(), PaymentMethod::Card, now)
+                    .unwrap(),
+            }
+        }
+
+        (accounts, bookings, passengers, trains, ledger)
+    }
+
+    #[test]
+    fn test_corporate_rate_and_spend_report() {
+        let (accounts, bookings, passengers, _, ledger) = setup();
+        assert_eq!(bookings["P1_1_20240610"].discount, 600);
+        assert_eq!(bookings["P1_1_20240610"].account.as_deref(), Some("C1"));
+        assert_eq!(bookings["P3_1_20240610"].account, None);
+        assert_eq!(ledger.entries[0].method, PaymentMethod::Account);
+        assert_eq!(ledger.entries[0].reference, "C1");
+
+        let lines = spend_report(
+            &accounts["C1"],
+            &bookings,
+            &passengers,
+            &ledger,
+            travel_date(),
+            travel_date(),
+        );
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].name, "John Doe");
+        assert_eq!(lines[0].trips, 1);
+        assert_eq!(lines[0].fares, 4000);
+        assert_eq!(lines[0].saved, 600);
+        assert_eq!(lines[0].paid, 3400);
+        assert!(spend_report(
+            &accounts["C1"],
+            &bookings,
+            &passengers,
+            &ledger,
+            next_month(travel_date()),
+            next_month(travel_date())
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_negotiated_rate_rounds_down() {
+        let (mut accounts, mut bookings, _, _, _) = setup();
+        bookings.get_mut("P3_1_20240610").unwrap().fare = 3333;
+        apply_corporate_rate(&mut bookings, &accounts["C1"], "P3_1_20240610").unwrap();
+        // 15% of $33.33 is rounded down to $4.99
+        assert_eq!(bookings["P3_1_20240610"].discount, 499);
+        assert_eq!(bookings["P3_1_20240610"].total(), 3333 - 499);
+
+        accounts.get_mut("C1").unwrap().discount_percent = 0;
+        apply_corporate_rate(&mut bookings, &accounts["C1"], "P3_1_20240610").unwrap();
+        assert_eq!(bookings["P3_1_20240610"].discount, 0);
+        assert_eq!(bookings["P3_1_20240610"].account.as_deref(), Some("C1"));
+        assert!(apply_corporate_rate(&mut bookings, &accounts["C1"], "P9_1_20240610").is_err());
+    }
+
+    #[test]
+    fn test_credit_limit_caps_monthly_billing() {
+        let (mut accounts, _, _, _, mut ledger) = setup();
+        let june = travel_date().and_hms_opt(10, 0, 0).unwrap();
+        assert_eq!(ledger.billed_to("C1", travel_date()), 6800);
+
+        // The limit counts what is already billed this month, and a refused bill leaves no entry
+        accounts.get_mut("C1").unwrap().credit_limit = 10000;
+        let entries = ledger.entries.len();
+        assert_eq!(
+            ledger
+                .bill_account("P3_1_20240610", &accounts["C1"], 3400, june)
+                .unwrap_err()
+                .to_string(),
+            "Account C1 has $32.00 of its $100.00 monthly credit limit left"
+        );
+        assert_eq!(ledger.entries.len(), entries);
+        ledger
+            .bill_account("P3_1_20240610", &accounts["C1"], 3200, june)
+            .unwrap();
+        assert_eq!(ledger.billed_to("C1", travel_date()), 10000);
+
+        // Refunds free up the limit, and next month starts again
+        ledger.refund("P3_1_20240610", 3200, june).unwrap();
+        ledger
+            .bill_account("P3_1_20240610", &accounts["C1"], 3200, june)
+            .unwrap();
+        let july = NaiveDate::from_ymd_opt(2024, 7, 1).unwrap();
+        assert_eq!(ledger.billed_to("C1", july), 0);
+        ledger
+            .bill_account(
+                "P3_1_20240610",
+                &accounts["C1"],
+                10000,
+                july.and_hms_opt(9, 0, 0).unwrap(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_monthly_invoice_consolidates_account_charges() {
+        let (accounts, bookings, passengers, trains, mut ledger) = setup();
+        ledger
+            .refund(
+                "P2_1_20240610",
+                3400,
+                NaiveDate::from_ymd_opt(2024, 7, 1)
+                    .unwrap()
+                    .and_hms_opt(9, 0, 0)
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let june = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let invoice = AccountInvoice::new(1, june, &accounts["C1"], june, &bookings, &passengers, &trains, &ledger);
+        assert_eq!(invoice.lines.len(), 2);
+        assert_eq!(invoice.total(), 6800);
+        let text = invoice.to_text();
+        assert!(text.contains("Acme Ltd (C1)"));
+        assert!(text.contains("P1_1_20240610, John Doe, train 1 Express on 2024-06-10"));
+        assert!(!text.contains("P3_1_20240610"));
+
+        let july = NaiveDate::from_ymd_opt(2024, 7, 1).unwrap();
+        let invoice = AccountInvoice::new(2, july, &accounts["C1"], july, &bookings, &passengers, &trains, &ledger);
+        assert_eq!(invoice.lines.len(), 1);
+        assert_eq!(invoice.lines[0].refunded, 3400);
+        assert_eq!(invoice.total(), -3400);
+    }
+}
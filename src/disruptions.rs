@@ -0,0 +1,1094 @@
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
+use requestty::{self, Answers, Question};
+
+use crate::actions::{add_remove_prompt, Action};
+use crate::bookings::{
+    edit_booking, record_event, release_passenger, Booking, BookingHistory, BookingList, BookingStatus, EventKind,
+};
+use crate::fares::{release_bucket, reserve_bucket, AppliedLoadBand};
+use crate::helper::{clear_screen, continue_prompt, format_money, parse_date};
+use crate::minors::Supervision;
+use crate::notifications::{notify, Notifier};
+use crate::passengers::PassengerList;
+use crate::payments::Ledger;
+use crate::rules::RulePipeline;
+use crate::trains::{Train, TrainList};
+use crate::vouchers::issue_compensation;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Disruption {
+    Delayed(u32), // Minutes late
+    Cancelled,
+}
+
+impl std::fmt::Display for Disruption {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            Disruption::Delayed(minutes) => write!(f, "Delayed {} min", minutes),
+            Disruption::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceDisruption {
+    pub disruption: Disruption,
+    pub reason: String,
+    pub recorded: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImpactReason {
+    Cancelled,
+    Delayed(u32),
+    MissedConnection(u32), // Line of the disrupted train the connection relied on
+    Overlap(u32),          // Line of the delayed train now running into this trip
+}
+
+impl std::fmt::Display for ImpactReason {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            ImpactReason::Cancelled => write!(f, "Train cancelled"),
+            ImpactReason::Delayed(minutes) => write!(f, "Train delayed {} min", minutes),
+            ImpactReason::MissedConnection(line) => write!(f, "Connection from train {} will be missed", line),
+            ImpactReason::Overlap(line) => write!(f, "Overlaps delayed train {}", line),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Impact {
+    pub booking_id: String,
+    pub passenger_id: String,
+    pub reason: ImpactReason,
+}
+
+pub fn is_cancelled(
+    train: &Train,
+    travel_date: NaiveDate,
+) -> bool {
+    train
+        .disruptions
+        .get(&travel_date)
+        .is_some_and(|service| service.disruption == Disruption::Cancelled)
+}
+
+/// Timetabled departure and arrival on the date. Trains arriving before they depart arrive the
+/// next day.
+pub fn scheduled_times(
+    train: &Train,
+    travel_date: NaiveDate,
+) -> (NaiveDateTime, NaiveDateTime) {
+    let departure = travel_date.and_time(train.departure);
+    let mut arrival = travel_date.and_time(train.arrival);
+    if arrival < departure {
+        arrival += Duration::days(1);
+    }
+    (departure, arrival)
+}
+
+/// Departure and arrival including any delay, or None when the train is cancelled.
+pub fn expected_times(
+    train: &Train,
+    travel_date: NaiveDate,
+) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    let (departure, arrival) = scheduled_times(train, travel_date);
+    match train.disruptions.get(&travel_date).map(|service| &service.disruption) {
+        None => Some((departure, arrival)),
+        Some(Disruption::Delayed(minutes)) => {
+            let delay = Duration::minutes(i64::from(*minutes));
+            Some((departure + delay, arrival + delay))
+        }
+        Some(Disruption::Cancelled) => None,
+    }
+}
+
+/// Bookings the agent has to act on after a disruption to a train: every booking on the train
+/// itself, and the same passengers' later trips that day whose connection is lost or that the
+/// delayed train now runs into.
+pub fn impacted_bookings(
+    bookings: &BookingList,
+    trains: &TrainList,
+    train_line: u32,
+    travel_date: NaiveDate,
+) -> Vec<Impact> {
+    let Some(train) = trains.get(&train_line) else {
+        return Vec::new();
+    };
+    let Some(service) = train.disruptions.get(&travel_date) else {
+        return Vec::new();
+    };
+    let (_, scheduled_arrival) = scheduled_times(train, travel_date);
+    let expected_arrival = expected_times(train, travel_date).map(|(_, arrival)| arrival);
+
+    let mut disrupted: Vec<_> = bookings
+        .values()
+        .filter(|booking| booking.train_line == train_line && booking.travel_date == travel_date)
+        .collect();
+    disrupted.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut impacts = Vec::new();
+    for booking in disrupted {
+        impacts.push(Impact {
+            booking_id: booking.id.clone(),
+            passenger_id: booking.passenger_id.clone(),
+            reason: match service.disruption {
+                Disruption::Delayed(minutes) => ImpactReason::Delayed(minutes),
+                Disruption::Cancelled => ImpactReason::Cancelled,
+            },
+        });
+
+        let mut onward: Vec<_> = bookings
+            .values()
+            .filter(|other| other.passenger_id == booking.passenger_id && other.id != booking.id)
+            .filter(|other| other.travel_date == travel_date)
+            .filter_map(|other| {
+                let other_train = trains.get(&other.train_line)?;
+                let (departure, _) = expected_times(other_train, other.travel_date)?;
+                (departure >= scheduled_arrival).then_some((other, other_train, departure))
+            })
+            .collect();
+        onward.sort_by_key(|(_, _, departure)| *departure);
+
+        for (other, other_train, departure) in onward {
+            let connects = other_train.origin == train.destination;
+            let reason = match expected_arrival {
+                None if connects => ImpactReason::MissedConnection(train_line),
+                Some(arrival) if arrival > departure && connects => ImpactReason::MissedConnection(train_line),
+                Some(arrival) if arrival > departure => ImpactReason::Overlap(train_line),
+                _ => continue,
+            };
+            impacts.push(Impact {
+                booking_id: other.id.clone(),
+                passenger_id: other.passenger_id.clone(),
+                reason,
+            });
+        }
+    }
+    impacts
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Reaccommodation {
+    pub moved: Vec<(String, u32)>,       // Booking ID and the train it was moved to
+    pub unplaced: Vec<(String, String)>, // Booking ID and why no alternative could take it
+}
+
+/// Other trains on the same route and date that have not left yet and depart no earlier than the
+/// original, earliest first.
+pub fn alternative_trains(
+    trains: &TrainList,
+    train_line: u32,
+    travel_date: NaiveDate,
+    now: NaiveDateTime,
+) -> Vec<u32> {
+    let Some(train) = trains.get(&train_line) else {
+        return Vec::new();
+    };
+    let (departure, _) = scheduled_times(train, travel_date);
+
+    let mut alternatives: Vec<(NaiveDateTime, u32)> = trains
+        .values()
+        .filter(|other| other.line != train_line)
+        .filter(|other| other.origin == train.origin && other.destination == train.destination)
+        .filter_map(|other| {
+            let (other_departure, _) = expected_times(other, travel_date)?;
+            (other_departure >= departure && other_departure > now).then_some((other_departure, other.line))
+        })
+        .collect();
+    alternatives.sort();
+    alternatives.into_iter().map(|(_, line)| line).collect()
+}
+
+// Where a booking sat before a move that may have to be undone
+struct Placement {
+    train_line: u32,
+    seat: u32,
+    fare_bucket: Option<String>,
+    load_band: Option<AppliedLoadBand>,
+    warnings: Vec<String>,
+}
+
+impl Placement {
+    fn of(booking: &Booking) -> Self {
+        Self {
+            train_line: booking.train_line,
+            seat: booking.seat,
+            fare_bucket: booking.fare_bucket.clone(),
+            load_band: booking.load_band,
+            warnings: booking.warnings.clone(),
+        }
+    }
+
+    // Puts the booking back without running the rules, since the original train may be cancelled
+    fn restore(
+        self,
+        bookings: &mut BookingList,
+        trains: &mut TrainList,
+        booking_id: &str,
+    ) {
+        let booking = &bookings[booking_id];
+        let moved_to = trains.get_mut(&booking.train_line).unwrap();
+        release_passenger(bookings, moved_to, &booking.passenger_id, booking_id);
+        let booking = bookings.get_mut(booking_id).unwrap();
+        if let Some(code) = &booking.fare_bucket {
+            release_bucket(moved_to, code, booking.travel_date);
+        }
+        let original = trains.get_mut(&self.train_line).unwrap();
+        original.passengers.insert(booking.passenger_id.clone());
+        if let Some(code) = &self.fare_bucket {
+            reserve_bucket(original, code, booking.travel_date);
+        }
+        booking.train_line = self.train_line;
+        booking.seat = self.seat;
+        booking.fare_bucket = self.fare_bucket;
+        booking.load_band = self.load_band;
+        booking.warnings = self.warnings;
+    }
+}
+
+/// Moves every booking on a cancelled train to the best alternative that still has room and
+/// fits around the passenger's other trips, among the trains that have not left by `now`. Minors travelling with an adult move with them as one
+/// group onto the same train, or the whole group stays unplaced. Passengers keep the fare they paid.
+pub fn reaccommodate(
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    trains: &mut TrainList,
+    rules: &RulePipeline,
+    train_line: u32,
+    travel_date: NaiveDate,
+    now: NaiveDateTime,
+) -> Result<Reaccommodation> {
+    let train = trains.get(&train_line).ok_or_else(|| anyhow!("Train not found"))?;
+    if !is_cancelled(train, travel_date) {
+        return Err(anyhow!("Train {} is not cancelled on {}", train_line, travel_date));
+    }
+
+    let mut stranded: Vec<&Booking> = bookings
+        .values()
+        .filter(|booking| booking.train_line == train_line && booking.travel_date == travel_date)
+        .filter(|booking| booking.status == BookingStatus::Booked)
+        .collect();
+    stranded.sort_by_key(|booking| booking.seat);
+    // Each group is an adult followed by the minors they look after on this train
+    let adult_of = |booking: &Booking| match &booking.supervision {
+        Supervision::Adult(adult_id) if stranded.iter().any(|other| &other.id == adult_id) => Some(adult_id.clone()),
+        _ => None,
+    };
+    let groups: Vec<Vec<String>> = stranded
+        .iter()
+        .filter(|booking| adult_of(booking).is_none())
+        .map(|lead| {
+            let mut group = vec![lead.id.clone()];
+            group.extend(
+                stranded
+                    .iter()
+                    .filter(|booking| adult_of(booking).as_ref() == Some(&lead.id))
+                    .map(|booking| booking.id.clone()),
+            );
+            group
+        })
+        .collect();
+    let alternatives = alternative_trains(trains, train_line, travel_date, now);
+
+    let mut result = Reaccommodation::default();
+    for group in groups {
+        let mut reason = "No alternative train on this route".to_string();
+        let mut placed = false;
+        for &alternative in &alternatives {
+            let mut moved = Vec::new();
+            for booking_id in &group {
+                let booking = &bookings[booking_id];
+                let (fare, discount, placement) = (booking.fare, booking.discount, Placement::of(booking));
+                match edit_booking(
+                    bookings,
+                    passengers,
+                    trains,
+                    rules,
+                    booking_id.clone(),
+                    alternative,
+                    travel_date,
+                ) {
+                    Ok(()) => {
+                        let booking = bookings.get_mut(booking_id).unwrap();
+                        booking.fare = fare;
+                        booking.discount = discount;
+                        moved.push((booking_id, placement));
+                    }
+                    Err(e) => {
+                        reason = e.to_string();
+                        break;
+                    }
+                }
+            }
+            if moved.len() == group.len() {
+                result
+                    .moved
+                    .extend(group.iter().map(|booking_id| (booking_id.clone(), alternative)));
+                placed = true;
+                break;
+            }
+            for (booking_id, placement) in moved.into_iter().rev() {
+                placement.restore(bookings, trains, booking_id);
+            }
+        }
+        if !placed {
+            result
+                .unplaced
+                .extend(group.into_iter().map(|booking_id| (booking_id, reason.clone())));
+        }
+    }
+    Ok(result)
+}
+
+/// Runs the bulk rebooking for a cancelled train, recording each move in the booking history.
+fn rebook_cancelled(
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    trains: &mut TrainList,
+    rules: &RulePipeline,
+    history: &mut BookingHistory,
+    train_line: u32,
+    travel_date: NaiveDate,
+) -> Result<()> {
+    let now = Local::now().naive_local();
+    let result = reaccommodate(bookings, passengers, trains, rules, train_line, travel_date, now)?;
+    for (booking_id, alternative) in &result.moved {
+        println!(
+            "Moved {} to train {} seat {}",
+            booking_id, alternative, bookings[booking_id].seat
+        );
+        record_event(
+            history,
+            &bookings[booking_id],
+            now,
+            EventKind::Rebooked,
+            format!("Rebooked from cancelled train {} to train {}", train_line, alternative),
+        );
+    }
+    for (booking_id, reason) in &result.unplaced {
+        println!("Could not place {}: {}", booking_id, reason);
+    }
+    println!(
+        "{} bookings moved, {} could not be placed",
+        result.moved.len(),
+        result.unplaced.len()
+    );
+    Ok(())
+}
+
+pub fn manage_disruptions(
+    trains: &mut TrainList,
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    rules: &RulePipeline,
+    ledger: &mut Ledger,
+    history: &mut BookingHistory,
+    notifier: &mut Notifier,
+) -> Result<()> {
+    loop {
+        notify(notifier, history, bookings, passengers, trains);
+        clear_screen();
+        match add_remove_prompt()? {
+            Action::List => {
+                let disruptions = disruption_choices(trains);
+                if disruptions.is_empty() {
+                    println!("No disruptions recorded");
+                }
+                for (train_line, travel_date) in disruptions {
+                    let service = &trains[&train_line].disruptions[&travel_date];
+                    println!(
+                        "Train {} {} on {}: {} ({})",
+                        train_line, trains[&train_line].name, travel_date, service.disruption, service.reason
+                    );
+                    list_impacted_bookings(bookings, passengers, trains, train_line, travel_date);
+                }
+                continue_prompt();
+            }
+            Action::Add => {
+                if trains.is_empty() {
+                    println!("No trains found");
+                    continue_prompt();
+                    continue;
+                }
+                let train_choices: Vec<String> = trains
+                    .values()
+                    .map(|train| format!("{}, {}", train.line, train.name))
+                    .collect();
+                let mut questions = vec![
+                    Question::select("train")
+                        .message("Select disrupted train")
+                        .choices(train_choices)
+                        .build(),
+                    Question::input("travel_date")
+                        .message("Enter the travel date (YYYY-MM-DD)")
+                        .default(Local::now().date_naive().to_string())
+                        .build(),
+                ];
+                questions.extend(disruption_questions(None));
+                let answers = requestty::prompt(questions)?;
+
+                let train_line: u32 = answers["train"]
+                    .as_list_item()
+                    .unwrap()
+                    .text
+                    .split(',')
+                    .next()
+                    .unwrap()
+                    .trim()
+                    .parse()?;
+                match parse_date(answers["travel_date"].as_string().unwrap())
+                    .and_then(|travel_date| Ok((travel_date, parse_disruption(&answers)?)))
+                {
+                    Ok((travel_date, service)) => {
+                        record_disruption(trains, train_line, travel_date, service)?;
+                        list_impacted_bookings(bookings, passengers, trains, train_line, travel_date);
+                        record_impacts(history, bookings, trains, train_line, travel_date);
+                        compensate(ledger, bookings, passengers, trains, history, train_line, travel_date);
+                        offer_rebooking(bookings, passengers, trains, rules, history, train_line, travel_date)?;
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+                continue_prompt();
+            }
+            Action::Edit => {
+                let Some((train_line, travel_date)) = select_disruption(trains, "Select disruption to edit")? else {
+                    continue;
+                };
+                let current = trains[&train_line].disruptions[&travel_date].clone();
+                let answers = requestty::prompt(disruption_questions(Some(&current)))?;
+                match parse_disruption(&answers) {
+                    Ok(service) => {
+                        record_disruption(trains, train_line, travel_date, service)?;
+                        list_impacted_bookings(bookings, passengers, trains, train_line, travel_date);
+                        record_impacts(history, bookings, trains, train_line, travel_date);
+                        compensate(ledger, bookings, passengers, trains, history, train_line, travel_date);
+                        offer_rebooking(bookings, passengers, trains, rules, history, train_line, travel_date)?;
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+                continue_prompt();
+            }
+            Action::Remove => {
+                if let Some((train_line, travel_date)) = select_disruption(trains, "Select disruption to clear")? {
+                    trains.get_mut(&train_line).unwrap().disruptions.remove(&travel_date);
+                }
+            }
+            Action::Back => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn offer_rebooking(
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    trains: &mut TrainList,
+    rules: &RulePipeline,
+    history: &mut BookingHistory,
+    train_line: u32,
+    travel_date: NaiveDate,
+) -> Result<()> {
+    let stranded = bookings
+        .values()
+        .any(|booking| booking.train_line == train_line && booking.travel_date == travel_date);
+    if !is_cancelled(&trains[&train_line], travel_date) || !stranded {
+        return Ok(());
+    }
+
+    let confirmed = requestty::prompt_one(
+        Question::confirm("rebook")
+            .message("Rebook passengers onto alternative trains?")
+            .build(),
+    )?;
+    if confirmed.as_bool().unwrap() {
+        if let Err(e) = rebook_cancelled(bookings, passengers, trains, rules, history, train_line, travel_date) {
+            println!("Error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+pub fn record_disruption(
+    trains: &mut TrainList,
+    train_line: u32,
+    travel_date: NaiveDate,
+    service: ServiceDisruption,
+) -> Result<()> {
+    let train = trains.get_mut(&train_line).ok_or_else(|| anyhow!("Train not found"))?;
+    train.disruptions.insert(travel_date, service);
+    Ok(())
+}
+
+/// Adds the disruption to the history of every impacted booking, skipping impacts already recorded
+/// so saving an unchanged disruption again adds nothing.
+fn record_impacts(
+    history: &mut BookingHistory,
+    bookings: &BookingList,
+    trains: &TrainList,
+    train_line: u32,
+    travel_date: NaiveDate,
+) {
+    let service = &trains[&train_line].disruptions[&travel_date];
+    let now = Local::now().naive_local();
+    for impact in impacted_bookings(bookings, trains, train_line, travel_date) {
+        let description = format!("{} on {} ({})", impact.reason, travel_date, service.reason);
+        let recorded = history.iter().any(|event| {
+            event.booking_id == impact.booking_id
+                && event.kind == EventKind::Disrupted
+                && event.description == description
+        });
+        if !recorded {
+            record_event(
+                history,
+                &bookings[&impact.booking_id],
+                now,
+                EventKind::Disrupted,
+                description,
+            );
+        }
+    }
+}
+
+/// Issues compensation vouchers to the passengers on the disrupted train and notes them in each
+/// booking's history.
+fn compensate(
+    ledger: &mut Ledger,
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    trains: &TrainList,
+    history: &mut BookingHistory,
+    train_line: u32,
+    travel_date: NaiveDate,
+) {
+    let now = Local::now().naive_local();
+    let issued = issue_compensation(
+        &mut ledger.vouchers,
+        bookings,
+        trains,
+        train_line,
+        travel_date,
+        now.date(),
+    );
+    for (booking_id, code, amount) in issued {
+        let booking = &bookings[&booking_id];
+        println!(
+            "  Compensation {} for {}: voucher {}",
+            format_money(amount),
+            passengers
+                .get(&booking.passenger_id)
+                .map_or(booking.passenger_id.as_str(), |passenger| passenger.name.as_str()),
+            code
+        );
+        record_event(
+            history,
+            booking,
+            now,
+            EventKind::Disrupted,
+            format!("Compensated {} with voucher {}", format_money(amount), code),
+        );
+    }
+}
+
+fn list_impacted_bookings(
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    trains: &TrainList,
+    train_line: u32,
+    travel_date: NaiveDate,
+) {
+    let impacts = impacted_bookings(bookings, trains, train_line, travel_date);
+    if impacts.is_empty() {
+        println!("  No bookings affected");
+    }
+    for impact in impacts {
+        println!(
+            "  {} {}: {}",
+            impact.booking_id,
+            passengers
+                .get(&impact.passenger_id)
+                .map_or(impact.passenger_id.as_str(), |passenger| passenger.name.as_str()),
+            impact.reason
+        );
+    }
+}
+
+/// Train line and date of every recorded disruption, in date order.
+fn disruption_choices(trains: &TrainList) -> Vec<(u32, NaiveDate)> {
+    let mut choices: Vec<(u32, NaiveDate)> = trains
+        .values()
+        .flat_map(|train| train.disruptions.keys().map(|date| (train.line, *date)))
+        .collect();
+    choices.sort_by_key(|(line, date)| (*date, *line));
+    choices
+}
+
+fn select_disruption(
+    trains: &TrainList,
+    message: &str,
+) -> Result<Option<(u32, NaiveDate)>> {
+    let choices = disruption_choices(trains);
+    if choices.is_empty() {
+        println!("No disruptions recorded");
+        continue_prompt();
+        return Ok(None);
+    }
+
+    let labels: Vec<String> = choices
+        .iter()
+        .map(|(line, date)| {
+            format!(
+                "Train {} on {}: {}",
+                line, date, trains[line].disruptions[date].disruption
+            )
+        })
+        .collect();
+    let selection = requestty::prompt_one(Question::select("disruption").message(message).choices(labels).build())?;
+    Ok(Some(choices[selection.as_list_item().unwrap().index]))
+}
+
+fn disruption_questions(current: Option<&ServiceDisruption>) -> Vec<Question<'static>> {
+    let delay = match current.map(|service| &service.disruption) {
+        Some(Disruption::Delayed(minutes)) => minutes.to_string(),
+        _ => String::new(),
+    };
+    vec![
+        Question::select("kind")
+            .message("Select disruption")
+            .choice("Delay")
+            .choice("Cancellation")
+            .default(usize::from(
+                current.is_some_and(|service| service.disruption == Disruption::Cancelled),
+            ))
+            .build(),
+        Question::input("minutes")
+            .message("Enter the delay in minutes")
+            .default(delay)
+            .when(|answers: &Answers| answers["kind"].as_list_item().unwrap().index == 0)
+            .build(),
+        Question::input("reason")
+            .message("Enter the reason")
+            .default(current.map(|service| service.reason.clone()).unwrap_or_default())
+            .build(),
+    ]
+}
+
+fn parse_disruption(answers: &Answers) -> Result<ServiceDisruption> {
+    let disruption = match answers["kind"].as_list_item().unwrap().index {
+        0 => {
+            let minutes: u32 = answers["minutes"].as_string().unwrap().trim().parse()?;
+            if minutes == 0 {
+                return Err(anyhow!("A delay must be at least one minute"));
+            }
+            Disruption::Delayed(minutes)
+        }
+        _ => Disruption::Cancelled,
+    };
+    Ok(ServiceDisruption {
+        disruption,
+        reason: answers["reason"].as_string().unwrap().trim().to_string(),
+        recorded: Local::now().naive_local(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::{add_booking, remove_booking};
+    use crate::minors::Supervision;
+    use crate::passengers::{approximate_date_of_birth, Passenger};
+    use crate::payments::MockProcessor;
+    use crate::test_support::travel_date;
+    use chrono::NaiveTime;
+
+    fn now() -> NaiveDateTime {
+        travel_date().and_hms_opt(8, 0, 0).unwrap()
+    }
+
+    fn service(disruption: Disruption) -> ServiceDisruption {
+        ServiceDisruption {
+            disruption,
+            reason: "Signal failure".to_string(),
+            recorded: now(),
+        }
+    }
+
+    fn train(
+        line: u32,
+        origin: &str,
+        destination: &str,
+        departure: u32,
+        arrival: u32,
+    ) -> Train {
+        Train::new(
+            line,
+            format!("Train {}", line),
+            100,
+            origin.to_string(),
+            destination.to_string(),
+            NaiveTime::from_hms_opt(departure, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(arrival, 0, 0).unwrap(),
+        )
+    }
+
+    /// P1 connects from train 1 onto train 2 at Hamilton, then takes train 3 from Niagara.
+    fn setup() -> (BookingList, TrainList) {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+
+        passengers.insert(
+            "P1".to_string(),
+            Passenger::new(
+                "P1".to_string(),
+                "John Doe".to_string(),
+                approximate_date_of_birth(30, travel_date()),
+            ),
+        );
+        trains.insert(1, train(1, "Toronto", "Hamilton", 9, 10));
+        trains.insert(2, train(2, "Hamilton", "Niagara", 11, 12));
+        trains.insert(3, train(3, "Niagara", "Ottawa", 13, 17));
+        for line in [1, 2, 3] {
+            add_booking(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &RulePipeline::default(),
+                "P1".to_string(),
+                line,
+                travel_date(),
+                Supervision::Alone,
+            )
+            .unwrap();
+        }
+
+        (bookings, trains)
+    }
+
+    #[test]
+    fn test_expected_times() {
+        let mut trains = TrainList::new();
+        trains.insert(1, train(1, "Toronto", "Montreal", 22, 5));
+        let (departure, arrival) = scheduled_times(&trains[&1], travel_date());
+        assert_eq!(arrival - departure, Duration::hours(7));
+
+        record_disruption(&mut trains, 1, travel_date(), service(Disruption::Delayed(45))).unwrap();
+        let (departure, _) = expected_times(&trains[&1], travel_date()).unwrap();
+        assert_eq!(departure, travel_date().and_hms_opt(22, 45, 0).unwrap());
+
+        record_disruption(&mut trains, 1, travel_date(), service(Disruption::Cancelled)).unwrap();
+        assert!(is_cancelled(&trains[&1], travel_date()));
+        assert!(expected_times(&trains[&1], travel_date()).is_none());
+        assert!(!is_cancelled(&trains[&1], travel_date().succ_opt().unwrap()));
+    }
+
+    #[test]
+    fn test_delay_impacts_connections_and_overlaps() {
+        let (bookings, mut trains) = setup();
+
+        record_disruption(&mut trains, 1, travel_date(), service(Disruption::Delayed(30))).unwrap();
+        let impacts = impacted_bookings(&bookings, &trains, 1, travel_date());
+        assert_eq!(impacts.len(), 1);
+        assert_eq!(impacts[0].reason, ImpactReason::Delayed(30));
+
+        record_disruption(&mut trains, 1, travel_date(), service(Disruption::Delayed(200))).unwrap();
+        let reasons: Vec<(String, ImpactReason)> = impacted_bookings(&bookings, &trains, 1, travel_date())
+            .into_iter()
+            .map(|impact| (impact.booking_id, impact.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                ("P1_1_20240610".to_string(), ImpactReason::Delayed(200)),
+                ("P1_2_20240610".to_string(), ImpactReason::MissedConnection(1)),
+                ("P1_3_20240610".to_string(), ImpactReason::Overlap(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_editing_a_disruption_does_not_repeat_history_or_compensation() {
+        let (mut bookings, mut trains) = setup();
+        bookings.get_mut("P1_1_20240610").unwrap().fare = 2000;
+        let mut ledger = Ledger::new(Box::new(MockProcessor::new()));
+        let mut history = BookingHistory::new();
+        let mut edit = |trains: &mut TrainList, minutes: u32| {
+            record_disruption(trains, 1, travel_date(), service(Disruption::Delayed(minutes))).unwrap();
+            record_impacts(&mut history, &bookings, trains, 1, travel_date());
+            compensate(
+                &mut ledger,
+                &bookings,
+                &PassengerList::new(),
+                trains,
+                &mut history,
+                1,
+                travel_date(),
+            );
+            (history.len(), ledger.vouchers.vouchers.len())
+        };
+
+        let first = edit(&mut trains, 200);
+        assert_eq!(first, (4, 1));
+        assert_eq!(edit(&mut trains, 200), first);
+        assert_eq!(edit(&mut trains, 200), first);
+    }
+
+    #[test]
+    fn test_cancellation_impacts_connections() {
+        let (bookings, mut trains) = setup();
+
+        record_disruption(&mut trains, 1, travel_date(), service(Disruption::Cancelled)).unwrap();
+        let reasons: Vec<ImpactReason> = impacted_bookings(&bookings, &trains, 1, travel_date())
+            .into_iter()
+            .map(|impact| impact.reason)
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![ImpactReason::Cancelled, ImpactReason::MissedConnection(1)]
+        );
+        assert!(impacted_bookings(&bookings, &trains, 2, travel_date()).is_empty());
+    }
+
+    #[test]
+    fn test_reaccommodate_cancelled_train() {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+
+        trains.insert(1, train(1, "Toronto", "Hamilton", 9, 10));
+        trains.insert(4, train(4, "Toronto", "Hamilton", 11, 12));
+        trains.insert(5, train(5, "Toronto", "Hamilton", 13, 14));
+        trains.insert(6, train(6, "Toronto", "Ottawa", 9, 10));
+        trains.insert(7, train(7, "Hamilton", "Toronto", 13, 15));
+        trains.insert(8, train(8, "Toronto", "Hamilton", 6, 7));
+        trains.get_mut(&1).unwrap().fare = 2000;
+        trains.get_mut(&5).unwrap().fare = 3000;
+        trains.get_mut(&4).unwrap().capacity = 1;
+        for id in ["P1", "P2", "P3"] {
+            passengers.insert(
+                id.to_string(),
+                Passenger::new(
+                    id.to_string(),
+                    id.to_string(),
+                    approximate_date_of_birth(30, travel_date()),
+                ),
+            );
+            add_booking(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &RulePipeline::default(),
+                id.to_string(),
+                1,
+                travel_date(),
+                Supervision::Alone,
+            )
+            .unwrap();
+        }
+        add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "P3".to_string(),
+            7,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
+
+        assert!(reaccommodate(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            1,
+            travel_date(),
+            now()
+        )
+        .is_err());
+        record_disruption(&mut trains, 1, travel_date(), service(Disruption::Cancelled)).unwrap();
+        // Train 8 leaves before the cancelled train, and by noon train 4 has gone too
+        assert_eq!(alternative_trains(&trains, 1, travel_date(), now()), vec![4, 5]);
+        let noon = travel_date().and_hms_opt(12, 0, 0).unwrap();
+        assert_eq!(alternative_trains(&trains, 1, travel_date(), noon), vec![5]);
+
+        let result = reaccommodate(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            1,
+            travel_date(),
+            now(),
+        )
+        .unwrap();
+        assert_eq!(
+            result.moved,
+            vec![("P1_1_20240610".to_string(), 4), ("P2_1_20240610".to_string(), 5)]
+        );
+        assert_eq!(result.unplaced.len(), 1);
+        assert_eq!(result.unplaced[0].0, "P3_1_20240610");
+
+        assert_eq!(bookings["P2_1_20240610"].train_line, 5);
+        assert_eq!(bookings["P2_1_20240610"].fare, 2000);
+        assert!(trains[&5].passengers.contains("P2"));
+        assert!(!trains[&1].passengers.contains("P2"));
+        assert_eq!(bookings["P3_1_20240610"].train_line, 1);
+    }
+
+    #[test]
+    fn test_reaccommodate_minor_follows_adult() {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+
+        trains.insert(1, train(1, "Toronto", "Hamilton", 9, 10));
+        trains.insert(4, train(4, "Toronto", "Hamilton", 11, 12));
+        for (id, age) in [("Other", 30), ("Parent", 40), ("Child", 6)] {
+            passengers.insert(
+                id.to_string(),
+                Passenger::new(
+                    id.to_string(),
+                    id.to_string(),
+                    approximate_date_of_birth(age, travel_date()),
+                ),
+            );
+        }
+        // The child takes seat 1 once it is freed, ahead of the parent in seat 2
+        for id in ["Other", "Parent"] {
+            add_booking(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &RulePipeline::default(),
+                id.to_string(),
+                1,
+                travel_date(),
+                Supervision::Alone,
+            )
+            .unwrap();
+        }
+        remove_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            "Other_1_20240610".to_string(),
+        )
+        .unwrap();
+        add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "Child".to_string(),
+            1,
+            travel_date(),
+            Supervision::Adult("Parent_1_20240610".to_string()),
+        )
+        .unwrap();
+        assert_eq!(bookings["Child_1_20240610"].seat, 1);
+
+        record_disruption(&mut trains, 1, travel_date(), service(Disruption::Cancelled)).unwrap();
+        let result = reaccommodate(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            1,
+            travel_date(),
+            now(),
+        )
+        .unwrap();
+        assert_eq!(
+            result.moved,
+            vec![
+                ("Parent_1_20240610".to_string(), 4),
+                ("Child_1_20240610".to_string(), 4)
+            ]
+        );
+        assert!(result.unplaced.is_empty());
+    }
+
+    #[test]
+    fn test_reaccommodate_keeps_groups_together() {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+
+        trains.insert(1, train(1, "Toronto", "Hamilton", 9, 10));
+        trains.insert(4, train(4, "Toronto", "Hamilton", 11, 12));
+        trains.get_mut(&4).unwrap().capacity = 1;
+        for (id, age, supervision) in [
+            ("Parent", 40, Supervision::Alone),
+            ("Child", 6, Supervision::Adult("Parent_1_20240610".to_string())),
+        ] {
+            passengers.insert(
+                id.to_string(),
+                Passenger::new(
+                    id.to_string(),
+                    id.to_string(),
+                    approximate_date_of_birth(age, travel_date()),
+                ),
+            );
+            add_booking(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &RulePipeline::default(),
+                id.to_string(),
+                1,
+                travel_date(),
+                supervision,
+            )
+            .unwrap();
+        }
+        record_disruption(&mut trains, 1, travel_date(), service(Disruption::Cancelled)).unwrap();
+
+        // Train 4 has room for the parent only, so neither of them moves
+        let result = reaccommodate(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            1,
+            travel_date(),
+            now(),
+        )
+        .unwrap();
+        assert!(result.moved.is_empty());
+        assert_eq!(result.unplaced.len(), 2);
+        assert_eq!(bookings["Parent_1_20240610"].train_line, 1);
+        assert_eq!(bookings["Parent_1_20240610"].seat, 1);
+        assert!(trains[&4].passengers.is_empty());
+
+        // A later train with room for both takes the whole group
+        trains.insert(5, train(5, "Toronto", "Hamilton", 13, 14));
+        let result = reaccommodate(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            1,
+            travel_date(),
+            now(),
+        )
+        .unwrap();
+        assert_eq!(
+            result.moved,
+            vec![
+                ("Parent_1_20240610".to_string(), 5),
+                ("Child_1_20240610".to_string(), 5)
+            ]
+        );
+        assert!(result.unplaced.is_empty());
+    }
+}
@@ -0,0 +1,477 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use std::collections::{HashMap, HashSet};
+
+use crate::bookings::BookingList;
+use crate::helper::{format_money, parse_money};
+use crate::trains::Train;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoadBand {
+    pub min_load: u32,     // Load factor in percent at which the band starts
+    pub fare_percent: u32, // Percentage of the base fare charged within the band
+}
+
+impl std::fmt::Display for LoadBand {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(f, "{}%+ at {}%", self.min_load, self.fare_percent)
+    }
+}
+
+/// A booking class with its own fare. Limits are nested: a bucket's limit caps the seats sold in it
+/// and every cheaper bucket combined, so cheap seats can never eat into the allocation above them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FareBucket {
+    pub code: String,
+    pub fare: u32,                     // Fare in cents
+    pub limit: u32,                    // Nested seat limit on each travel date
+    pub sold: HashMap<NaiveDate, u32>, // Seats sold in this bucket by travel date
+}
+
+impl FareBucket {
+    pub fn new(
+        code: String,
+        fare: u32,
+        limit: u32,
+    ) -> Self {
+        Self {
+            code,
+            fare,
+            limit,
+            sold: HashMap::new(),
+        }
+    }
+
+    pub fn sold_on(
+        &self,
+        travel_date: NaiveDate,
+    ) -> u32 {
+        self.sold.get(&travel_date).copied().unwrap_or(0)
+    }
+
+    pub fn total_sold(&self) -> u32 {
+        self.sold.values().sum()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PricingMode {
+    Fixed,
+    LoadBased(Vec<LoadBand>), // Sorted by min_load, first band starts at 0
+}
+
+/// The band a fare was priced in, kept on the booking for auditing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AppliedLoadBand {
+    pub load_factor: u32, // Load factor in percent on the travel date when the fare was quoted
+    pub band: LoadBand,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FareQuote {
+    pub fare: u32,                   // Fare in cents
+    pub fare_bucket: Option<String>, // Code of the bucket the seat is sold from
+    pub load_band: Option<AppliedLoadBand>,
+}
+
+pub const DEFAULT_LOAD_BANDS: [LoadBand; 3] = [
+    LoadBand {
+        min_load: 0,
+        fare_percent: 100,
+    },
+    LoadBand {
+        min_load: 50,
+        fare_percent: 125,
+    },
+    LoadBand {
+        min_load: 80,
+        fare_percent: 150,
+    },
+];
+
+pub fn load_factor(
+    bookings: &BookingList,
+    train: &Train,
+    travel_date: NaiveDate,
+    excluding: Option<&str>,
+) -> u32 {
+    if train.capacity == 0 {
+        return 100;
+    }
+    let booked = bookings
+        .values()
+        .filter(|booking| booking.train_line == train.line && booking.travel_date == travel_date)
+        .filter(|booking| Some(booking.id.as_str()) != excluding)
+        .count() as u32;
+    booked * 100 / train.capacity
+}
+
+/// Returns the index of the cheapest bucket that can still sell a seat on the date without breaching
+/// the nested limit of any bucket above it. Buckets must be sorted from cheapest to most expensive.
+pub fn open_bucket(
+    buckets: &[FareBucket],
+    travel_date: NaiveDate,
+) -> Option<usize> {
+    (0..buckets.len()).find(|&index| {
+        let mut sold = buckets[..index]
+            .iter()
+            .map(|bucket| bucket.sold_on(travel_date))
+            .sum::<u32>();
+        buckets[index..].iter().all(|bucket| {
+            sold += bucket.sold_on(travel_date);
+            sold < bucket.limit
+        })
+    })
+}
+
+pub fn quote_fare(
+    bookings: &BookingList,
+    train: &Train,
+    travel_date: NaiveDate,
+    excluding: Option<&str>,
+) -> Result<FareQuote> {
+    let (base_fare, fare_bucket) = if train.fare_buckets.is_empty() {
+        (train.fare, None)
+    } else {
+        let index = open_bucket(&train.fare_buckets, travel_date).ok_or_else(|| {
+            anyhow!(
+                "All fare buckets on train {} are sold out on {}",
+                train.line,
+                travel_date
+            )
+        })?;
+        let bucket = &train.fare_buckets[index];
+        (bucket.fare, Some(bucket.code.clone()))
+    };
+
+    let load_band = match &train.pricing {
+        PricingMode::Fixed => None,
+        PricingMode::LoadBased(bands) => {
+            let load_factor = load_factor(bookings, train, travel_date, excluding);
+            bands
+                .iter()
+                .rev()
+                .find(|band| band.min_load <= load_factor)
+                .map(|band| AppliedLoadBand {
+                    load_factor,
+                    band: *band,
+                })
+        }
+    };
+
+    Ok(FareQuote {
+        fare: load_band.map_or(base_fare, |applied| base_fare * applied.band.fare_percent / 100),
+        fare_bucket,
+        load_band,
+    })
+}
+
+pub fn reserve_bucket(
+    train: &mut Train,
+    code: &str,
+    travel_date: NaiveDate,
+) {
+    if let Some(bucket) = train.fare_buckets.iter_mut().find(|bucket| bucket.code == code) {
+        *bucket.sold.entry(travel_date).or_insert(0) += 1;
+    }
+}
+
+pub fn release_bucket(
+    train: &mut Train,
+    code: &str,
+    travel_date: NaiveDate,
+) {
+    if let Some(bucket) = train.fare_buckets.iter_mut().find(|bucket| bucket.code == code) {
+        if let Some(sold) = bucket.sold.get_mut(&travel_date) {
+            *sold = sold.saturating_sub(1);
+            if *sold == 0 {
+                bucket.sold.remove(&travel_date);
+            }
+        }
+    }
+}
+
+/// Parses buckets written as `code:fare:limit` triples, e.g. `Q:15.00:20, M:20.00:60, Y:30.00:100`.
+/// Seats already sold are carried over from `current` by bucket code.
+pub fn parse_fare_buckets(
+    input: &str,
+    current: &[FareBucket],
+) -> Result<Vec<FareBucket>> {
+    let mut buckets = input
+        .split(',')
+        .map(str::trim)
+        .filter(|bucket| !bucket.is_empty())
+        .map(|bucket| {
+            let parts: Vec<&str> = bucket.split(':').map(str::trim).collect();
+            if parts.len() != 3 || parts[0].is_empty() {
+                return Err(anyhow!("Invalid bucket {}, expected code:fare:limit", bucket));
+            }
+            let mut bucket = FareBucket::new(
+                parts[0].to_uppercase(),
+                parse_money(parts[1])?,
+                parts[2].parse().context("Invalid bucket limit")?,
+            );
+            if let Some(existing) = current.iter().find(|existing| existing.code == bucket.code) {
+                bucket.sold = existing.sold.clone();
+            }
+            Ok(bucket)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for existing in current.iter().filter(|existing| existing.total_sold() > 0) {
+        if !buckets.iter().any(|bucket| bucket.code == existing.code) {
+            return Err(anyhow!(
+                "Cannot remove bucket {} with {} seats sold",
+                existing.code,
+                existing.total_sold()
+            ));
+        }
+    }
+
+    let mut codes = HashSet::new();
+    if let Some(bucket) = buckets.iter().find(|bucket| !codes.insert(bucket.code.as_str())) {
+        return Err(anyhow!("Duplicate bucket {}", bucket.code));
+    }
+
+    buckets.sort_by_key(|bucket| bucket.fare);
+    for pair in buckets.windows(2) {
+        if pair[0].limit > pair[1].limit {
+            return Err(anyhow!(
+                "Bucket {} cannot allow more seats than the dearer bucket {}",
+                pair[0].code,
+                pair[1].code
+            ));
+        }
+    }
+
+    Ok(buckets)
+}
+
+pub fn format_fare_buckets(buckets: &[FareBucket]) -> String {
+    buckets
+        .iter()
+        .map(|bucket| {
+            format!(
+                "{}:{}:{}",
+                bucket.code,
+                format_money(bucket.fare).trim_start_matches('$'),
+                bucket.limit
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Parses bands written as `min_load:fare_percent` pairs, e.g. `0:100, 50:125, 80:150`.
+pub fn parse_load_bands(input: &str) -> Result<Vec<LoadBand>> {
+    let mut bands = input
+        .split(',')
+        .map(str::trim)
+        .filter(|band| !band.is_empty())
+        .map(|band| {
+            let (min_load, fare_percent) = band
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Invalid band {}, expected load:percent", band))?;
+            Ok(LoadBand {
+                min_load: min_load.trim().parse().context("Invalid band load factor")?,
+                fare_percent: fare_percent.trim().parse().context("Invalid band fare percentage")?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    bands.sort_by_key(|band| band.min_load);
+    match bands.first() {
+        Some(band) if band.min_load == 0 => Ok(bands),
+        _ => Err(anyhow!("The first load band must start at 0")),
+    }
+}
+
+pub fn format_load_bands(bands: &[LoadBand]) -> String {
+    bands
+        .iter()
+        .map(|band| format!("{}:{}", band.min_load, band.fare_percent))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::add_booking;
+    use crate::minors::Supervision;
+    use crate::passengers::{approximate_date_of_birth, Passenger, PassengerList};
+    use crate::rules::RulePipeline;
+    use crate::test_support::travel_date;
+    use crate::trains::TrainList;
+    use chrono::NaiveTime;
+
+    fn train() -> Train {
+        let mut train = Train::new(
+            1,
+            "Express".to_string(),
+            10,
+            "Toronto".to_string(),
+            "Hamilton".to_string(),
+            NaiveTime::from_hms_opt(11, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+        );
+        train.fare = 2000;
+        train
+    }
+
+    /// Books passengers P{from}..P{to} on train 1 on the date.
+    fn book(
+        bookings: &mut BookingList,
+        trains: &mut TrainList,
+        ids: std::ops::Range<u32>,
+        travel_date: NaiveDate,
+    ) {
+        let mut passengers = PassengerList::new();
+        for id in ids {
+            let id = format!("P{}", id);
+            passengers.insert(
+                id.clone(),
+                Passenger::new(id.clone(), id.clone(), approximate_date_of_birth(30, travel_date)),
+            );
+            add_booking(
+                bookings,
+                &mut passengers,
+                trains,
+                &RulePipeline::default(),
+                id,
+                1,
+                travel_date,
+                Supervision::Alone,
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_quote_fixed_fare() {
+        let quote = quote_fare(&BookingList::new(), &train(), travel_date(), None).unwrap();
+        assert_eq!(quote.fare, 2000);
+        assert_eq!(quote.fare_bucket, None);
+        assert_eq!(quote.load_band, None);
+    }
+
+    #[test]
+    fn test_quote_load_based_fare() {
+        let mut bookings = BookingList::new();
+        let mut trains = TrainList::new();
+        let mut train = train();
+        train.pricing = PricingMode::LoadBased(DEFAULT_LOAD_BANDS.to_vec());
+        trains.insert(1, train);
+
+        assert_eq!(
+            quote_fare(&bookings, &trains[&1], travel_date(), None).unwrap().fare,
+            2000
+        );
+
+        book(&mut bookings, &mut trains, 0..5, travel_date());
+        let quote = quote_fare(&bookings, &trains[&1], travel_date(), None).unwrap();
+        assert_eq!(quote.fare, 2500);
+        assert_eq!(quote.load_band.unwrap().load_factor, 50);
+        assert_eq!(quote.load_band.unwrap().band, DEFAULT_LOAD_BANDS[1]);
+        // A booking being moved does not count towards the load it is quoted at
+        assert_eq!(
+            quote_fare(&bookings, &trains[&1], travel_date(), Some("P0_1_20240610"))
+                .unwrap()
+                .load_band
+                .unwrap()
+                .load_factor,
+            40
+        );
+
+        book(&mut bookings, &mut trains, 5..8, travel_date());
+        assert_eq!(
+            quote_fare(&bookings, &trains[&1], travel_date(), None).unwrap().fare,
+            3000
+        );
+
+        // Bookings on other dates do not load the train
+        let next_day = travel_date().succ_opt().unwrap();
+        let quote = quote_fare(&bookings, &trains[&1], next_day, None).unwrap();
+        assert_eq!(quote.fare, 2000);
+        assert_eq!(quote.load_band.unwrap().load_factor, 0);
+    }
+
+    #[test]
+    fn test_nested_fare_buckets() {
+        let mut train = train();
+        train.fare_buckets = parse_fare_buckets("Y:30.00:10, Q:10.00:2, M:20.00:5", &[]).unwrap();
+
+        let mut sold = Vec::new();
+        for _ in 0..10 {
+            let quote = quote_fare(&BookingList::new(), &train, travel_date(), None).unwrap();
+            let code = quote.fare_bucket.unwrap();
+            reserve_bucket(&mut train, &code, travel_date());
+            sold.push(code);
+        }
+
+        assert_eq!(sold[..2], ["Q", "Q"]);
+        assert_eq!(sold[2..5], ["M", "M", "M"]);
+        assert_eq!(sold[5..], ["Y", "Y", "Y", "Y", "Y"]);
+        assert!(quote_fare(&BookingList::new(), &train, travel_date(), None).is_err());
+
+        // Releasing a cheap seat reopens the cheap bucket
+        release_bucket(&mut train, "Q", travel_date());
+        assert_eq!(
+            quote_fare(&BookingList::new(), &train, travel_date(), None)
+                .unwrap()
+                .fare_bucket
+                .as_deref(),
+            Some("Q")
+        );
+    }
+
+    #[test]
+    fn test_fare_buckets_are_counted_per_travel_date() {
+        let mut train = train();
+        train.fare_buckets = parse_fare_buckets("Q:10:1, Y:30:2", &[]).unwrap();
+        let next_day = travel_date().succ_opt().unwrap();
+
+        reserve_bucket(&mut train, "Q", travel_date());
+        reserve_bucket(&mut train, "Y", travel_date());
+        assert!(quote_fare(&BookingList::new(), &train, travel_date(), None).is_err());
+
+        // Another date still starts in the cheapest bucket
+        let quote = quote_fare(&BookingList::new(), &train, next_day, None).unwrap();
+        assert_eq!(quote.fare_bucket.as_deref(), Some("Q"));
+        reserve_bucket(&mut train, "Q", next_day);
+        let quote = quote_fare(&BookingList::new(), &train, next_day, None).unwrap();
+        assert_eq!(quote.fare_bucket.as_deref(), Some("Y"));
+
+        assert_eq!(train.fare_buckets[0].sold_on(travel_date()), 1);
+        assert_eq!(train.fare_buckets[0].total_sold(), 2);
+    }
+
+    #[test]
+    fn test_parse_fare_buckets() {
+        let mut current = parse_fare_buckets("Q:10:2, Y:30:10", &[]).unwrap();
+        current[0].sold.insert(travel_date(), 2);
+
+        let buckets = parse_fare_buckets("q:12.50:4, Y:30:10", &current).unwrap();
+        assert_eq!(buckets[0].code, "Q");
+        assert_eq!(buckets[0].fare, 1250);
+        assert_eq!(buckets[0].sold_on(travel_date()), 2);
+        assert_eq!(format_fare_buckets(&buckets), "Q:12.50:4, Y:30.00:10");
+
+        assert!(parse_fare_buckets("Y:30:10", &current).is_err());
+        assert!(parse_fare_buckets("Q:10:20, Y:30:10", &[]).is_err());
+        assert!(parse_fare_buckets("Q:10", &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_load_bands() {
+        let bands = parse_load_bands("80:150, 0:100,50:125").unwrap();
+        assert_eq!(bands, DEFAULT_LOAD_BANDS.to_vec());
+        assert_eq!(format_load_bands(&bands), "0:100, 50:125, 80:150");
+
+        assert!(parse_load_bands("50:125").is_err());
+        assert!(parse_load_bands("0-100").is_err());
+    }
+}
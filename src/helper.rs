@@ -1,3 +1,5 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
 use requestty::{prompt_one, Answers, Question};
 use std::num::ParseIntError;
 use std::process::Command;
@@ -32,7 +34,7 @@ pub fn parse_number_answer(
     answers: Answers,
     field: &str,
     delimiter: &str,
-) -> Result<u32, ParseIntError> {
+) -> std::result::Result<u32, ParseIntError> {
     answers
         .get(field)
         .unwrap()
@@ -45,3 +47,63 @@ pub fn parse_number_answer(
         .trim()
         .parse::<u32>()
 }
+
+pub fn format_money(cents: u32) -> String {
+    format!("${}.{:02}", cents / 100, cents % 100)
+}
+
+pub fn parse_money(input: &str) -> Result<u32> {
+    let input = input.trim().trim_start_matches('$');
+    let (whole, fraction) = match input.split_once('.') {
+        Some((whole, fraction)) => (whole, fraction),
+        None => (input, "0"),
+    };
+    if fraction.is_empty() || fraction.len() > 2 {
+        return Err(anyhow!("Invalid amount: {}", input));
+    }
+    let whole: u32 = whole.parse().context("Invalid amount")?;
+    let fraction: u32 = format!("{:0<2}", fraction).parse().context("Invalid amount")?;
+    whole
+        .checked_mul(100)
+        .and_then(|cents| cents.checked_add(fraction))
+        .ok_or_else(|| anyhow!("Invalid amount: {}", input))
+}
+
+pub fn parse_date(input: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(input.trim(), "%Y-%m-%d").context("Invalid date, expected YYYY-MM-DD")
+}
+
+pub fn escape_html(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_money() {
+        assert_eq!(format_money(0), "$0.00");
+        assert_eq!(format_money(1250), "$12.50");
+        assert_eq!(format_money(7), "$0.07");
+    }
+
+    #[test]
+    fn test_parse_money() {
+        assert_eq!(parse_money("12.50").unwrap(), 1250);
+        assert_eq!(parse_money("$3").unwrap(), 300);
+        assert_eq!(parse_money("0.5").unwrap(), 50);
+        assert!(parse_money("1.234").is_err());
+        assert!(parse_money("abc").is_err());
+        assert_eq!(parse_money("42949672.95").unwrap(), u32::MAX);
+        assert_eq!(
+            parse_money("42949672.96").unwrap_err().to_string(),
+            "Invalid amount: 42949672.96"
+        );
+        assert!(parse_money("50000000").is_err());
+    }
+}
@@ -0,0 +1,521 @@
+use chrono::{Datelike, NaiveDate};
+
+use crate::bookings::{Booking, BookingList};
+use crate::corporate::{next_month, CorporateAccount};
+use crate::helper::{escape_html, format_money};
+use crate::passengers::{Passenger, PassengerList};
+use crate::payments::{EntryKind, Ledger, LedgerEntry, PaymentMethod};
+use crate::trains::{Train, TrainList};
+
+/// Sales tax included in every fare, in percent.
+pub const TAX_RATE_PERCENT: u32 = 13;
+
+pub struct Invoice<'a> {
+    pub number: u32,
+    pub issued: NaiveDate,
+    pub booking: &'a Booking,
+    pub passenger: &'a Passenger,
+    pub train: &'a Train,
+    pub payments: Vec<&'a LedgerEntry>,
+}
+
+impl<'a> Invoice<'a> {
+    pub fn new(
+        number: u32,
+        issued: NaiveDate,
+        booking: &'a Booking,
+        passenger: &'a Passenger,
+        train: &'a Train,
+        ledger: &'a Ledger,
+    ) -> Self {
+        Self {
+            number,
+            issued,
+            booking,
+            passenger,
+            train,
+            payments: ledger.entries_for(&booking.id).collect(),
+        }
+    }
+
+    pub fn charged(&self) -> u32 {
+        self.payments
+            .iter()
+            .filter(|entry| entry.kind == EntryKind::Charge)
+            .map(|entry| entry.amount)
+            .sum()
+    }
+
+    pub fn refunded(&self) -> u32 {
+        self.payments
+            .iter()
+            .filter(|entry| entry.kind == EntryKind::Refund)
+            .map(|entry| entry.amount)
+            .sum()
+    }
+
+    /// Tax contained in the amount kept, since fares are tax inclusive.
+    pub fn tax(&self) -> u32 {
+        let kept = self.charged().saturating_sub(self.refunded());
+        kept * TAX_RATE_PERCENT / (100 + TAX_RATE_PERCENT)
+    }
+
+    pub fn status(&self) -> &'static str {
+        let (charged, refunded) = (self.charged(), self.refunded());
+        if charged == 0 {
+            "Unpaid"
+        } else if refunded == 0 {
+            "Paid"
+        } else if refunded < charged {
+            "Partially refunded"
+        } else {
+            "Refunded"
+        }
+    }
+
+    /// Label and amount pairs making up the fare, with discounts as negative amounts.
+    fn fare_lines(&self) -> Vec<(String, i64)> {
+        let mut description = format!("Fare, train {} {}", self.train.line, self.train.name);
+        if let Some(code) = &self.booking.fare_bucket {
+            description.push_str(&format!(", bucket {}", code));
+        }
+        if let Some(applied) = &self.booking.load_band {
+            description.push_str(&format!(", load {}% band {}", applied.load_factor, applied.band));
+        }
+        if let Some(pass_id) = &self.booking.pass {
+            description.push_str(&format!(", covered by pass {}", pass_id));
+        }
+
+        let mut lines = vec![(description, i64::from(self.booking.fare))];
+        if self.booking.discount > 0 {
+            let source = match (&self.booking.promo_code, &self.booking.account) {
+                (Some(code), _) => format!("promo {}", code),
+                (None, Some(account_id)) => format!("corporate rate {}", account_id),
+                (None, None) => "promo -".to_string(),
+            };
+            lines.push((format!("Discount, {}", source), -i64::from(self.booking.discount)));
+        }
+        for ancillary in &self.booking.ancillaries {
+            lines.push((format!("Extra, {}", ancillary.kind), i64::from(ancillary.price)));
+        }
+        let adjustments = i64::from(self.charged()) - i64::from(self.booking.total());
+        if adjustments > 0 {
+            lines.push(("Change fees and fare differences".to_string(), adjustments));
+        }
+        lines
+    }
+
+    fn signed_money(amount: i64) -> String {
+        if amount < 0 {
+            format!("-{}", format_money(amount.unsigned_abs() as u32))
+        } else {
+            format_money(amount as u32)
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "INVOICE {:06}\nIssued: {}\nBooking: {}\n\nPassenger: {} ({})\nTrain: {} {}\nRoute: {} to {}\nTravel date: {}\nDeparture: {}\nArrival: {}\n\n",
+            self.number,
+            self.issued,
+            self.booking.id,
+            self.passenger.name,
+            self.passenger.id,
+            self.train.line,
+            self.train.name,
+            self.train.origin,
+            self.train.destination,
+            self.booking.travel_date,
+            self.train.departure.format("%H:%M"),
+            self.train.arrival.format("%H:%M"),
+        );
+
+        for (label, amount) in self.fare_lines() {
+            text.push_str(&format!("{:<60} {:>10}\n", label, Self::signed_money(amount)));
+        }
+        text.push_str(&format!(
+            "{:<60} {:>10}\n",
+            "Total charged",
+            format_money(self.charged())
+        ));
+        if self.refunded() > 0 {
+            text.push_str(&format!(
+                "{:<60} {:>10}\n",
+                "Refunded",
+                format!("-{}", format_money(self.refunded()))
+            ));
+        }
+        text.push_str(&format!(
+            "{:<60} {:>10}\n\n",
+            format!("Includes tax at {}%", TAX_RATE_PERCENT),
+            format_money(self.tax())
+        ));
+
+        text.push_str("Payments:\n");
+        for entry in &self.payments {
+            text.push_str(&format!(
+                "  {} {:?} {} by {} ({})\n",
+                entry.timestamp.format("%Y-%m-%d %H:%M"),
+                entry.kind,
+                format_money(entry.amount),
+                entry.method,
+                entry.reference
+            ));
+        }
+        text.push_str(&format!("Payment status: {}\n", self.status()));
+        text
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut rows = String::new();
+        for (label, amount) in self.fare_lines() {
+            rows.push_str(&format!(
+                "<tr><td>{}</td><td class=\"amount\">{}</td></tr>\n",
+                escape_html(&label),
+                Self::signed_money(amount)
+            ));
+        }
+        rows.push_str(&format!(
+            "<tr class=\"total\"><td>Total charged</td><td class=\"amount\">{}</td></tr>\n",
+            format_money(self.charged())
+        ));
+        if self.refunded() > 0 {
+            rows.push_str(&format!(
+                "<tr><td>Refunded</td><td class=\"amount\">-{}</td></tr>\n",
+                format_money(self.refunded())
+            ));
+        }
+        rows.push_str(&format!(
+            "<tr><td>Includes tax at {}%</td><td class=\"amount\">{}</td></tr>\n",
+            TAX_RATE_PERCENT,
+            format_money(self.tax())
+        ));
+
+        let mut payments = String::new();
+        for entry in &self.payments {
+            payments.push_str(&format!(
+                "<li>{} {:?} {} by {} ({})</li>\n",
+                entry.timestamp.format("%Y-%m-%d %H:%M"),
+                entry.kind,
+                format_money(entry.amount),
+                entry.method,
+                escape_html(&entry.reference)
+            ));
+        }
+
+        format!(
+            "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Invoice {number:06}</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; }}
+td {{ padding: 4px 12px; }}
+.amount {{ text-align: right; }}
+.total td {{ border-top: 1px solid #000; font-weight: bold; }}
+</style>
+</head>
+<body>
+<h1>Invoice {number:06}</h1>
+<p>Issued: {issued}<br>Booking: {booking}</p>
+<p>Passenger: {name} ({passenger_id})<br>Train: {line} {train}<br>Route: {origin} to {destination}<br>Travel date: {date}<br>Departure: {departure}<br>Arrival: {arrival}</p>
+<table>
+{rows}</table>
+<h2>Payments</h2>
+<ul>
+{payments}</ul>
+<p>Payment status: {status}</p>
+</body>
+</html>
+",
+            number = self.number,
+            issued = self.issued,
+            booking = escape_html(&self.booking.id),
+            name = escape_html(&self.passenger.name),
+            passenger_id = escape_html(&self.passenger.id),
+            line = self.train.line,
+            train = escape_html(&self.train.name),
+            origin = escape_html(&self.train.origin),
+            destination = escape_html(&self.train.destination),
+            date = self.booking.travel_date,
+            departure = self.train.departure.format("%H:%M"),
+            arrival = self.train.arrival.format("%H:%M"),
+            rows = rows,
+            payments = payments,
+            status = self.status(),
+        )
+    }
+}
+
+pub struct AccountInvoiceLine {
+    pub booking_id: String,
+    pub description: String, // Passenger, train and travel date, or a note that the booking was cancelled
+    pub charged: u32,
+    pub refunded: u32,
+}
+
+/// Consolidated invoice of everything billed to a corporate account in a month, one line per
+/// booking. Bookings are billed in the month the charge or refund was made, not the travel month.
+pub struct AccountInvoice<'a> {
+    pub number: u32,
+    pub issued: NaiveDate,
+    pub account: &'a CorporateAccount,
+    pub month: NaiveDate, // First day of the month invoiced
+    pub lines: Vec<AccountInvoiceLine>,
+}
+
+impl<'a> AccountInvoice<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        number: u32,
+        issued: NaiveDate,
+        account: &'a CorporateAccount,
+        month: NaiveDate,
+        bookings: &BookingList,
+        passengers: &PassengerList,
+        trains: &TrainList,
+        ledger: &Ledger,
+    ) -> Self {
+        let month = month.with_day(1).unwrap();
+        let end = next_month(month);
+        let mut lines: Vec<AccountInvoiceLine> = Vec::new();
+        for entry in ledger.entries.iter().filter(|entry| {
+            entry.method == PaymentMethod::Account
+                && entry.reference == account.id
+                && entry.timestamp.date() >= month
+                && entry.timestamp.date() < end
+        }) {
+            let index = match lines.iter().position(|line| line.booking_id == entry.booking_id) {
+                Some(index) => index,
+                None => {
+                    let description = match bookings.get(&entry.booking_id) {
+                        Some(booking) => format!(
+                            "{}, {}, train {} {} on {}",
+                            booking.id,
+                            passengers
+                                .get(&booking.passenger_id)
+                                .map_or(booking.passenger_id.as_str(), |passenger| passenger.name.as_str()),
+                            booking.train_line,
+                            trains.get(&booking.train_line).map_or("", |train| train.name.as_str()),
+                            booking.travel_date
+                        ),
+                        None => format!("{}, cancelled", entry.booking_id),
+                    };
+                    lines.push(AccountInvoiceLine {
+                        booking_id: entry.booking_id.clone(),
+                        description,
+                        charged: 0,
+                        refunded: 0,
+                    });
+                    lines.len() - 1
+                }
+            };
+            match entry.kind {
+                EntryKind::Charge => lines[index].charged += entry.amount,
+                EntryKind::Refund => lines[index].refunded += entry.amount,
+            }
+        }
+
+        Self {
+            number,
+            issued,
+            account,
+            month,
+            lines,
+        }
+    }
+
+    /// Net amount billed in the month, negative when refunds outweigh charges.
+    pub fn total(&self) -> i64 {
+        self.lines
+            .iter()
+            .map(|line| i64::from(line.charged) - i64::from(line.refunded))
+            .sum()
+    }
+
+    pub fn tax(&self) -> i64 {
+        self.total() * i64::from(TAX_RATE_PERCENT) / i64::from(100 + TAX_RATE_PERCENT)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "INVOICE {:06}\nIssued: {}\nAccount: {} ({})\nBilling contact: {}\nPeriod: {}\n\n",
+            self.number,
+            self.issued,
+            self.account.name,
+            self.account.id,
+            self.account.billing_contact,
+            self.month.format("%B %Y"),
+        );
+        if self.lines.is_empty() {
+            text.push_str("Nothing billed to the account this month\n");
+        }
+        for line in &self.lines {
+            text.push_str(&format!(
+                "{:<60} {:>10}\n",
+                line.description,
+                Invoice::signed_money(i64::from(line.charged) - i64::from(line.refunded))
+            ));
+        }
+        text.push_str(&format!(
+            "{:<60} {:>10}\n",
+            "Total due",
+            Invoice::signed_money(self.total())
+        ));
+        text.push_str(&format!(
+            "{:<60} {:>10}\n",
+            format!("Includes tax at {}%", TAX_RATE_PERCENT),
+            Invoice::signed_money(self.tax())
+        ));
+        text
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut rows = String::new();
+        for line in &self.lines {
+            rows.push_str(&format!(
+                "<tr><td>{}</td><td class=\"amount\">{}</td></tr>\n",
+                escape_html(&line.description),
+                Invoice::signed_money(i64::from(line.charged) - i64::from(line.refunded))
+            ));
+        }
+        rows.push_str(&format!(
+            "<tr class=\"total\"><td>Total due</td><td class=\"amount\">{}</td></tr>\n",
+            Invoice::signed_money(self.total())
+        ));
+        rows.push_str(&format!(
+            "<tr><td>Includes tax at {}%</td><td class=\"amount\">{}</td></tr>\n",
+            TAX_RATE_PERCENT,
+            Invoice::signed_money(self.tax())
+        ));
+
+        format!(
+            "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Invoice {number:06}</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; }}
+td {{ padding: 4px 12px; }}
+.amount {{ text-align: right; }}
+.total td {{ border-top: 1px solid #000; font-weight: bold; }}
+</style>
+</head>
+<body>
+<h1>Invoice {number:06}</h1>
+<p>Issued: {issued}<br>Account: {name} ({id})<br>Billing contact: {contact}<br>Period: {period}</p>
+<table>
+{rows}</table>
+</body>
+</html>
+",
+            number = self.number,
+            issued = self.issued,
+            name = escape_html(&self.account.name),
+            id = escape_html(&self.account.id),
+            contact = escape_html(&self.account.billing_contact),
+            period = self.month.format("%B %Y"),
+            rows = rows,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::BookingList;
+    use crate::passengers::{approximate_date_of_birth, PassengerList};
+    use crate::payments::{MockProcessor, PaymentMethod};
+    use crate::test_support::{book, express, travel_date};
+    use crate::trains::TrainList;
+
+    fn setup() -> (BookingList, PassengerList, TrainList, Ledger) {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+        let mut ledger = Ledger::new(Box::new(MockProcessor::new()));
+
+        passengers.insert(
+            "P1".to_string(),
+            Passenger::new(
+                "P1".to_string(),
+                "Ada <Lovelace>".to_string(),
+                approximate_date_of_birth(36, travel_date()),
+            ),
+        );
+        let mut train = express();
+        train.fare = 11300;
+        trains.insert(1, train);
+
+        let booking_id = book(&mut bookings, &mut passengers, &mut trains, "P1", 1).unwrap();
+        ledger
+            .charge(
+                &booking_id,
+                11300,
+                PaymentMethod::Card,
+                travel_date().and_hms_opt(9, 0, 0).unwrap(),
+            )
+            .unwrap();
+
+        (bookings, passengers, trains, ledger)
+    }
+
+    #[test]
+    fn test_invoice_totals_and_status() {
+        let (bookings, passengers, trains, mut ledger) = setup();
+        let issued = NaiveDate::from_ymd_opt(2024, 6, 10).unwrap();
+
+        let invoice = Invoice::new(
+            1,
+            issued,
+            &bookings["P1_1_20240610"],
+            &passengers["P1"],
+            &trains[&1],
+            &ledger,
+        );
+        assert_eq!(invoice.charged(), 11300);
+        assert_eq!(invoice.tax(), 1300);
+        assert_eq!(invoice.status(), "Paid");
+
+        ledger
+            .refund("P1_1_20240610", 5650, issued.and_hms_opt(10, 0, 0).unwrap())
+            .unwrap();
+        let invoice = Invoice::new(
+            1,
+            issued,
+            &bookings["P1_1_20240610"],
+            &passengers["P1"],
+            &trains[&1],
+            &ledger,
+        );
+        assert_eq!(invoice.tax(), 650);
+        assert_eq!(invoice.status(), "Partially refunded");
+    }
+
+    #[test]
+    fn test_invoice_documents() {
+        let (bookings, passengers, trains, ledger) = setup();
+        let issued = NaiveDate::from_ymd_opt(2024, 6, 10).unwrap();
+        let invoice = Invoice::new(
+            7,
+            issued,
+            &bookings["P1_1_20240610"],
+            &passengers["P1"],
+            &trains[&1],
+            &ledger,
+        );
+
+        let text = invoice.to_text();
+        assert!(text.starts_with("INVOICE 000007"));
+        assert!(text.contains("Route: Toronto to Hamilton"));
+        assert!(text.contains("Payment status: Paid"));
+
+        let html = invoice.to_html();
+        assert!(html.contains("<h1>Invoice 000007</h1>"));
+        assert!(html.contains("Ada &lt;Lovelace&gt;"));
+    }
+}
@@ -0,0 +1,670 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{Months, NaiveDateTime};
+use requestty::{self, Answers, Question};
+use std::collections::HashMap;
+
+use crate::actions::{add_remove_prompt, Action};
+use crate::bookings::{BookingHistory, BookingList, EventKind};
+use crate::helper::{clear_screen, continue_prompt, format_money};
+use crate::passengers::PassengerList;
+use crate::payments::{EntryKind, Ledger, PaymentMethod};
+use crate::trains::TrainList;
+
+/// How points are earned on a completed trip.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccrualBasis {
+    Fare(u32),     // Points per whole dollar paid, not counting amounts paid with points
+    Distance(u32), // Points per kilometre travelled
+}
+
+impl std::fmt::Display for AccrualBasis {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            AccrualBasis::Fare(points) => write!(f, "{} points per dollar paid", points),
+            AccrualBasis::Distance(points) => write!(f, "{} points per km travelled", points),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointsKind {
+    Earned,
+    Redeemed,
+    Refunded, // Points given back when a booking paid with points is refunded
+    Expired,  // Points left unspent past the validity period
+}
+
+impl std::fmt::Display for PointsKind {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            PointsKind::Earned => write!(f, "Earned"),
+            PointsKind::Redeemed => write!(f, "Redeemed"),
+            PointsKind::Refunded => write!(f, "Refunded"),
+            PointsKind::Expired => write!(f, "Expired"),
+        }
+    }
+}
+
+pub struct PointsEntry {
+    pub timestamp: NaiveDateTime,
+    pub booking_id: String,
+    pub kind: PointsKind,
+    pub points: u32,
+    pub description: String,
+}
+
+pub struct LoyaltyAccount {
+    pub number: String,
+    pub passenger_id: String,
+    pub entries: Vec<PointsEntry>, // Oldest first
+}
+
+impl LoyaltyAccount {
+    pub fn balance(&self) -> u32 {
+        self.entries.iter().fold(0, |balance, entry| match entry.kind {
+            PointsKind::Earned | PointsKind::Refunded => balance + entry.points,
+            PointsKind::Redeemed | PointsKind::Expired => balance.saturating_sub(entry.points),
+        })
+    }
+
+    /// Unspent points credited on or before the cutoff, spending the oldest points first.
+    fn unspent_since(
+        &self,
+        cutoff: NaiveDateTime,
+    ) -> u32 {
+        let (credited, spent) = self
+            .entries
+            .iter()
+            .fold((0u32, 0u32), |(credited, spent), entry| match entry.kind {
+                PointsKind::Earned | PointsKind::Refunded if entry.timestamp <= cutoff => {
+                    (credited + entry.points, spent)
+                }
+                PointsKind::Earned | PointsKind::Refunded => (credited, spent),
+                PointsKind::Redeemed | PointsKind::Expired => (credited, spent + entry.points),
+            });
+        credited.saturating_sub(spent)
+    }
+
+    fn record(
+        &mut self,
+        timestamp: NaiveDateTime,
+        booking_id: &str,
+        kind: PointsKind,
+        points: u32,
+        description: String,
+    ) {
+        self.entries.push(PointsEntry {
+            timestamp,
+            booking_id: booking_id.to_string(),
+            kind,
+            points,
+            description,
+        });
+    }
+}
+
+/// Loyalty accounts by passenger ID, with the earning and redemption rates.
+pub struct LoyaltyProgramme {
+    pub accounts: HashMap<String, LoyaltyAccount>,
+    pub basis: AccrualBasis,
+    pub cents_per_point: u32, // What a point is worth when paying with points
+    pub valid_months: u32,    // How long points stay valid once credited, 0 for no expiry
+    next_number: u32,
+    accrued: usize, // Booking history events already looked at for completed trips
+}
+
+impl Default for LoyaltyProgramme {
+    fn default() -> Self {
+        Self {
+            accounts: HashMap::new(),
+            basis: AccrualBasis::Fare(5),
+            cents_per_point: 1,
+            valid_months: 12,
+            next_number: 1,
+            accrued: 0,
+        }
+    }
+}
+
+impl LoyaltyProgramme {
+    pub fn enrol(
+        &mut self,
+        passenger_id: &str,
+    ) -> Result<&LoyaltyAccount> {
+        if self.accounts.contains_key(passenger_id) {
+            return Err(anyhow!("Passenger {} already has a loyalty account", passenger_id));
+        }
+        let account = LoyaltyAccount {
+            number: format!("LY{:06}", self.next_number),
+            passenger_id: passenger_id.to_string(),
+            entries: Vec::new(),
+        };
+        self.next_number += 1;
+        Ok(self.accounts.entry(passenger_id.to_string()).or_insert(account))
+    }
+
+    fn account_mut(
+        &mut self,
+        passenger_id: &str,
+    ) -> Result<&mut LoyaltyAccount> {
+        self.accounts
+            .get_mut(passenger_id)
+            .ok_or_else(|| anyhow!("Passenger {} is not in the loyalty programme", passenger_id))
+    }
+
+    /// Points needed to pay an amount, rounded up to a whole point.
+    pub fn points_for(
+        &self,
+        amount: u32,
+    ) -> u32 {
+        amount.div_ceil(self.cents_per_point.max(1))
+    }
+
+    /// Takes the points for a payment from the passenger's account.
+    pub fn redeem(
+        &mut self,
+        passenger_id: &str,
+        booking_id: &str,
+        amount: u32,
+        timestamp: NaiveDateTime,
+    ) -> Result<u32> {
+        let points = self.points_for(amount);
+        let account = self.account_mut(passenger_id)?;
+        if account.balance() < points {
+            return Err(anyhow!(
+                "Not enough points, {} needed and {} available",
+                points,
+                account.balance()
+            ));
+        }
+        account.record(
+            timestamp,
+            booking_id,
+            PointsKind::Redeemed,
+            points,
+            format!("Paid {}", format_money(amount)),
+        );
+        Ok(points)
+    }
+
+    /// Gives back the points for a refunded payment.
+    pub fn refund(
+        &mut self,
+        passenger_id: &str,
+        booking_id: &str,
+        amount: u32,
+        timestamp: NaiveDateTime,
+    ) -> Result<u32> {
+        let points = self.points_for(amount);
+        self.account_mut(passenger_id)?.record(
+            timestamp,
+            booking_id,
+            PointsKind::Refunded,
+            points,
+            format!("Refund of {}", format_money(amount)),
+        );
+        Ok(points)
+    }
+
+    /// Expires points credited more than the validity period ago that have not been spent.
+    pub fn expire(
+        &mut self,
+        now: NaiveDateTime,
+    ) {
+        if self.valid_months == 0 {
+            return;
+        }
+        let Some(cutoff) = now.checked_sub_months(Months::new(self.valid_months)) else {
+            return;
+        };
+        for account in self.accounts.values_mut() {
+            let points = account.unspent_since(cutoff);
+            if points > 0 {
+                account.record(
+                    now,
+                    "",
+                    PointsKind::Expired,
+                    points,
+                    format!("Unspent for {} months", self.valid_months),
+                );
+            }
+        }
+    }
+
+    /// Awards points for trips completed since the last call, except trips paid with points.
+    pub fn accrue(
+        &mut self,
+        history: &BookingHistory,
+        bookings: &BookingList,
+        trains: &TrainList,
+        ledger: &Ledger,
+    ) {
+        for event in &history[self.accrued.min(history.len())..] {
+            if event.kind != EventKind::Completed || !self.accounts.contains_key(&event.passenger_id) {
+                continue;
+            }
+            let Some(train) = bookings
+                .get(&event.booking_id)
+                .and_then(|booking| trains.get(&booking.train_line))
+            else {
+                continue;
+            };
+            let paid: i64 = ledger
+                .entries_for(&event.booking_id)
+                .filter(|entry| entry.method != PaymentMethod::Points)
+                .map(|entry| match entry.kind {
+                    EntryKind::Charge => i64::from(entry.amount),
+                    EntryKind::Refund => -i64::from(entry.amount),
+                })
+                .sum();
+            if paid <= 0 {
+                continue;
+            }
+            let (points, description) = match self.basis {
+                AccrualBasis::Fare(rate) => (
+                    paid as u32 / 100 * rate,
+                    format!("Train {}, {} paid", train.line, format_money(paid as u32)),
+                ),
+                AccrualBasis::Distance(rate) => (
+                    train.distance_km * rate,
+                    format!("Train {}, {} km", train.line, train.distance_km),
+                ),
+            };
+            if points > 0 {
+                let account = self.accounts.get_mut(&event.passenger_id).unwrap();
+                account.record(
+                    event.timestamp,
+                    &event.booking_id,
+                    PointsKind::Earned,
+                    points,
+                    description,
+                );
+            }
+        }
+        self.accrued = history.len();
+    }
+}
+
+pub fn print_statement(
+    account: &LoyaltyAccount,
+    passengers: &PassengerList,
+) {
+    let name = passengers
+        .get(&account.passenger_id)
+        .map_or("Unknown passenger", |passenger| passenger.name.as_str());
+    println!(
+        "Loyalty account {} for {} ({})",
+        account.number, name, account.passenger_id
+    );
+    let mut balance: u32 = 0;
+    for entry in &account.entries {
+        balance = match entry.kind {
+            PointsKind::Earned | PointsKind::Refunded => balance + entry.points,
+            PointsKind::Redeemed | PointsKind::Expired => balance.saturating_sub(entry.points),
+        };
+        let booking = if entry.booking_id.is_empty() {
+            String::new()
+        } else {
+            format!("booking {} - ", entry.booking_id)
+        };
+        println!(
+            "{} {:<8} {}{:>7} {}{} (balance {})",
+            entry.timestamp.format("%Y-%m-%d %H:%M"),
+            entry.kind,
+            if matches!(entry.kind, PointsKind::Redeemed | PointsKind::Expired) {
+                "-"
+            } else {
+                "+"
+            },
+            entry.points,
+            booking,
+            entry.description,
+            balance
+        );
+    }
+    println!("Balance: {} points", account.balance());
+}
+
+fn select_account(
+    programme: &LoyaltyProgramme,
+    passengers: &PassengerList,
+    message: &str,
+) -> Result<String> {
+    let mut accounts: Vec<&LoyaltyAccount> = programme.accounts.values().collect();
+    accounts.sort_by(|a, b| a.number.cmp(&b.number));
+    let choices: Vec<String> = accounts
+        .iter()
+        .map(|account| {
+            format!(
+                "{}, {}, {}",
+                account.passenger_id,
+                account.number,
+                passengers
+                    .get(&account.passenger_id)
+                    .map_or("Unknown passenger", |passenger| passenger.name.as_str())
+            )
+        })
+        .collect();
+    let selection = requestty::prompt_one(Question::select("account").message(message).choices(choices).build())?;
+    Ok(selection
+        .as_list_item()
+        .unwrap()
+        .text
+        .split(',')
+        .next()
+        .unwrap()
+        .trim()
+        .to_string())
+}
+
+fn enrol_passenger(
+    programme: &mut LoyaltyProgramme,
+    passengers: &PassengerList,
+) -> Result<()> {
+    let mut choices: Vec<String> = passengers
+        .values()
+        .filter(|passenger| !programme.accounts.contains_key(&passenger.id))
+        .map(|passenger| format!("{}, {}", passenger.id, passenger.name))
+        .collect();
+    if choices.is_empty() {
+        return Err(anyhow!("Every passenger already has a loyalty account"));
+    }
+    choices.sort();
+    let selection = requestty::prompt_one(
+        Question::select("passenger")
+            .message("Select passenger to enrol")
+            .choices(choices)
+            .build(),
+    )?;
+    let passenger_id = selection
+        .as_list_item()
+        .unwrap()
+        .text
+        .split(',')
+        .next()
+        .unwrap()
+        .trim()
+        .to_string();
+    let account = programme.enrol(&passenger_id)?;
+    println!("Enrolled {} with account {}", passenger_id, account.number);
+    Ok(())
+}
+
+fn edit_programme(programme: &mut LoyaltyProgramme) -> Result<()> {
+    let (basis, rate) = match programme.basis {
+        AccrualBasis::Fare(rate) => (0, rate),
+        AccrualBasis::Distance(rate) => (1, rate),
+    };
+    let answers = requestty::prompt(vec![
+        Question::select("basis")
+            .message("Earn points on")
+            .choice("Fare paid")
+            .choice("Distance travelled")
+            .default(basis)
+            .build(),
+        Question::input("rate")
+            .message("Enter the points earned per dollar or per km")
+            .default(rate.to_string())
+            .build(),
+        Question::input("cents_per_point")
+            .message("Enter what a point is worth when paying, in cents")
+            .default(programme.cents_per_point.to_string())
+            .validate(|value, _: &Answers| match value.trim().parse::<u32>() {
+                Ok(cents) if cents > 0 => Ok(()),
+                _ => Err("Enter a whole number of cents above zero".to_string()),
+            })
+            .build(),
+        Question::input("valid_months")
+            .message("Enter how many months points stay valid, 0 for no expiry")
+            .default(programme.valid_months.to_string())
+            .validate(|value, _: &Answers| match value.trim().parse::<u32>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err("Enter a whole number of months".to_string()),
+            })
+            .build(),
+    ])?;
+
+    let rate = answers["rate"]
+        .as_string()
+        .unwrap()
+        .trim()
+        .parse()
+        .context("Invalid points rate")?;
+    programme.basis = match answers["basis"].as_list_item().unwrap().index {
+        0 => AccrualBasis::Fare(rate),
+        _ => AccrualBasis::Distance(rate),
+    };
+    programme.cents_per_point = answers["cents_per_point"].as_string().unwrap().trim().parse()?;
+    programme.valid_months = answers["valid_months"].as_string().unwrap().trim().parse()?;
+    Ok(())
+}
+
+pub fn manage_loyalty(
+    programme: &mut LoyaltyProgramme,
+    passengers: &PassengerList,
+) -> Result<()> {
+    loop {
+        clear_screen();
+        match add_remove_prompt()? {
+            Action::List => {
+                println!(
+                    "Earning {}, points worth {} each",
+                    programme.basis,
+                    format_money(programme.cents_per_point)
+                );
+                match programme.valid_months {
+                    0 => println!("Points do not expire"),
+                    months => println!("Points expire {} months after they are credited", months),
+                }
+                if programme.accounts.is_empty() {
+                    println!("No loyalty accounts found");
+                } else {
+                    let passenger_id = select_account(programme, passengers, "Select account for a statement")?;
+                    print_statement(&programme.accounts[&passenger_id], passengers);
+                }
+                continue_prompt();
+            }
+            Action::Add => {
+                if let Err(e) = enrol_passenger(programme, passengers) {
+                    println!("Error: {}", e);
+                }
+                continue_prompt();
+            }
+            Action::Edit => {
+                if let Err(e) = edit_programme(programme) {
+                    println!("Error: {}", e);
+                    continue_prompt();
+                }
+            }
+            Action::Remove => {
+                if programme.accounts.is_empty() {
+                    println!("No loyalty accounts to close");
+                } else {
+                    let passenger_id = select_account(programme, passengers, "Select account to close")?;
+                    let account = programme.accounts.remove(&passenger_id).unwrap();
+                    println!(
+                        "Closed account {}, {} points forfeited",
+                        account.number,
+                        account.balance()
+                    );
+                }
+                continue_prompt();
+            }
+            Action::Back => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::record_event;
+    use crate::payments::MockProcessor;
+    use crate::test_support::{adult, book, express, travel_date};
+
+    fn now() -> NaiveDateTime {
+        travel_date().and_hms_opt(9, 0, 0).unwrap()
+    }
+
+    fn setup() -> (BookingList, PassengerList, TrainList, Ledger) {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+
+        let mut train = express();
+        train.fare = 2550;
+        train.distance_km = 68;
+        trains.insert(1, train);
+        for id in ["P1", "P2"] {
+            passengers.insert(id.to_string(), adult(id, &format!("Passenger {}", id)));
+            book(&mut bookings, &mut passengers, &mut trains, id, 1).unwrap();
+        }
+
+        (
+            bookings,
+            passengers,
+            trains,
+            Ledger::new(Box::new(MockProcessor::new())),
+        )
+    }
+
+    fn complete(
+        history: &mut BookingHistory,
+        bookings: &BookingList,
+        booking_id: &str,
+    ) {
+        record_event(
+            history,
+            &bookings[booking_id],
+            now(),
+            EventKind::Completed,
+            "Completed".to_string(),
+        );
+    }
+
+    #[test]
+    fn test_points_accrue_on_completed_trips() {
+        let (bookings, _, trains, mut ledger) = setup();
+        let mut programme = LoyaltyProgramme::default();
+        let mut history = BookingHistory::new();
+        assert_eq!(programme.enrol("P1").unwrap().number, "LY000001");
+        assert!(programme.enrol("P1").is_err());
+
+        ledger
+            .charge("P1_1_20240610", 2550, PaymentMethod::Card, now())
+            .unwrap();
+        ledger
+            .charge("P2_1_20240610", 2550, PaymentMethod::Card, now())
+            .unwrap();
+        programme.accrue(&history, &bookings, &trains, &ledger);
+        assert_eq!(programme.accounts["P1"].balance(), 0);
+
+        // P2 is not enrolled and earns nothing
+        complete(&mut history, &bookings, "P1_1_20240610");
+        complete(&mut history, &bookings, "P2_1_20240610");
+        programme.accrue(&history, &bookings, &trains, &ledger);
+        assert_eq!(programme.accounts["P1"].balance(), 25 * 5);
+        assert_eq!(programme.accounts["P1"].entries[0].description, "Train 1, $25.50 paid");
+
+        // Events are only counted once
+        programme.accrue(&history, &bookings, &trains, &ledger);
+        assert_eq!(programme.accounts["P1"].balance(), 125);
+
+        programme.basis = AccrualBasis::Distance(2);
+        complete(&mut history, &bookings, "P1_1_20240610");
+        programme.accrue(&history, &bookings, &trains, &ledger);
+        assert_eq!(programme.accounts["P1"].balance(), 125 + 136);
+    }
+
+    #[test]
+    fn test_pay_with_points() {
+        let (bookings, _, trains, mut ledger) = setup();
+        let mut programme = LoyaltyProgramme::default();
+        let mut history = BookingHistory::new();
+
+        assert_eq!(
+            programme
+                .redeem("P1", "P1_1_20240610", 2550, now())
+                .unwrap_err()
+                .to_string(),
+            "Passenger P1 is not in the loyalty programme"
+        );
+        programme.enrol("P1").unwrap();
+        programme.accounts.get_mut("P1").unwrap().record(
+            now(),
+            "P0_1_20240610",
+            PointsKind::Earned,
+            3000,
+            "Welcome".to_string(),
+        );
+
+        programme.cents_per_point = 2;
+        assert_eq!(programme.redeem("P1", "P1_1_20240610", 2551, now()).unwrap(), 1276);
+        assert_eq!(programme.accounts["P1"].balance(), 3000 - 1276);
+        assert_eq!(
+            programme
+                .redeem("P1", "P1_1_20240610", 4000, now())
+                .unwrap_err()
+                .to_string(),
+            "Not enough points, 2000 needed and 1724 available"
+        );
+        programme.refund("P1", "P1_1_20240610", 2551, now()).unwrap();
+        assert_eq!(programme.accounts["P1"].balance(), 3000);
+
+        // A trip paid with points does not earn more points
+        ledger
+            .charge("P1_1_20240610", 2550, PaymentMethod::Points, now())
+            .unwrap();
+        complete(&mut history, &bookings, "P1_1_20240610");
+        programme.accrue(&history, &bookings, &trains, &ledger);
+        assert_eq!(programme.accounts["P1"].balance(), 3000);
+    }
+
+    #[test]
+    fn test_points_expire_oldest_first() {
+        let mut programme = LoyaltyProgramme::default();
+        programme.enrol("P1").unwrap();
+        let account = programme.accounts.get_mut("P1").unwrap();
+        let last_year = now().checked_sub_months(Months::new(12)).unwrap();
+        account.record(
+            last_year,
+            "P1_1_20230610",
+            PointsKind::Earned,
+            500,
+            "Old trip".to_string(),
+        );
+        account.record(now(), "P1_1_20240610", PointsKind::Earned, 200, "New trip".to_string());
+
+        // Spending comes out of the oldest points, so only the rest of them lapse
+        programme.redeem("P1", "P1_2_20240610", 300, now()).unwrap();
+        programme.expire(now() - chrono::Duration::minutes(1));
+        assert_eq!(programme.accounts["P1"].balance(), 400);
+        programme.expire(now());
+        assert_eq!(programme.accounts["P1"].balance(), 200);
+        assert_eq!(programme.accounts["P1"].entries[3].kind, PointsKind::Expired);
+        assert_eq!(programme.accounts["P1"].entries[3].points, 200);
+
+        // Expired points cannot be spent, and expiring again takes nothing more
+        assert_eq!(
+            programme
+                .redeem("P1", "P1_2_20240610", 201, now())
+                .unwrap_err()
+                .to_string(),
+            "Not enough points, 201 needed and 200 available"
+        );
+        programme.expire(now());
+        assert_eq!(programme.accounts["P1"].entries.len(), 4);
+
+        programme.valid_months = 0;
+        programme.expire(now().checked_add_months(Months::new(24)).unwrap());
+        assert_eq!(programme.accounts["P1"].balance(), 200);
+    }
+}
@@ -1,26 +1,92 @@
 use anyhow::Result;
+use chrono::Local;
 use std::collections::HashMap;
+use std::env;
+use std::path::Path;
 
 pub mod actions;
+pub mod ancillaries;
+pub mod assistance;
+pub mod boarding;
 pub mod bookings;
+pub mod corporate;
+pub mod disruptions;
+pub mod fares;
 pub mod helper;
+pub mod invoices;
+pub mod loyalty;
+pub mod manifest;
 pub mod menu;
+pub mod minors;
+pub mod notifications;
 pub mod passengers;
+pub mod passes;
+pub mod payments;
+pub mod policies;
+pub mod promotions;
+pub mod rules;
+pub mod scheduler;
+pub mod signing;
+#[cfg(test)]
+mod test_support;
+pub mod tickets;
 pub mod trains;
+pub mod vouchers;
+use boarding::manage_boarding;
 use bookings::manage_bookings;
-use helper::clear_screen;
+use corporate::manage_corporate_accounts;
+use disruptions::manage_disruptions;
+use helper::{clear_screen, continue_prompt};
+use loyalty::{manage_loyalty, LoyaltyProgramme};
 use menu::{main_menu_prompt, Menu};
+use notifications::{notify, Notifier};
 use passengers::manage_passengers;
+use passes::manage_passes;
+use payments::{Ledger, MockProcessor};
+use policies::{manage_fee_policy, FeePolicy};
+use promotions::manage_promotions;
+use rules::{manage_booking_rules, RulePipeline};
+use scheduler::{Scheduler, SystemClock, SCHEDULER_PATH};
+use signing::verify_command;
 use trains::manage_trains;
+use vouchers::manage_vouchers;
 
 fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("verify") {
+        return verify_command(&args[2..]);
+    }
+
     let mut trains: trains::TrainList = HashMap::new();
     let mut passengers: passengers::PassengerList = HashMap::new();
     let mut bookings: bookings::BookingList = HashMap::new();
+    let mut promotions: promotions::PromotionList = HashMap::new();
+    let mut accounts: corporate::AccountList = HashMap::new();
+    let mut ledger = Ledger::new(Box::new(MockProcessor::new()));
+    let mut loyalty = LoyaltyProgramme::default();
+    let mut fee_policy = FeePolicy::default();
+    let mut booking_rules = RulePipeline::default();
+    let mut history: bookings::BookingHistory = Vec::new();
+    let mut notifier = Notifier::from_env()?;
+    let mut scheduler = Scheduler::load(Path::new(SCHEDULER_PATH), Box::new(SystemClock))?;
 
     println!("Welcome to the Train Ticketing system. Please choose an option from the menu below");
 
     loop {
+        if let Err(e) = scheduler.run_due(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &mut promotions,
+            &mut history,
+        ) {
+            println!("Error: {}", e);
+            continue_prompt();
+        }
+        loyalty.accrue(&history, &bookings, &trains, &ledger);
+        loyalty.expire(Local::now().naive_local());
+        notify(&mut notifier, &history, &bookings, &passengers, &trains);
+
         clear_screen();
         match main_menu_prompt()? {
             Menu::Trains => {
@@ -30,7 +96,54 @@ fn main() -> Result<()> {
                 manage_passengers(&mut passengers)?;
             }
             Menu::Bookings => {
-                manage_bookings(&mut bookings, &mut passengers, &mut trains)?;
+                manage_bookings(
+                    &mut bookings,
+                    &mut passengers,
+                    &mut trains,
+                    &booking_rules,
+                    &mut promotions,
+                    &accounts,
+                    &mut ledger,
+                    &mut loyalty,
+                    &fee_policy,
+                    &mut history,
+                    &mut notifier,
+                )?;
+            }
+            Menu::Boarding => {
+                manage_boarding(&mut bookings, &passengers, &trains, &mut history)?;
+            }
+            Menu::Disruptions => {
+                manage_disruptions(
+                    &mut trains,
+                    &mut bookings,
+                    &mut passengers,
+                    &booking_rules,
+                    &mut ledger,
+                    &mut history,
+                    &mut notifier,
+                )?;
+            }
+            Menu::Promotions => {
+                manage_promotions(&mut promotions)?;
+            }
+            Menu::FeePolicy => {
+                manage_fee_policy(&mut fee_policy)?;
+            }
+            Menu::BookingRules => {
+                manage_booking_rules(&mut booking_rules)?;
+            }
+            Menu::Loyalty => {
+                manage_loyalty(&mut loyalty, &passengers)?;
+            }
+            Menu::Passes => {
+                manage_passes(&mut passengers, &trains, &bookings, &mut ledger)?;
+            }
+            Menu::Vouchers => {
+                manage_vouchers(&mut ledger, &passengers)?;
+            }
+            Menu::CorporateAccounts => {
+                manage_corporate_accounts(&mut accounts, &passengers, &bookings, &trains, &mut ledger)?;
             }
             Menu::Exit => {
                 println!("Exiting...");
@@ -0,0 +1,327 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use printpdf::{BuiltinFont, Mm, PdfDocument};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::ancillaries::format_ancillaries;
+use crate::bookings::{BookingList, BookingStatus};
+use crate::passengers::PassengerList;
+use crate::trains::Train;
+
+const PDF_LINES_PER_PAGE: usize = 40;
+const PDF_FONT_SIZE: f32 = 9.0;
+const PDF_LINE_HEIGHT: f32 = 4.5; // Millimetres between lines
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ManifestSort {
+    Name,
+    Seat,
+}
+
+pub struct ManifestEntry {
+    pub seat: u32,
+    pub name: String,
+    pub age: u16,
+    pub passenger_id: String,
+    pub booking_id: String,
+    pub status: BookingStatus,
+    pub extras: String, // Bike spaces, meals and other extras to hand out or check
+    pub special_requirements: String,
+}
+
+/// The passengers booked on a train for one travel date, for the on-board crew.
+pub struct Manifest<'a> {
+    pub train: &'a Train,
+    pub travel_date: NaiveDate,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl<'a> Manifest<'a> {
+    pub fn new(
+        bookings: &BookingList,
+        passengers: &PassengerList,
+        train: &'a Train,
+        travel_date: NaiveDate,
+        sort: ManifestSort,
+    ) -> Self {
+        let mut entries: Vec<ManifestEntry> = bookings
+            .values()
+            .filter(|booking| booking.train_line == train.line && booking.travel_date == travel_date)
+            .map(|booking| {
+                let passenger = passengers.get(&booking.passenger_id);
+                ManifestEntry {
+                    seat: booking.seat,
+                    name: passenger.map_or_else(|| "Unknown passenger".to_string(), |p| p.name.clone()),
+                    age: passenger.map_or(0, |p| p.age_on(travel_date)),
+                    passenger_id: booking.passenger_id.clone(),
+                    booking_id: booking.id.clone(),
+                    status: booking.status,
+                    extras: if booking.ancillaries.is_empty() {
+                        String::new()
+                    } else {
+                        format_ancillaries(&booking.ancillaries)
+                    },
+                    special_requirements: passenger.map(|p| p.special_requirements.clone()).unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        match sort {
+            ManifestSort::Name => entries.sort_by(|a, b| a.name.cmp(&b.name).then(a.seat.cmp(&b.seat))),
+            ManifestSort::Seat => entries.sort_by_key(|entry| entry.seat),
+        }
+        Self {
+            train,
+            travel_date,
+            entries,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        format!(
+            "MANIFEST train {} {}, {} to {}, {} departing {}",
+            self.train.line,
+            self.train.name,
+            self.train.origin,
+            self.train.destination,
+            self.travel_date,
+            self.train.departure.format("%H:%M")
+        )
+    }
+
+    /// Header and passenger rows as fixed width columns.
+    fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{:>4}  {:<24} {:>3}  {:<10} {:<14} {:<8} {:<20} {}",
+            "Seat", "Name", "Age", "Passenger", "Booking", "Status", "Extras", "Special requirements"
+        )];
+        for entry in &self.entries {
+            lines.push(format!(
+                "{:>4}  {:<24} {:>3}  {:<10} {:<14} {:<8} {:<20} {}",
+                entry.seat,
+                entry.name,
+                entry.age,
+                entry.passenger_id,
+                entry.booking_id,
+                entry.status,
+                entry.extras,
+                entry.special_requirements
+            ));
+        }
+        lines
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n{} passengers\n\n", self.title(), self.entries.len());
+        for line in self.lines() {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn to_csv(&self) -> Result<String> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.write_record([
+            "seat",
+            "name",
+            "age",
+            "passenger_id",
+            "booking",
+            "status",
+            "extras",
+            "special_requirements",
+        ])?;
+        for entry in &self.entries {
+            writer.write_record([
+                entry.seat.to_string(),
+                entry.name.clone(),
+                entry.age.to_string(),
+                entry.passenger_id.clone(),
+                entry.booking_id.clone(),
+                entry.status.to_string(),
+                entry.extras.clone(),
+                entry.special_requirements.clone(),
+            ])?;
+        }
+        Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+
+    pub fn write_csv(
+        &self,
+        path: &Path,
+    ) -> Result<()> {
+        fs::write(path, self.to_csv()?).with_context(|| format!("Could not write {}", path.display()))
+    }
+
+    /// Writes the manifest as an A4 landscape PDF, repeating the title and header on every page.
+    pub fn write_pdf(
+        &self,
+        path: &Path,
+    ) -> Result<()> {
+        let (width, height) = (Mm(297.0), Mm(210.0));
+        let (document, first_page, first_layer) = PdfDocument::new(self.title(), width, height, "Manifest");
+        let font = document.add_builtin_font(BuiltinFont::Courier)?;
+
+        let lines = self.lines();
+        let (header, rows) = lines.split_first().unwrap();
+        let pages: Vec<&[String]> = if rows.is_empty() {
+            vec![&[]]
+        } else {
+            rows.chunks(PDF_LINES_PER_PAGE).collect()
+        };
+        for (number, page_rows) in pages.iter().enumerate() {
+            let layer = if number == 0 {
+                document.get_page(first_page).get_layer(first_layer)
+            } else {
+                let (page, layer) = document.add_page(width, height, "Manifest");
+                document.get_page(page).get_layer(layer)
+            };
+
+            let mut y = 195.0;
+            let title = format!("{} (page {} of {})", self.title(), number + 1, pages.len());
+            for line in [title.as_str(), "", header.as_str()]
+                .into_iter()
+                .chain(page_rows.iter().map(String::as_str))
+            {
+                layer.use_text(line, PDF_FONT_SIZE, Mm(10.0), Mm(y), &font);
+                y -= PDF_LINE_HEIGHT;
+            }
+        }
+
+        let file = File::create(path).with_context(|| format!("Could not create {}", path.display()))?;
+        document.save(&mut BufWriter::new(file))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ancillaries::{add_ancillaries, parse_ancillary_offers, AncillaryKind};
+    use crate::bookings::add_booking;
+    use crate::minors::Supervision;
+    use crate::passengers::{approximate_date_of_birth, Passenger};
+    use crate::rules::RulePipeline;
+    use crate::test_support::travel_date;
+    use crate::trains::TrainList;
+    use chrono::NaiveTime;
+
+    fn setup() -> (BookingList, PassengerList, TrainList) {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+
+        for (id, name, age) in [("P1", "Zoe, Smith", 30), ("P2", "Adam Jones", 8)] {
+            passengers.insert(
+                id.to_string(),
+                Passenger::new(
+                    id.to_string(),
+                    name.to_string(),
+                    approximate_date_of_birth(age, travel_date()),
+                ),
+            );
+        }
+        passengers.get_mut("P2").unwrap().special_requirements = "Travelling with guardian".to_string();
+        trains.insert(
+            1,
+            Train::new(
+                1,
+                "Express".to_string(),
+                100,
+                "Toronto".to_string(),
+                "Hamilton".to_string(),
+                NaiveTime::from_hms_opt(11, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            ),
+        );
+        trains.get_mut(&1).unwrap().ancillaries = parse_ancillary_offers("bike:5.00:4, meal:12.50:40").unwrap();
+        for id in ["P1", "P2"] {
+            add_booking(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &RulePipeline::default(),
+                id.to_string(),
+                1,
+                travel_date(),
+                // Adam is 8 and travels with Zoe
+                if id == "P2" {
+                    Supervision::Adult("P1_1_20240610".to_string())
+                } else {
+                    Supervision::Alone
+                },
+            )
+            .unwrap();
+        }
+        add_ancillaries(
+            &mut bookings,
+            &trains,
+            "P1_1_20240610",
+            &[AncillaryKind::Bicycle, AncillaryKind::Meal],
+        )
+        .unwrap();
+
+        (bookings, passengers, trains)
+    }
+
+    #[test]
+    fn test_manifest_sorting() {
+        let (bookings, passengers, trains) = setup();
+
+        let by_seat = Manifest::new(&bookings, &passengers, &trains[&1], travel_date(), ManifestSort::Seat);
+        let seats: Vec<u32> = by_seat.entries.iter().map(|entry| entry.seat).collect();
+        assert_eq!(seats, vec![1, 2]);
+        assert_eq!(by_seat.entries[0].passenger_id, "P1");
+
+        let by_name = Manifest::new(&bookings, &passengers, &trains[&1], travel_date(), ManifestSort::Name);
+        assert_eq!(by_name.entries[0].name, "Adam Jones");
+        assert_eq!(by_name.entries[0].special_requirements, "Travelling with guardian");
+
+        let other_day = travel_date().succ_opt().unwrap();
+        assert!(
+            Manifest::new(&bookings, &passengers, &trains[&1], other_day, ManifestSort::Seat)
+                .entries
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_manifest_csv_and_text() {
+        let (bookings, passengers, trains) = setup();
+        let manifest = Manifest::new(&bookings, &passengers, &trains[&1], travel_date(), ManifestSort::Seat);
+
+        let csv = manifest.to_csv().unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "seat,name,age,passenger_id,booking,status,extras,special_requirements"
+        );
+        assert_eq!(
+            lines[1],
+            "1,\"Zoe, Smith\",30,P1,P1_1_20240610,Booked,\"Bicycle, Meal\","
+        );
+        assert_eq!(
+            lines[2],
+            "2,Adam Jones,8,P2,P2_1_20240610,Booked,,Travelling with guardian"
+        );
+
+        let text = manifest.to_text();
+        assert!(text.starts_with("MANIFEST train 1 Express, Toronto to Hamilton, 2024-06-10 departing 11:00"));
+        assert!(text.contains("2 passengers"));
+    }
+
+    #[test]
+    fn test_manifest_pdf() {
+        let (bookings, passengers, trains) = setup();
+        let manifest = Manifest::new(&bookings, &passengers, &trains[&1], travel_date(), ManifestSort::Name);
+
+        let path = std::env::temp_dir().join(format!("manifest-test-{}.pdf", std::process::id()));
+        manifest.write_pdf(&path).unwrap();
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(contents.starts_with(b"%PDF"));
+    }
+}
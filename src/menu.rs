@@ -5,6 +5,15 @@ pub enum Menu {
     Trains,
     Passengers,
     Bookings,
+    Boarding,
+    Disruptions,
+    Promotions,
+    FeePolicy,
+    BookingRules,
+    Loyalty,
+    Passes,
+    Vouchers,
+    CorporateAccounts,
     Exit,
 }
 
@@ -15,6 +24,15 @@ pub fn main_menu_prompt() -> Result<Menu> {
             .choice("Trains")
             .choice("Passengers")
             .choice("Bookings")
+            .choice("Boarding")
+            .choice("Disruptions")
+            .choice("Promotions")
+            .choice("Fee policy")
+            .choice("Booking rules")
+            .choice("Loyalty")
+            .choice("Passes")
+            .choice("Vouchers")
+            .choice("Corporate accounts")
             .choice("Exit"),
     )?;
 
@@ -22,7 +40,16 @@ pub fn main_menu_prompt() -> Result<Menu> {
         0 => Ok(Menu::Trains),
         1 => Ok(Menu::Passengers),
         2 => Ok(Menu::Bookings),
-        3 => Ok(Menu::Exit),
+        3 => Ok(Menu::Boarding),
+        4 => Ok(Menu::Disruptions),
+        5 => Ok(Menu::Promotions),
+        6 => Ok(Menu::FeePolicy),
+        7 => Ok(Menu::BookingRules),
+        8 => Ok(Menu::Loyalty),
+        9 => Ok(Menu::Passes),
+        10 => Ok(Menu::Vouchers),
+        11 => Ok(Menu::CorporateAccounts),
+        12 => Ok(Menu::Exit),
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,452 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{NaiveDate, NaiveTime};
+use requestty::{self, Question};
+
+use crate::bookings::{BookingList, BookingStatus};
+use crate::passengers::{Passenger, PassengerList};
+use crate::trains::Train;
+
+/// Passengers this age or older count as adults who can accompany a minor.
+pub const ADULT_AGE: u16 = 18;
+
+/// How a passenger is looked after on a trip.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Supervision {
+    Alone,
+    Adult(String), // Booking of the adult sharing the trip
+    Escorted,      // Unaccompanied-minor service requested from the crew
+}
+
+impl std::fmt::Display for Supervision {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            Supervision::Alone => write!(f, "Alone"),
+            Supervision::Adult(booking_id) => write!(f, "With adult on booking {}", booking_id),
+            Supervision::Escorted => write!(f, "Unaccompanied-minor service"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MinorRequirement {
+    Adult,         // Must share the trip with an adult
+    AdultOrEscort, // An adult, or the unaccompanied-minor service
+}
+
+/// Passengers younger than `under_age` on the travel date need the given supervision, optionally
+/// only on trains departing at or after `departing_from`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MinorRule {
+    pub under_age: u16,
+    pub departing_from: Option<NaiveTime>,
+    pub requirement: MinorRequirement,
+}
+
+impl std::fmt::Display for MinorRule {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(f, "under {}", self.under_age)?;
+        if let Some(time) = self.departing_from {
+            write!(f, " from {}", time.format("%H:%M"))?;
+        }
+        match self.requirement {
+            MinorRequirement::Adult => write!(f, " must travel with an adult"),
+            MinorRequirement::AdultOrEscort => {
+                write!(f, " must travel with an adult or the unaccompanied-minor service")
+            }
+        }
+    }
+}
+
+impl MinorRule {
+    fn applies(
+        &self,
+        age: u16,
+        train: &Train,
+    ) -> bool {
+        age < self.under_age && self.departing_from.is_none_or(|time| train.departure >= time)
+    }
+}
+
+pub fn default_minor_rules() -> Vec<MinorRule> {
+    vec![
+        MinorRule {
+            under_age: 8,
+            departing_from: None,
+            requirement: MinorRequirement::Adult,
+        },
+        MinorRule {
+            under_age: 16,
+            departing_from: None,
+            requirement: MinorRequirement::AdultOrEscort,
+        },
+        MinorRule {
+            under_age: ADULT_AGE,
+            departing_from: NaiveTime::from_hms_opt(21, 0, 0),
+            requirement: MinorRequirement::AdultOrEscort,
+        },
+    ]
+}
+
+/// Checks a passenger's supervision against the train's minor rules. An adult companion must be
+/// booked on the same train and date.
+pub fn check_minor_rules(
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    passenger: &Passenger,
+    train: &Train,
+    travel_date: NaiveDate,
+    supervision: &Supervision,
+) -> Result<()> {
+    let age = passenger.age_on(travel_date);
+    for rule in train.minor_rules.iter().filter(|rule| rule.applies(age, train)) {
+        match (rule.requirement, supervision) {
+            (_, Supervision::Adult(booking_id)) => {
+                check_companion(bookings, passengers, passenger, train, travel_date, booking_id)?
+            }
+            (MinorRequirement::AdultOrEscort, Supervision::Escorted) => {}
+            _ => {
+                return Err(anyhow!(
+                    "{} is {} on {}. On train {} passengers {}",
+                    passenger.name,
+                    age,
+                    travel_date,
+                    train.line,
+                    rule
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_companion(
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    passenger: &Passenger,
+    train: &Train,
+    travel_date: NaiveDate,
+    booking_id: &str,
+) -> Result<()> {
+    let booking = bookings
+        .get(booking_id)
+        .ok_or_else(|| anyhow!("Companion booking {} not found", booking_id))?;
+    if booking.passenger_id == passenger.id {
+        return Err(anyhow!("A passenger cannot accompany themselves"));
+    }
+    if booking.train_line != train.line || booking.travel_date != travel_date || booking.status != BookingStatus::Booked
+    {
+        return Err(anyhow!(
+            "Companion booking {} is not on train {} on {}",
+            booking_id,
+            train.line,
+            travel_date
+        ));
+    }
+    let companion = passengers
+        .get(&booking.passenger_id)
+        .ok_or_else(|| anyhow!("Passenger not found"))?;
+    if companion.age_on(travel_date) < ADULT_AGE {
+        return Err(anyhow!(
+            "{} is not an adult on {} and cannot accompany {}",
+            companion.name,
+            travel_date,
+            passenger.name
+        ));
+    }
+    Ok(())
+}
+
+/// Asks how a passenger will be looked after when one of the train's minor rules applies to them.
+pub fn prompt_supervision(
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    passenger: &Passenger,
+    train: &Train,
+    travel_date: NaiveDate,
+) -> Result<Supervision> {
+    let age = passenger.age_on(travel_date);
+    if !train.minor_rules.iter().any(|rule| rule.applies(age, train)) {
+        return Ok(Supervision::Alone);
+    }
+
+    let mut companions: Vec<String> = bookings
+        .values()
+        .filter(|booking| booking.train_line == train.line && booking.travel_date == travel_date)
+        .filter(|booking| booking.passenger_id != passenger.id && booking.status == BookingStatus::Booked)
+        .filter(|booking| {
+            passengers
+                .get(&booking.passenger_id)
+                .is_some_and(|companion| companion.age_on(travel_date) >= ADULT_AGE)
+        })
+        .map(|booking| format!("{}, {}", booking.id, passengers[&booking.passenger_id].name))
+        .collect();
+    companions.sort();
+
+    let selection = requestty::prompt_one(
+        Question::select("supervision")
+            .message(format!(
+                "{} is {} on {}. How will they travel?",
+                passenger.name, age, travel_date
+            ))
+            .choice("Alone")
+            .choice("With an adult booked on this train")
+            .choice("With the unaccompanied-minor service")
+            .build(),
+    )?;
+    match selection.as_list_item().unwrap().index {
+        0 => Ok(Supervision::Alone),
+        1 => {
+            if companions.is_empty() {
+                return Err(anyhow!(
+                    "No adult is booked on train {} on {}, book the adult first",
+                    train.line,
+                    travel_date
+                ));
+            }
+            let companion = requestty::prompt_one(
+                Question::select("companion")
+                    .message("Select the adult's booking")
+                    .choices(companions)
+                    .build(),
+            )?;
+            let text = &companion.as_list_item().unwrap().text;
+            Ok(Supervision::Adult(text.split(',').next().unwrap().trim().to_string()))
+        }
+        2 => Ok(Supervision::Escorted),
+        _ => unreachable!(),
+    }
+}
+
+/// Parses rules written as `age[@HH:MM]:adult|escort`, e.g. `8:adult, 16:escort, 18@21:00:escort`.
+/// `escort` allows either an adult or the unaccompanied-minor service.
+pub fn parse_minor_rules(input: &str) -> Result<Vec<MinorRule>> {
+    let mut rules = input
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| {
+            let (condition, requirement) = rule
+                .rsplit_once(':')
+                .filter(|(condition, _)| !condition.is_empty())
+                .ok_or_else(|| anyhow!("Invalid rule {}, expected age[@HH:MM]:adult|escort", rule))?;
+            let (age, time) = match condition.split_once('@') {
+                Some((age, time)) => (
+                    age,
+                    Some(NaiveTime::parse_from_str(time.trim(), "%H:%M").context("Invalid rule departure time")?),
+                ),
+                None => (condition, None),
+            };
+            let requirement = match requirement.trim().to_lowercase().as_str() {
+                "adult" => MinorRequirement::Adult,
+                "escort" => MinorRequirement::AdultOrEscort,
+                other => return Err(anyhow!("Unknown requirement {}, expected adult or escort", other)),
+            };
+            Ok(MinorRule {
+                under_age: age.trim().parse().context("Invalid rule age")?,
+                departing_from: time,
+                requirement,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    rules.sort_by_key(|rule| (rule.under_age, rule.departing_from));
+    Ok(rules)
+}
+
+pub fn format_minor_rules(rules: &[MinorRule]) -> String {
+    rules
+        .iter()
+        .map(|rule| {
+            format!(
+                "{}{}:{}",
+                rule.under_age,
+                rule.departing_from
+                    .map(|time| format!("@{}", time.format("%H:%M")))
+                    .unwrap_or_default(),
+                match rule.requirement {
+                    MinorRequirement::Adult => "adult",
+                    MinorRequirement::AdultOrEscort => "escort",
+                }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::add_booking;
+    use crate::passengers::approximate_date_of_birth;
+    use crate::rules::RulePipeline;
+    use crate::test_support::travel_date;
+    use crate::trains::TrainList;
+    use chrono::Datelike;
+
+    fn setup() -> (BookingList, PassengerList, TrainList) {
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+        for (id, age) in [("Adult", 40), ("Teen", 14), ("Child", 6)] {
+            passengers.insert(
+                id.to_string(),
+                Passenger::new(
+                    id.to_string(),
+                    id.to_string(),
+                    approximate_date_of_birth(age, travel_date()),
+                ),
+            );
+        }
+        for (line, departure) in [(1, 11), (2, 22)] {
+            trains.insert(
+                line,
+                Train::new(
+                    line,
+                    "Express".to_string(),
+                    100,
+                    "Toronto".to_string(),
+                    "Hamilton".to_string(),
+                    NaiveTime::from_hms_opt(departure, 0, 0).unwrap(),
+                    NaiveTime::from_hms_opt(departure + 1, 0, 0).unwrap(),
+                ),
+            );
+        }
+        (BookingList::new(), passengers, trains)
+    }
+
+    #[test]
+    fn test_minor_rules() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+
+        let error = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "Child".to_string(),
+            1,
+            travel_date(),
+            Supervision::Escorted,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Child is 6 on 2024-06-10. On train 1 passengers under 8 must travel with an adult"
+        );
+        assert!(add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "Teen".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .is_err());
+        let teen = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "Teen".to_string(),
+            1,
+            travel_date(),
+            Supervision::Escorted,
+        )
+        .unwrap();
+
+        // A minor cannot accompany another minor, an adult on the same train can
+        let error = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "Child".to_string(),
+            1,
+            travel_date(),
+            Supervision::Adult(teen),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Teen is not an adult on 2024-06-10 and cannot accompany Child"
+        );
+        let adult = add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "Adult".to_string(),
+            1,
+            travel_date(),
+            Supervision::Alone,
+        )
+        .unwrap();
+        add_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            "Child".to_string(),
+            1,
+            travel_date(),
+            Supervision::Adult(adult.clone()),
+        )
+        .unwrap();
+        assert!(check_minor_rules(
+            &bookings,
+            &passengers,
+            &passengers["Child"],
+            &trains[&2],
+            travel_date(),
+            &Supervision::Adult(adult),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_late_train_rule() {
+        let (bookings, mut passengers, trains) = setup();
+        passengers.get_mut("Teen").unwrap().date_of_birth = approximate_date_of_birth(17, travel_date());
+        let teen = &passengers["Teen"];
+
+        assert!(check_minor_rules(
+            &bookings,
+            &passengers,
+            teen,
+            &trains[&1],
+            travel_date(),
+            &Supervision::Alone
+        )
+        .is_ok());
+        assert!(check_minor_rules(
+            &bookings,
+            &passengers,
+            teen,
+            &trains[&2],
+            travel_date(),
+            &Supervision::Alone
+        )
+        .is_err());
+        // Turning 18 lifts the rule
+        let birthday = travel_date().with_year(2025).unwrap();
+        assert!(check_minor_rules(&bookings, &passengers, teen, &trains[&2], birthday, &Supervision::Alone).is_ok());
+    }
+
+    #[test]
+    fn test_parse_minor_rules() {
+        let rules = parse_minor_rules("16:escort, 8:adult, 18@21:00:Escort").unwrap();
+        assert_eq!(rules, default_minor_rules());
+        assert_eq!(format_minor_rules(&rules), "8:adult, 16:escort, 18@21:00:escort");
+        assert!(parse_minor_rules("").unwrap().is_empty());
+        assert!(parse_minor_rules("8:guardian").is_err());
+        assert!(parse_minor_rules("8@9pm:adult").is_err());
+        assert!(parse_minor_rules("adult").is_err());
+    }
+}
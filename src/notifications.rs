@@ -0,0 +1,514 @@
+use anyhow::{Context, Result};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::{Message, SmtpTransport, Transport};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::bookings::{BookingEvent, BookingHistory, BookingList, EventKind};
+use crate::helper::continue_prompt;
+use crate::passengers::{Passenger, PassengerList};
+use crate::trains::TrainList;
+
+pub const OUTBOX_DIR: &str = "outbox";
+const DEFAULT_SMTP_PORT: u16 = 587;
+const DEFAULT_SMTP_FROM: &str = "tickets@train-rs.local";
+const SMS_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Notification {
+    pub booking_id: String,
+    pub subject: String,
+    pub summary: String, // One line version for SMS
+    pub body: String,
+}
+
+impl Notification {
+    /// Builds the message for a booking history event, or None for events passengers are not
+    /// told about.
+    pub fn from_event(
+        event: &BookingEvent,
+        passenger: &Passenger,
+        bookings: &BookingList,
+        trains: &TrainList,
+    ) -> Option<Self> {
+        let subject = match event.kind {
+            EventKind::Booked => format!("Booking {} confirmed", event.booking_id),
+            EventKind::Changed => format!("Booking {} changed", event.booking_id),
+            EventKind::Cancelled => format!("Booking {} cancelled", event.booking_id),
+            EventKind::Disrupted => format!("Service disruption affecting booking {}", event.booking_id),
+            EventKind::Rebooked => format!("Booking {} moved to another train", event.booking_id),
+            EventKind::Reminder => format!("Departure reminder for booking {}", event.booking_id),
+            EventKind::Boarded | EventKind::NoShow | EventKind::Completed => return None,
+        };
+
+        let mut body = format!("Dear {},\n\n{}.\n", passenger.name, event.description);
+        let trip = bookings
+            .get(&event.booking_id)
+            .filter(|_| event.kind != EventKind::Cancelled)
+            .and_then(|booking| Some((booking, trains.get(&booking.train_line)?)));
+        if let Some((booking, train)) = trip {
+            body.push_str(&format!(
+                "\nTrain {} {} from {} to {}\nDate: {}\nDeparture: {}\nSeat: {}\n",
+                train.line,
+                train.name,
+                train.origin,
+                train.destination,
+                booking.travel_date,
+                train.departure.format("%H:%M"),
+                booking.seat
+            ));
+        }
+
+        Some(Self {
+            booking_id: event.booking_id.clone(),
+            subject,
+            summary: event.description.clone(),
+            body,
+        })
+    }
+}
+
+pub trait NotificationChannel {
+    fn name(&self) -> &'static str;
+    /// Where the passenger is reached on this channel, or None when they cannot be.
+    fn address(
+        &self,
+        passenger: &Passenger,
+    ) -> Option<String>;
+    fn send(
+        &mut self,
+        to: &str,
+        notification: &Notification,
+    ) -> Result<()>;
+}
+
+/// Plain text email through an SMTP relay, encrypted with STARTTLS.
+pub struct EmailChannel {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl EmailChannel {
+    pub fn new(
+        host: &str,
+        port: u16,
+        from: &str,
+    ) -> Result<Self> {
+        Ok(Self {
+            transport: SmtpTransport::starttls_relay(host)
+                .with_context(|| format!("Invalid SMTP host {}", host))?
+                .port(port)
+                .build(),
+            from: from.parse().context("Invalid sender address")?,
+        })
+    }
+
+    /// Unencrypted, for a mail catcher on a development machine only.
+    pub fn plaintext(
+        host: &str,
+        port: u16,
+        from: &str,
+    ) -> Result<Self> {
+        Ok(Self {
+            transport: SmtpTransport::builder_dangerous(host).port(port).build(),
+            from: from.parse().context("Invalid sender address")?,
+        })
+    }
+}
+
+impl NotificationChannel for EmailChannel {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn address(
+        &self,
+        passenger: &Passenger,
+    ) -> Option<String> {
+        (!passenger.email.is_empty()).then(|| passenger.email.clone())
+    }
+
+    fn send(
+        &mut self,
+        to: &str,
+        notification: &Notification,
+    ) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse().context("Invalid email address")?)
+            .subject(&notification.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(notification.body.clone())?;
+        self.transport.send(&message)?;
+        Ok(())
+    }
+}
+
+/// Text messages through an HTTP gateway that accepts a form POST with `to` and `message` fields.
+pub struct SmsChannel {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl SmsChannel {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            agent: ureq::AgentBuilder::new().timeout(SMS_TIMEOUT).build(),
+        }
+    }
+}
+
+impl NotificationChannel for SmsChannel {
+    fn name(&self) -> &'static str {
+        "SMS"
+    }
+
+    fn address(
+        &self,
+        passenger: &Passenger,
+    ) -> Option<String> {
+        (!passenger.phone.is_empty()).then(|| passenger.phone.clone())
+    }
+
+    fn send(
+        &mut self,
+        to: &str,
+        notification: &Notification,
+    ) -> Result<()> {
+        let message = format!("{}: {}", notification.subject, notification.summary);
+        self.agent
+            .post(&self.url)
+            .send_form(&[("to", to), ("message", &message)])
+            .with_context(|| format!("SMS gateway {} rejected the message", self.url))?;
+        Ok(())
+    }
+}
+
+/// Writes every notification to a numbered file in a local directory, reaching every passenger.
+pub struct OutboxChannel {
+    dir: PathBuf,
+}
+
+impl OutboxChannel {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+impl NotificationChannel for OutboxChannel {
+    fn name(&self) -> &'static str {
+        "outbox"
+    }
+
+    fn address(
+        &self,
+        passenger: &Passenger,
+    ) -> Option<String> {
+        Some(format!("{} <{}>", passenger.name, passenger.id))
+    }
+
+    fn send(
+        &mut self,
+        to: &str,
+        notification: &Notification,
+    ) -> Result<()> {
+        fs::create_dir_all(&self.dir).with_context(|| format!("Could not create {}", self.dir.display()))?;
+        // Number after the highest message so far, so removed messages never get overwritten
+        let number = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str()?.split('-').next()?.parse::<u32>().ok())
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let path = self.dir.join(format!("{:06}-{}.txt", number, notification.booking_id));
+        fs::write(
+            &path,
+            format!("To: {}\nSubject: {}\n\n{}", to, notification.subject, notification.body),
+        )
+        .with_context(|| format!("Could not write {}", path.display()))
+    }
+}
+
+/// Sends notifications for new booking history events through every channel that can reach the
+/// passenger.
+pub struct Notifier {
+    channels: Vec<Box<dyn NotificationChannel>>,
+    notified: usize, // History events already handled
+}
+
+impl Notifier {
+    pub fn new(channels: Vec<Box<dyn NotificationChannel>>) -> Self {
+        Self { channels, notified: 0 }
+    }
+
+    /// The outbox channel, plus email when `TRAIN_RS_SMTP_HOST` is set and SMS when
+    /// `TRAIN_RS_SMS_URL` is set. Email is sent unencrypted only when `TRAIN_RS_SMTP_PLAINTEXT` is
+    /// set, for local development.
+    pub fn from_env() -> Result<Self> {
+        let mut channels: Vec<Box<dyn NotificationChannel>> =
+            vec![Box::new(OutboxChannel::new(PathBuf::from(OUTBOX_DIR)))];
+        if let Ok(host) = env::var("TRAIN_RS_SMTP_HOST") {
+            let port = match env::var("TRAIN_RS_SMTP_PORT") {
+                Ok(port) => port.parse().context("Invalid TRAIN_RS_SMTP_PORT")?,
+                Err(_) => DEFAULT_SMTP_PORT,
+            };
+            let from = env::var("TRAIN_RS_SMTP_FROM").unwrap_or_else(|_| DEFAULT_SMTP_FROM.to_string());
+            let email = if env::var_os("TRAIN_RS_SMTP_PLAINTEXT").is_some() {
+                EmailChannel::plaintext(&host, port, &from)?
+            } else {
+                EmailChannel::new(&host, port, &from)?
+            };
+            channels.push(Box::new(email));
+        }
+        if let Ok(url) = env::var("TRAIN_RS_SMS_URL") {
+            channels.push(Box::new(SmsChannel::new(&url)));
+        }
+        Ok(Self::new(channels))
+    }
+
+    /// Notifies passengers of the events recorded since the last dispatch. A failing channel does
+    /// not stop the others; failures are returned as messages for the agent.
+    pub fn dispatch(
+        &mut self,
+        history: &BookingHistory,
+        bookings: &BookingList,
+        passengers: &PassengerList,
+        trains: &TrainList,
+    ) -> Vec<String> {
+        let mut failures = Vec::new();
+        for event in &history[self.notified.min(history.len())..] {
+            let Some(passenger) = passengers.get(&event.passenger_id) else {
+                continue;
+            };
+            let Some(notification) = Notification::from_event(event, passenger, bookings, trains) else {
+                continue;
+            };
+            for channel in &mut self.channels {
+                if let Some(to) = channel.address(passenger) {
+                    if let Err(e) = channel.send(&to, &notification) {
+                        failures.push(format!(
+                            "Could not notify {} by {} about booking {}: {:#}",
+                            passenger.name,
+                            channel.name(),
+                            event.booking_id,
+                            e
+                        ));
+                    }
+                }
+            }
+        }
+        self.notified = history.len();
+        failures
+    }
+}
+
+/// Dispatches pending notifications, called after every action that can record booking events.
+pub fn notify(
+    notifier: &mut Notifier,
+    history: &BookingHistory,
+    bookings: &BookingList,
+    passengers: &PassengerList,
+    trains: &TrainList,
+) {
+    let failures = notifier.dispatch(history, bookings, passengers, trains);
+    if !failures.is_empty() {
+        for failure in failures {
+            println!("{}", failure);
+        }
+        continue_prompt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::record_event;
+    use crate::test_support::{adult, book, express, travel_date};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn setup() -> (BookingList, PassengerList, TrainList, BookingHistory) {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+        let mut history = BookingHistory::new();
+
+        let mut passenger = adult("P1", "John Doe");
+        passenger.email = "john@example.com".to_string();
+        passenger.phone = "+15550100".to_string();
+        passengers.insert("P1".to_string(), passenger);
+        trains.insert(1, express());
+        let booking_id = book(&mut bookings, &mut passengers, &mut trains, "P1", 1).unwrap();
+        record_event(
+            &mut history,
+            &bookings[&booking_id],
+            travel_date().and_hms_opt(9, 0, 0).unwrap(),
+            EventKind::Booked,
+            "Booked train 1 on 2024-06-10".to_string(),
+        );
+
+        (bookings, passengers, trains, history)
+    }
+
+    #[test]
+    fn test_notification_from_event() {
+        let (bookings, passengers, trains, mut history) = setup();
+
+        let notification = Notification::from_event(&history[0], &passengers["P1"], &bookings, &trains).unwrap();
+        assert_eq!(notification.subject, "Booking P1_1_20240610 confirmed");
+        assert!(notification.body.starts_with("Dear John Doe,"));
+        assert!(notification.body.contains("Train 1 Express from Toronto to Hamilton"));
+        assert!(notification.body.contains("Seat: 1"));
+
+        let timestamp = history[0].timestamp;
+        record_event(
+            &mut history,
+            &bookings["P1_1_20240610"],
+            timestamp,
+            EventKind::Boarded,
+            "Boarded".to_string(),
+        );
+        assert!(Notification::from_event(&history[1], &passengers["P1"], &bookings, &trains).is_none());
+    }
+
+    #[test]
+    fn test_outbox_only_sends_new_events() {
+        let (bookings, passengers, trains, mut history) = setup();
+        let dir = std::env::temp_dir().join(format!("train-rs-outbox-{}", std::process::id()));
+        let mut notifier = Notifier::new(vec![Box::new(OutboxChannel::new(dir.clone()))]);
+
+        assert!(notifier.dispatch(&history, &bookings, &passengers, &trains).is_empty());
+        assert!(notifier.dispatch(&history, &bookings, &passengers, &trains).is_empty());
+        let timestamp = history[0].timestamp;
+        record_event(
+            &mut history,
+            &bookings["P1_1_20240610"],
+            timestamp,
+            EventKind::Cancelled,
+            "Cancelled".to_string(),
+        );
+        notifier.dispatch(&history, &bookings, &passengers, &trains);
+
+        let outbox = || {
+            let mut files: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+            files.sort();
+            files
+        };
+        let files = outbox();
+        let contents: Vec<String> = files.iter().map(|path| fs::read_to_string(path).unwrap()).collect();
+        assert_eq!(contents.len(), 2);
+        assert!(contents[0].starts_with("To: John Doe <P1>\nSubject: Booking P1_1_20240610 confirmed"));
+        assert!(contents[1].contains("Subject: Booking P1_1_20240610 cancelled"));
+
+        // A message removed from the outbox does not make the next one overwrite the last
+        fs::remove_file(&files[0]).unwrap();
+        record_event(
+            &mut history,
+            &bookings["P1_1_20240610"],
+            timestamp,
+            EventKind::Cancelled,
+            "Cancelled again".to_string(),
+        );
+        notifier.dispatch(&history, &bookings, &passengers, &trains);
+        let names: Vec<String> = outbox()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(names, vec!["000002-P1_1_20240610.txt", "000003-P1_1_20240610.txt"]);
+    }
+
+    #[test]
+    fn test_email_through_local_smtp_server() {
+        let (bookings, passengers, trains, history) = setup();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Minimal SMTP stand-in that accepts one message and hands back what it received
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut received = String::new();
+            let mut in_data = false;
+            writer.write_all(b"220 localhost ESMTP\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                if in_data {
+                    if line == ".\r\n" {
+                        in_data = false;
+                        writer.write_all(b"250 OK\r\n").unwrap();
+                    } else {
+                        received.push_str(&line);
+                    }
+                } else if line.starts_with("EHLO") {
+                    writer.write_all(b"250 localhost\r\n").unwrap();
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    writer.write_all(b"354 Go ahead\r\n").unwrap();
+                } else if line.starts_with("QUIT") {
+                    writer.write_all(b"221 Bye\r\n").unwrap();
+                    break;
+                } else {
+                    writer.write_all(b"250 OK\r\n").unwrap();
+                }
+            }
+            received
+        });
+
+        let email = EmailChannel::plaintext("127.0.0.1", port, DEFAULT_SMTP_FROM).unwrap();
+        let mut notifier = Notifier::new(vec![Box::new(email)]);
+        assert!(notifier.dispatch(&history, &bookings, &passengers, &trains).is_empty());
+        drop(notifier);
+
+        let received = server.join().unwrap();
+        assert!(received.contains("To: john@example.com"));
+        assert!(received.contains("Subject: Booking P1_1_20240610 confirmed"));
+        assert!(received.contains("Dear John Doe,"));
+    }
+
+    #[test]
+    fn test_sms_through_local_http_gateway() {
+        let (bookings, passengers, trains, history) = setup();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/send", listener.local_addr().unwrap());
+
+        // HTTP stand-in that answers one request and hands back the form body
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = length.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let mut writer = stream;
+            writer
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            String::from_utf8(body).unwrap()
+        });
+
+        let mut notifier = Notifier::new(vec![Box::new(SmsChannel::new(&url))]);
+        assert!(notifier.dispatch(&history, &bookings, &passengers, &trains).is_empty());
+
+        let body = server.join().unwrap();
+        assert!(body.starts_with("to=%2B15550100&message="));
+        assert!(body.contains("Booking+P1_1_20240610+confirmed"));
+    }
+}
@@ -1,30 +1,73 @@
-use anyhow::Result;
-use requestty::{self, Question};
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Local, Months, NaiveDate};
+use requestty::{self, Answers, Question};
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 use crate::actions::{add_remove_prompt, Action};
+use crate::assistance::{accessibility_question, format_needs, parse_accessibility_answer, AccessibilityNeed};
 use crate::helper::{clear_screen, continue_prompt};
+use crate::passes::Pass;
+
+pub struct EmergencyContact {
+    pub name: String,
+    pub phone: String,
+}
+
+impl fmt::Display for EmergencyContact {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.phone)
+    }
+}
 
 pub struct Passenger {
     pub id: String,
     pub name: String,
-    pub age: u16,
+    pub date_of_birth: NaiveDate,
+    pub date_of_birth_approximate: bool, // Estimated from an age recorded before dates of birth were kept
+    pub email: String,                   // Empty when the passenger has no email address
+    pub phone: String,                   // Empty when the passenger has no phone number
+    pub postal_address: String,          // Empty when the passenger has no postal address
+    pub emergency_contact: Option<EmergencyContact>, // Who to call if something happens on board
+    pub special_requirements: String,    // Free text shown to the crew on the manifest
+    pub accessibility: Vec<AccessibilityNeed>, // Copied onto each new booking for station staff
+    pub passes: Vec<Pass>,               // Season passes and carnets the passenger has bought
     pub bookings: HashSet<String>,
+    pub past_bookings: HashSet<String>, // Removed booking IDs, never issued again so ledger entries stay with the old trip
 }
 
 impl Passenger {
     pub fn new(
         id: String,
         name: String,
-        age: u16,
+        date_of_birth: NaiveDate,
     ) -> Self {
         Self {
             id,
             name,
-            age,
+            date_of_birth,
+            date_of_birth_approximate: false,
+            email: String::new(),
+            phone: String::new(),
+            postal_address: String::new(),
+            emergency_contact: None,
+            special_requirements: String::new(),
+            accessibility: Vec::new(),
+            passes: Vec::new(),
             bookings: HashSet::new(),
+            past_bookings: HashSet::new(),
         }
     }
+    /// Age in whole years on the given date, e.g. the travel date of a booking.
+    pub fn age_on(
+        &self,
+        date: NaiveDate,
+    ) -> u16 {
+        date.years_since(self.date_of_birth).unwrap_or(0) as u16
+    }
     pub fn add_booking(
         &mut self,
         booking_id: String,
@@ -35,12 +78,295 @@ impl Passenger {
         &mut self,
         booking_id: &str,
     ) {
-        self.bookings.remove(booking_id);
+        if self.bookings.remove(booking_id) {
+            self.past_bookings.insert(booking_id.to_string());
+        }
+    }
+    // Adds a numeric suffix when the ID is or was in use by another booking
+    pub fn next_booking_id(
+        &self,
+        base: String,
+    ) -> String {
+        let in_use = |id: &String| self.bookings.contains(id) || self.past_bookings.contains(id);
+        if !in_use(&base) {
+            return base;
+        }
+        (2..).map(|n| format!("{}-{}", base, n)).find(|id| !in_use(id)).unwrap()
+    }
+
+    /// True when the query appears in any identifying or contact field, ignoring case.
+    /// Phone numbers also match on their digits alone, so "416 555" finds "+1 (416) 555-0100".
+    pub fn matches(
+        &self,
+        query: &str,
+    ) -> bool {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return true;
+        }
+        let mut fields = vec![
+            self.id.as_str(),
+            self.name.as_str(),
+            self.email.as_str(),
+            self.phone.as_str(),
+            self.postal_address.as_str(),
+        ];
+        if let Some(contact) = &self.emergency_contact {
+            fields.push(contact.name.as_str());
+            fields.push(contact.phone.as_str());
+        }
+        if fields.iter().any(|field| field.to_lowercase().contains(&query)) {
+            return true;
+        }
+
+        let query_digits = digits(&query);
+        if query_digits.is_empty() || !query.chars().all(|c| c.is_ascii_digit() || " +-().".contains(c)) {
+            return false;
+        }
+        let mut phones = vec![self.phone.as_str()];
+        if let Some(contact) = &self.emergency_contact {
+            phones.push(contact.phone.as_str());
+        }
+        phones.iter().any(|phone| digits(phone).contains(&query_digits))
     }
 }
 
 pub type PassengerList = HashMap<String, Passenger>;
 
+/// Passengers matching the query, sorted by ID. A blank query returns everyone.
+pub fn search_passengers<'a>(
+    passengers: &'a PassengerList,
+    query: &str,
+) -> Vec<&'a Passenger> {
+    let mut found: Vec<&Passenger> = passengers
+        .values()
+        .filter(|passenger| passenger.matches(query))
+        .collect();
+    found.sort_by(|a, b| a.id.cmp(&b.id));
+    found
+}
+
+fn digits(value: &str) -> String {
+    value.chars().filter(char::is_ascii_digit).collect()
+}
+
+pub fn validate_name(value: &str) -> Result<(), String> {
+    if value.trim().is_empty() {
+        Err("A name is required".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+const MAX_AGE: u16 = 130;
+
+/// Migrates an age-only record to a date of birth on which the passenger is exactly `age`
+/// on `as_of`. Callers should set `date_of_birth_approximate` on the result.
+pub fn approximate_date_of_birth(
+    age: u16,
+    as_of: NaiveDate,
+) -> NaiveDate {
+    as_of
+        .checked_sub_months(Months::new(u32::from(age) * 12))
+        .unwrap_or(as_of)
+}
+
+/// Reads a date of birth as YYYY-MM-DD, or falls back to a plain age when the date is unknown.
+/// The flag is true when the date was estimated from an age.
+pub fn parse_date_of_birth(
+    value: &str,
+    today: NaiveDate,
+) -> Result<(NaiveDate, bool), String> {
+    let value = value.trim();
+    if let Ok(age) = value.parse::<u16>() {
+        if age > MAX_AGE {
+            return Err(format!("Enter an age between 0 and {}", MAX_AGE));
+        }
+        return Ok((approximate_date_of_birth(age, today), true));
+    }
+    let date_of_birth = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| "Enter a date of birth as YYYY-MM-DD, or an age if it is unknown".to_string())?;
+    if date_of_birth > today {
+        return Err("The date of birth cannot be in the future".to_string());
+    }
+    if today.year() - date_of_birth.year() > i32::from(MAX_AGE) {
+        return Err(format!("The date of birth is more than {} years ago", MAX_AGE));
+    }
+    Ok((date_of_birth, false))
+}
+
+/// Accepts a blank value (no email) or a single local@domain.tld address without spaces.
+pub fn validate_email(value: &str) -> Result<(), String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(());
+    }
+    let valid = match value.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && !value.contains(char::is_whitespace)
+                && domain.split('.').count() >= 2
+                && domain.split('.').all(|part| !part.is_empty())
+        }
+        None => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err("Enter an email address like name@example.com".to_string())
+    }
+}
+
+/// Accepts a blank value (no phone) or 7 to 15 digits, optionally led by '+' and
+/// grouped with spaces, dashes, dots or brackets.
+pub fn validate_phone(value: &str) -> Result<(), String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(());
+    }
+    let rest = value.strip_prefix('+').unwrap_or(value);
+    let count = digits(rest).len();
+    if rest.chars().all(|c| c.is_ascii_digit() || " -().".contains(c)) && (7..=15).contains(&count) {
+        Ok(())
+    } else {
+        Err("Enter a phone number of 7 to 15 digits, optionally starting with +".to_string())
+    }
+}
+
+/// Accepts a blank value (no address) or a street line and a town, separated by a comma.
+pub fn validate_postal_address(value: &str) -> Result<(), String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(());
+    }
+    let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+    if parts.len() >= 2 && parts.iter().all(|part| !part.is_empty()) && value.chars().any(|c| c.is_alphabetic()) {
+        Ok(())
+    } else {
+        Err("Enter the address as comma separated lines, e.g. 12 King St, Toronto, M5H 1A1".to_string())
+    }
+}
+
+/// The emergency contact phone is required once a contact name has been given.
+fn validate_emergency_phone(
+    value: &str,
+    answers: &Answers,
+) -> Result<(), String> {
+    let has_name = answers
+        .get("emergency_name")
+        .and_then(|answer| answer.as_string())
+        .is_some_and(|name| !name.trim().is_empty());
+    if has_name && value.trim().is_empty() {
+        return Err("Enter a phone number for the emergency contact".to_string());
+    }
+    validate_phone(value)
+}
+
+fn contact_questions<'a>(passenger: Option<&Passenger>) -> Vec<Question<'a>> {
+    let default = |value: Option<&str>| value.unwrap_or_default().to_string();
+    let contact = passenger.and_then(|p| p.emergency_contact.as_ref());
+    vec![
+        Question::input("email")
+            .message("Enter the email address of the passenger (blank for none)")
+            .default(default(passenger.map(|p| p.email.as_str())))
+            .validate(|value, _| validate_email(value))
+            .build(),
+        Question::input("phone")
+            .message("Enter the phone number of the passenger (blank for none)")
+            .default(default(passenger.map(|p| p.phone.as_str())))
+            .validate(|value, _| validate_phone(value))
+            .build(),
+        Question::input("postal_address")
+            .message("Enter the postal address of the passenger (blank for none)")
+            .default(default(passenger.map(|p| p.postal_address.as_str())))
+            .validate(|value, _| validate_postal_address(value))
+            .build(),
+        Question::input("emergency_name")
+            .message("Enter the name of an emergency contact (blank for none)")
+            .default(default(contact.map(|c| c.name.as_str())))
+            .build(),
+        Question::input("emergency_phone")
+            .message("Enter the phone number of the emergency contact")
+            .default(default(contact.map(|c| c.phone.as_str())))
+            .when(|answers: &Answers| {
+                answers
+                    .get("emergency_name")
+                    .and_then(|answer| answer.as_string())
+                    .is_some_and(|name| !name.trim().is_empty())
+            })
+            .validate(validate_emergency_phone)
+            .build(),
+        Question::input("special_requirements")
+            .message("Enter any special requirements (blank for none)")
+            .default(default(passenger.map(|p| p.special_requirements.as_str())))
+            .build(),
+        accessibility_question(passenger.map_or(&[][..], |p| p.accessibility.as_slice())),
+    ]
+}
+
+fn apply_contact_answers(
+    passenger: &mut Passenger,
+    answers: &Answers,
+) {
+    let text = |key: &str| {
+        answers
+            .get(key)
+            .and_then(|answer| answer.as_string())
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    };
+    passenger.email = text("email");
+    passenger.phone = text("phone");
+    passenger.postal_address = text("postal_address");
+    let emergency_name = text("emergency_name");
+    passenger.emergency_contact = if emergency_name.is_empty() {
+        None
+    } else {
+        Some(EmergencyContact {
+            name: emergency_name,
+            phone: text("emergency_phone"),
+        })
+    };
+    passenger.special_requirements = text("special_requirements");
+    passenger.accessibility = parse_accessibility_answer(answers.get("accessibility"));
+}
+
+fn print_passenger(passenger: &Passenger) {
+    println!(
+        "ID: {}, Name: {}, Born: {}{}, Age: {}",
+        passenger.id,
+        passenger.name,
+        passenger.date_of_birth,
+        if passenger.date_of_birth_approximate {
+            " (approx.)"
+        } else {
+            ""
+        },
+        passenger.age_on(Local::now().date_naive())
+    );
+    let or_none = |value: &str| {
+        if value.is_empty() {
+            "-".to_string()
+        } else {
+            value.to_string()
+        }
+    };
+    println!(
+        "    Email: {}, Phone: {}, Address: {}",
+        or_none(&passenger.email),
+        or_none(&passenger.phone),
+        or_none(&passenger.postal_address)
+    );
+    if let Some(contact) = &passenger.emergency_contact {
+        println!("    Emergency contact: {}", contact);
+    }
+    if !passenger.accessibility.is_empty() {
+        println!("    Accessibility: {}", format_needs(&passenger.accessibility));
+    }
+}
+
 pub fn manage_passengers(passengers: &mut PassengerList) -> Result<()> {
     loop {
         clear_screen();
@@ -49,8 +375,15 @@ pub fn manage_passengers(passengers: &mut PassengerList) -> Result<()> {
                 if passengers.is_empty() {
                     println!("No passengers found");
                 } else {
-                    for (id, passenger) in &mut *passengers {
-                        println!("ID: {}, Name: {}, Age: {}", id, passenger.name, passenger.age);
+                    let query = requestty::prompt_one(
+                        Question::input("query").message("Search by name, ID or contact details (blank for all)"),
+                    )?;
+                    let found = search_passengers(passengers, query.as_string().unwrap());
+                    if found.is_empty() {
+                        println!("No passengers match");
+                    }
+                    for passenger in found {
+                        print_passenger(passenger);
                     }
                 }
                 continue_prompt();
@@ -88,21 +421,42 @@ pub fn manage_passengers(passengers: &mut PassengerList) -> Result<()> {
 }
 
 fn add_passenger() -> Result<Passenger> {
-    let questions: Vec<Question> = vec![
-        Question::input("id").message("Enter the ID of the passenger").build(),
+    let today = Local::now().date_naive();
+    let mut questions: Vec<Question> = vec![
+        Question::input("id")
+            .message("Enter the ID of the passenger")
+            .validate(|value, _| {
+                if value.trim().is_empty() {
+                    Err("An ID is required".to_string())
+                } else {
+                    Ok(())
+                }
+            })
+            .build(),
         Question::input("name")
             .message("Enter the name of the passenger")
+            .validate(|value, _| validate_name(value))
+            .build(),
+        Question::input("date_of_birth")
+            .message("Enter the date of birth of the passenger (YYYY-MM-DD, or an age if unknown)")
+            .validate(move |value, _| parse_date_of_birth(value, today).map(|_| ()))
             .build(),
-        Question::input("age").message("Enter the age of the passenger").build(),
     ];
+    questions.extend(contact_questions(None));
 
     let passenger = requestty::prompt(questions)?;
 
-    Ok(Passenger::new(
-        passenger.get("id").unwrap().as_string().unwrap().to_string(),
-        passenger.get("name").unwrap().as_string().unwrap().to_string(),
-        passenger.get("age").unwrap().as_string().unwrap().parse()?,
-    ))
+    let (date_of_birth, approximate) =
+        parse_date_of_birth(passenger.get("date_of_birth").unwrap().as_string().unwrap(), today)
+            .map_err(|e| anyhow!(e))?;
+    let mut new_passenger = Passenger::new(
+        passenger.get("id").unwrap().as_string().unwrap().trim().to_string(),
+        passenger.get("name").unwrap().as_string().unwrap().trim().to_string(),
+        date_of_birth,
+    );
+    new_passenger.date_of_birth_approximate = approximate;
+    apply_contact_answers(&mut new_passenger, &passenger);
+    Ok(new_passenger)
 }
 
 fn remove_passenger(passengers: &mut PassengerList) -> Result<()> {
@@ -123,6 +477,7 @@ fn remove_passenger(passengers: &mut PassengerList) -> Result<()> {
 }
 
 fn edit_passenger(passengers: &mut PassengerList) -> Result<()> {
+    let today = Local::now().date_naive();
     let passenger_choices: Vec<String> = passengers
         .values()
         .map(|passenger| format!("{}, {}", passenger.id, passenger.name))
@@ -135,44 +490,66 @@ fn edit_passenger(passengers: &mut PassengerList) -> Result<()> {
     let selected_passenger = selection.as_list_item().unwrap().text.clone();
     let id = selected_passenger.split(',').next().unwrap().trim().to_string();
 
-    let questions: Vec<Question> = vec![
+    let mut questions: Vec<Question> = vec![
         Question::input("name")
             .message("Enter the new name of the passenger")
             .default(passengers[&id].name.clone())
+            .validate(|value, _| validate_name(value))
             .build(),
-        Question::input("age")
-            .message("Enter the new age of the passenger")
-            .default(passengers[&id].age.to_string())
+        Question::input("date_of_birth")
+            .message("Enter the new date of birth of the passenger (YYYY-MM-DD, or an age if unknown)")
+            .default(passengers[&id].date_of_birth.to_string())
+            .validate(move |value, _| parse_date_of_birth(value, today).map(|_| ()))
             .build(),
     ];
+    questions.extend(contact_questions(passengers.get(&id)));
 
     let answers = requestty::prompt(questions)?;
 
     if let Some(passenger) = passengers.get_mut(&id) {
-        passenger.name = answers.get("name").unwrap().as_string().unwrap().to_string();
-        passenger.age = answers.get("age").unwrap().as_string().unwrap().parse()?;
+        passenger.name = answers.get("name").unwrap().as_string().unwrap().trim().to_string();
+        let (date_of_birth, approximate) =
+            parse_date_of_birth(answers.get("date_of_birth").unwrap().as_string().unwrap(), today)
+                .map_err(|e| anyhow!(e))?;
+        // An approximate date kept as it was stays approximate
+        if date_of_birth != passenger.date_of_birth {
+            passenger.date_of_birth = date_of_birth;
+            passenger.date_of_birth_approximate = approximate;
+        }
+        apply_contact_answers(passenger, &answers);
     }
 
     Ok(())
 }
 
 #[cfg(test)]
+#[allow(clippy::unnecessary_to_owned)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_create_passenger() {
-        let passenger = Passenger::new("1".to_string(), "John Doe".to_string(), 30);
+        let passenger = Passenger::new(
+            "1".to_string(),
+            "John Doe".to_string(),
+            approximate_date_of_birth(30, NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()),
+        );
 
         assert_eq!(passenger.id, "1");
         assert_eq!(passenger.name, "John Doe");
-        assert_eq!(passenger.age, 30);
+        assert_eq!(passenger.age_on(NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()), 30);
+        assert_eq!(passenger.age_on(NaiveDate::from_ymd_opt(2025, 6, 9).unwrap()), 30);
+        assert_eq!(passenger.age_on(NaiveDate::from_ymd_opt(2025, 6, 10).unwrap()), 31);
     }
 
     #[test]
     fn test_manage_passengers_add() {
         let mut passengers = PassengerList::new();
-        let passenger = Passenger::new("1".to_string(), "John Doe".to_string(), 30);
+        let passenger = Passenger::new(
+            "1".to_string(),
+            "John Doe".to_string(),
+            approximate_date_of_birth(30, NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()),
+        );
 
         passengers.insert(passenger.id.clone(), passenger);
 
@@ -183,7 +560,11 @@ mod tests {
     #[test]
     fn test_manage_passengers_remove() {
         let mut passengers = PassengerList::new();
-        let passenger = Passenger::new("1".to_string(), "John Doe".to_string(), 30);
+        let passenger = Passenger::new(
+            "1".to_string(),
+            "John Doe".to_string(),
+            approximate_date_of_birth(30, NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()),
+        );
 
         passengers.insert(passenger.id.clone(), passenger);
         passengers.remove(&"1".to_string());
@@ -195,7 +576,11 @@ mod tests {
     #[test]
     fn test_manage_passengers_list() {
         let mut passengers = PassengerList::new();
-        let passenger = Passenger::new("1".to_string(), "John Doe".to_string(), 30);
+        let passenger = Passenger::new(
+            "1".to_string(),
+            "John Doe".to_string(),
+            approximate_date_of_birth(30, NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()),
+        );
 
         passengers.insert(passenger.id.clone(), passenger);
 
@@ -206,18 +591,105 @@ mod tests {
     #[test]
     fn test_edit_passenger() {
         let mut passengers = PassengerList::new();
-        let passenger = Passenger::new("1".to_string(), "John Doe".to_string(), 30);
+        let passenger = Passenger::new(
+            "1".to_string(),
+            "John Doe".to_string(),
+            approximate_date_of_birth(30, NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()),
+        );
 
         passengers.insert(passenger.id.clone(), passenger);
 
         // Edit the passenger details directly
         if let Some(passenger) = passengers.get_mut(&"1".to_string()) {
             passenger.name = "Jane Doe".to_string();
-            passenger.age = 35;
+            passenger.date_of_birth = NaiveDate::from_ymd_opt(1989, 2, 28).unwrap();
         }
 
         let edited_passenger = passengers.get(&"1".to_string()).unwrap();
         assert_eq!(edited_passenger.name, "Jane Doe");
-        assert_eq!(edited_passenger.age, 35);
+        assert_eq!(
+            edited_passenger.age_on(NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()),
+            35
+        );
+    }
+
+    #[test]
+    fn test_contact_validation() {
+        assert!(validate_email("").is_ok());
+        assert!(validate_email("jane.doe@example.com").is_ok());
+        assert!(validate_email("jane.doe@example").is_err());
+        assert!(validate_email("jane doe@example.com").is_err());
+        assert!(validate_email("@example.com").is_err());
+
+        assert!(validate_phone("").is_ok());
+        assert!(validate_phone("+1 (416) 555-0100").is_ok());
+        assert!(validate_phone("555-01").is_err());
+        assert!(validate_phone("416-555-CALL").is_err());
+
+        assert!(validate_postal_address("12 King St, Toronto, M5H 1A1").is_ok());
+        assert!(validate_postal_address("12 King St").is_err());
+        assert!(validate_postal_address("12, 34").is_err());
+
+        assert!(validate_name("  ").is_err());
+    }
+
+    #[test]
+    fn test_search_passengers() {
+        let mut passengers = PassengerList::new();
+        let mut jane = Passenger::new(
+            "1".to_string(),
+            "Jane Doe".to_string(),
+            approximate_date_of_birth(35, NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()),
+        );
+        jane.email = "jane@example.com".to_string();
+        jane.phone = "+1 (416) 555-0100".to_string();
+        jane.postal_address = "12 King St, Toronto".to_string();
+        jane.emergency_contact = Some(EmergencyContact {
+            name: "Sam Doe".to_string(),
+            phone: "905-555-0199".to_string(),
+        });
+        passengers.insert(jane.id.clone(), jane);
+        passengers.insert(
+            "2".to_string(),
+            Passenger::new(
+                "2".to_string(),
+                "John Smith".to_string(),
+                approximate_date_of_birth(40, NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()),
+            ),
+        );
+
+        let ids = |query: &str| -> Vec<String> {
+            search_passengers(&passengers, query)
+                .iter()
+                .map(|passenger| passenger.id.clone())
+                .collect()
+        };
+        assert_eq!(ids(""), vec!["1", "2"]);
+        assert_eq!(ids("JANE@"), vec!["1"]);
+        assert_eq!(ids("4165550100"), vec!["1"]);
+        assert_eq!(ids("905 555"), vec!["1"]);
+        assert_eq!(ids("king st"), vec!["1"]);
+        assert_eq!(ids("sam"), vec!["1"]);
+        assert_eq!(ids("smith"), vec!["2"]);
+        assert!(ids("Ottawa").is_empty());
+    }
+
+    #[test]
+    fn test_parse_date_of_birth() {
+        let today = NaiveDate::from_ymd_opt(2024, 6, 10).unwrap();
+
+        let (date_of_birth, approximate) = parse_date_of_birth("1990-02-28", today).unwrap();
+        assert_eq!(date_of_birth, NaiveDate::from_ymd_opt(1990, 2, 28).unwrap());
+        assert!(!approximate);
+
+        // An age alone is migrated to an approximate date on which the passenger has that age
+        let (date_of_birth, approximate) = parse_date_of_birth("8", today).unwrap();
+        assert_eq!(date_of_birth, NaiveDate::from_ymd_opt(2016, 6, 10).unwrap());
+        assert!(approximate);
+
+        assert!(parse_date_of_birth("2024-06-11", today).is_err());
+        assert!(parse_date_of_birth("1850-01-01", today).is_err());
+        assert!(parse_date_of_birth("200", today).is_err());
+        assert!(parse_date_of_birth("10/06/1990", today).is_err());
     }
 }
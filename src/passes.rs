@@ -0,0 +1,697 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{Local, Months, NaiveDate};
+use requestty::{self, Answers, Question};
+
+use crate::actions::{add_remove_prompt, Action};
+use crate::bookings::{Booking, BookingList, BookingStatus};
+use crate::fares::release_bucket;
+use crate::helper::{clear_screen, continue_prompt, format_money, parse_date, parse_money};
+use crate::passengers::{Passenger, PassengerList};
+use crate::payments::{Ledger, PaymentMethod};
+use crate::trains::{Train, TrainList};
+
+pub const UNLIMITED_VALID_MONTHS: u32 = 1;
+pub const CARNET_VALID_MONTHS: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PassKind {
+    Unlimited,   // Any number of rides on the route while the pass is valid
+    Carnet(u32), // A fixed number of rides on the route
+}
+
+impl std::fmt::Display for PassKind {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            PassKind::Unlimited => write!(f, "Unlimited"),
+            PassKind::Carnet(rides) => write!(f, "{}-ride carnet", rides),
+        }
+    }
+}
+
+/// A season pass or carnet owned by a passenger, valid on one route in both directions.
+pub struct Pass {
+    pub id: String,
+    pub kind: PassKind,
+    pub origin: String,
+    pub destination: String,
+    pub valid_from: NaiveDate,
+    pub valid_until: NaiveDate, // Last day the pass can be used
+    pub price: u32,             // Price in cents
+    pub rides_used: u32,        // Bookings made with the pass and not cancelled
+}
+
+impl Pass {
+    pub fn new(
+        id: String,
+        kind: PassKind,
+        origin: String,
+        destination: String,
+        valid_from: NaiveDate,
+        price: u32,
+    ) -> Self {
+        let months = match kind {
+            PassKind::Unlimited => UNLIMITED_VALID_MONTHS,
+            PassKind::Carnet(_) => CARNET_VALID_MONTHS,
+        };
+        let valid_until = (valid_from + Months::new(months)).pred_opt().unwrap();
+        Self {
+            id,
+            kind,
+            origin,
+            destination,
+            valid_from,
+            valid_until,
+            price,
+            rides_used: 0,
+        }
+    }
+
+    pub fn rides_left(&self) -> Option<u32> {
+        match self.kind {
+            PassKind::Unlimited => None,
+            PassKind::Carnet(rides) => Some(rides.saturating_sub(self.rides_used)),
+        }
+    }
+
+    pub fn check_date(
+        &self,
+        travel_date: NaiveDate,
+    ) -> Result<()> {
+        if travel_date < self.valid_from || travel_date > self.valid_until {
+            return Err(anyhow!(
+                "Pass {} is valid from {} to {}, not on {}",
+                self.id,
+                self.valid_from,
+                self.valid_until,
+                travel_date
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the pass can be used for the train on the travel date, not counting rides.
+    pub fn check(
+        &self,
+        train: &Train,
+        travel_date: NaiveDate,
+    ) -> Result<()> {
+        let forward = train.origin == self.origin && train.destination == self.destination;
+        let back = train.origin == self.destination && train.destination == self.origin;
+        if !forward && !back {
+            return Err(anyhow!(
+                "Pass {} covers {} to {}, not train {} from {} to {}",
+                self.id,
+                self.origin,
+                self.destination,
+                train.line,
+                train.origin,
+                train.destination
+            ));
+        }
+        self.check_date(travel_date)
+    }
+}
+
+/// The pass a passenger would use for a trip, preferring unlimited passes so carnet rides are kept.
+pub fn find_pass<'a>(
+    passenger: &'a Passenger,
+    train: &Train,
+    travel_date: NaiveDate,
+) -> Option<&'a Pass> {
+    passenger
+        .passes
+        .iter()
+        .filter(|pass| pass.check(train, travel_date).is_ok() && pass.rides_left() != Some(0))
+        .min_by_key(|pass| pass.rides_left().is_some())
+}
+
+/// Covers a booking with a pass instead of a fare, using up a carnet ride.
+pub fn use_pass(
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    trains: &mut TrainList,
+    booking_id: &str,
+    pass_id: &str,
+) -> Result<()> {
+    let booking = bookings
+        .get_mut(booking_id)
+        .ok_or_else(|| anyhow!("Booking not found"))?;
+    let pass = passengers
+        .get_mut(&booking.passenger_id)
+        .and_then(|passenger| passenger.passes.iter_mut().find(|pass| pass.id == pass_id))
+        .ok_or_else(|| anyhow!("Pass {} not found for passenger {}", pass_id, booking.passenger_id))?;
+    let train = trains
+        .get_mut(&booking.train_line)
+        .ok_or_else(|| anyhow!("Train not found"))?;
+
+    if booking.pass.is_some() {
+        return Err(anyhow!("Booking {} is already covered by a pass", booking_id));
+    }
+    pass.check(train, booking.travel_date)?;
+    if pass.rides_left() == Some(0) {
+        return Err(anyhow!("Pass {} has no rides left", pass_id));
+    }
+
+    if let Some(code) = booking.fare_bucket.take() {
+        release_bucket(train, &code, booking.travel_date);
+    }
+    booking.fare = 0;
+    booking.discount = 0;
+    booking.promo_code = None;
+    booking.load_band = None;
+    booking.pass = Some(pass.id.clone());
+    pass.rides_used += 1;
+    Ok(())
+}
+
+/// Gives back the ride a cancelled booking used on its pass.
+pub fn release_pass(
+    passengers: &mut PassengerList,
+    booking: &Booking,
+) {
+    let Some(pass_id) = &booking.pass else {
+        return;
+    };
+    if let Some(pass) = passengers
+        .get_mut(&booking.passenger_id)
+        .and_then(|passenger| passenger.passes.iter_mut().find(|pass| &pass.id == pass_id))
+    {
+        pass.rides_used = pass.rides_used.saturating_sub(1);
+    }
+}
+
+/// Checks at boarding that the pass a booking uses still exists and is valid on the travel date.
+pub fn check_pass_at_boarding(
+    passengers: &PassengerList,
+    booking: &Booking,
+) -> Result<()> {
+    let Some(pass_id) = &booking.pass else {
+        return Ok(());
+    };
+    passengers
+        .get(&booking.passenger_id)
+        .and_then(|passenger| passenger.passes.iter().find(|pass| &pass.id == pass_id))
+        .ok_or_else(|| anyhow!("Pass {} for booking {} has been cancelled", pass_id, booking.id))?
+        .check_date(booking.travel_date)
+}
+
+pub fn sell_pass(
+    passengers: &mut PassengerList,
+    passenger_id: &str,
+    kind: PassKind,
+    origin: String,
+    destination: String,
+    valid_from: NaiveDate,
+    price: u32,
+) -> Result<String> {
+    let passenger = passengers
+        .get_mut(passenger_id)
+        .ok_or_else(|| anyhow!("Passenger not found"))?;
+    let id = (1..)
+        .map(|number| format!("{}_PASS{}", passenger_id, number))
+        .find(|id| !passenger.passes.iter().any(|pass| &pass.id == id))
+        .unwrap();
+    passenger
+        .passes
+        .push(Pass::new(id.clone(), kind, origin, destination, valid_from, price));
+    Ok(id)
+}
+
+fn print_pass(
+    pass: &Pass,
+    passenger: &Passenger,
+) {
+    println!(
+        "{}: {} ({}), {} {} to {}, valid {} to {}, {}{}",
+        pass.id,
+        passenger.name,
+        passenger.id,
+        pass.kind,
+        pass.origin,
+        pass.destination,
+        pass.valid_from,
+        pass.valid_until,
+        format_money(pass.price),
+        pass.rides_left()
+            .map(|left| format!(", {} rides left", left))
+            .unwrap_or_default()
+    );
+}
+
+fn select_pass(
+    passengers: &PassengerList,
+    message: &str,
+) -> Result<(String, String)> {
+    let mut choices: Vec<String> = passengers
+        .values()
+        .flat_map(|passenger| {
+            passenger
+                .passes
+                .iter()
+                .map(move |pass| format!("{}, {}, {}", pass.id, passenger.id, passenger.name))
+        })
+        .collect();
+    choices.sort();
+    let selection = requestty::prompt_one(Question::select("pass").message(message).choices(choices).build())?;
+    let mut fields = selection.as_list_item().unwrap().text.split(',').map(str::trim);
+    let pass_id = fields.next().unwrap().to_string();
+    let passenger_id = fields.next().unwrap().to_string();
+    Ok((passenger_id, pass_id))
+}
+
+/// Sells a pass and charges for it in the ledger under the pass ID.
+fn add_pass(
+    passengers: &mut PassengerList,
+    trains: &TrainList,
+    ledger: &mut Ledger,
+) -> Result<()> {
+    let mut passenger_choices: Vec<String> = passengers
+        .values()
+        .map(|passenger| format!("{}, {}", passenger.id, passenger.name))
+        .collect();
+    passenger_choices.sort();
+    let mut routes: Vec<(String, String)> = trains
+        .values()
+        .map(|train| (train.origin.clone(), train.destination.clone()))
+        .collect();
+    routes.sort();
+    routes.dedup();
+    let methods: Vec<PaymentMethod> = PaymentMethod::ALL
+        .into_iter()
+        .filter(|method| {
+            !matches!(
+                method,
+                PaymentMethod::Points | PaymentMethod::Voucher | PaymentMethod::Account
+            )
+        })
+        .collect();
+
+    let answers = requestty::prompt(vec![
+        Question::select("passenger")
+            .message("Select passenger")
+            .choices(passenger_choices)
+            .build(),
+        Question::select("route")
+            .message("Select route")
+            .choices(
+                routes
+                    .iter()
+                    .map(|(origin, destination)| format!("{} to {}", origin, destination)),
+            )
+            .build(),
+        Question::select("kind")
+            .message("Select pass type")
+            .choice(format!("Unlimited for {} month", UNLIMITED_VALID_MONTHS))
+            .choice(format!("Carnet, valid for {} months", CARNET_VALID_MONTHS))
+            .build(),
+        Question::input("rides")
+            .message("Enter the number of rides")
+            .default("10")
+            .when(|answers: &Answers| answers["kind"].as_list_item().unwrap().index == 1)
+            .build(),
+        Question::input("valid_from")
+            .message("Enter the first day of the pass (YYYY-MM-DD)")
+            .default(Local::now().date_naive().to_string())
+            .build(),
+        Question::input("price")
+            .message("Enter the price of the pass (e.g. 150.00)")
+            .build(),
+        Question::select("payment_method")
+            .message("Select payment method")
+            .choices(methods.iter().map(|method| method.to_string()))
+            .build(),
+    ])?;
+
+    let passenger_id = answers["passenger"]
+        .as_list_item()
+        .unwrap()
+        .text
+        .split(',')
+        .next()
+        .unwrap()
+        .trim()
+        .to_string();
+    let (origin, destination) = routes[answers["route"].as_list_item().unwrap().index].clone();
+    let kind = match answers["kind"].as_list_item().unwrap().index {
+        0 => PassKind::Unlimited,
+        _ => PassKind::Carnet(
+            answers["rides"]
+                .as_string()
+                .unwrap()
+                .trim()
+                .parse()
+                .context("Invalid number of rides")?,
+        ),
+    };
+    let valid_from = parse_date(answers["valid_from"].as_string().unwrap())?;
+    let price = parse_money(answers["price"].as_string().unwrap())?;
+    let method = methods[answers["payment_method"].as_list_item().unwrap().index];
+
+    let pass_id = sell_pass(passengers, &passenger_id, kind, origin, destination, valid_from, price)?;
+    if let Err(e) = ledger.charge(&pass_id, price, method, Local::now().naive_local()) {
+        passengers
+            .get_mut(&passenger_id)
+            .unwrap()
+            .passes
+            .retain(|pass| pass.id != pass_id);
+        return Err(e);
+    }
+    println!("Sold pass {}, charged {} by {}", pass_id, format_money(price), method);
+    Ok(())
+}
+
+fn edit_pass(passengers: &mut PassengerList) -> Result<()> {
+    let (passenger_id, pass_id) = select_pass(passengers, "Select pass to edit")?;
+    let pass = passengers
+        .get_mut(&passenger_id)
+        .and_then(|passenger| passenger.passes.iter_mut().find(|pass| pass.id == pass_id))
+        .unwrap();
+
+    let mut questions = vec![Question::input("valid_until")
+        .message("Enter the last day of the pass (YYYY-MM-DD)")
+        .default(pass.valid_until.to_string())
+        .build()];
+    if let PassKind::Carnet(rides) = pass.kind {
+        questions.push(
+            Question::input("rides")
+                .message("Enter the number of rides")
+                .default(rides.to_string())
+                .build(),
+        );
+    }
+    let answers = requestty::prompt(questions)?;
+
+    let valid_until = parse_date(answers["valid_until"].as_string().unwrap())?;
+    if valid_until < pass.valid_from {
+        return Err(anyhow!("Pass {} cannot end before {}", pass.id, pass.valid_from));
+    }
+    if let Some(rides) = answers.get("rides") {
+        let rides: u32 = rides
+            .as_string()
+            .unwrap()
+            .trim()
+            .parse()
+            .context("Invalid number of rides")?;
+        if rides < pass.rides_used {
+            return Err(anyhow!(
+                "Pass {} has already been used for {} rides",
+                pass.id,
+                pass.rides_used
+            ));
+        }
+        pass.kind = PassKind::Carnet(rides);
+    }
+    pass.valid_until = valid_until;
+    Ok(())
+}
+
+/// Cancels a pass that no upcoming booking uses. Refunds are left to the agent.
+fn remove_pass(
+    passengers: &mut PassengerList,
+    bookings: &BookingList,
+) -> Result<()> {
+    let (passenger_id, pass_id) = select_pass(passengers, "Select pass to cancel")?;
+    if let Some(booking) = bookings
+        .values()
+        .find(|booking| booking.pass.as_deref() == Some(pass_id.as_str()) && booking.status == BookingStatus::Booked)
+    {
+        return Err(anyhow!("Pass {} is still used by booking {}", pass_id, booking.id));
+    }
+    passengers
+        .get_mut(&passenger_id)
+        .unwrap()
+        .passes
+        .retain(|pass| pass.id != pass_id);
+    println!("Cancelled pass {}", pass_id);
+    Ok(())
+}
+
+pub fn manage_passes(
+    passengers: &mut PassengerList,
+    trains: &TrainList,
+    bookings: &BookingList,
+    ledger: &mut Ledger,
+) -> Result<()> {
+    loop {
+        clear_screen();
+        let has_passes = passengers.values().any(|passenger| !passenger.passes.is_empty());
+        match add_remove_prompt()? {
+            Action::List => {
+                if !has_passes {
+                    println!("No passes found");
+                }
+                let mut owners: Vec<&Passenger> = passengers.values().collect();
+                owners.sort_by(|a, b| a.id.cmp(&b.id));
+                for passenger in owners {
+                    for pass in &passenger.passes {
+                        print_pass(pass, passenger);
+                    }
+                }
+                continue_prompt();
+            }
+            Action::Add => {
+                if passengers.is_empty() || trains.is_empty() {
+                    println!("Add passengers and trains before selling passes");
+                } else if let Err(e) = add_pass(passengers, trains, ledger) {
+                    println!("Error: {}", e);
+                }
+                continue_prompt();
+            }
+            Action::Edit => {
+                if !has_passes {
+                    println!("No passes to edit");
+                    continue_prompt();
+                } else if let Err(e) = edit_pass(passengers) {
+                    println!("Error: {}", e);
+                    continue_prompt();
+                }
+            }
+            Action::Remove => {
+                if !has_passes {
+                    println!("No passes to cancel");
+                } else if let Err(e) = remove_pass(passengers, bookings) {
+                    println!("Error: {}", e);
+                }
+                continue_prompt();
+            }
+            Action::Back => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::{add_booking, edit_booking, remove_booking};
+    use crate::minors::Supervision;
+    use crate::passengers::approximate_date_of_birth;
+    use crate::rules::RulePipeline;
+    use crate::test_support::travel_date;
+    use chrono::NaiveTime;
+
+    fn setup() -> (BookingList, PassengerList, TrainList) {
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+
+        passengers.insert(
+            "P1".to_string(),
+            Passenger::new(
+                "P1".to_string(),
+                "John Doe".to_string(),
+                approximate_date_of_birth(30, travel_date()),
+            ),
+        );
+        for (line, origin, destination, departure) in [
+            (1, "Toronto", "Hamilton", 7),
+            (2, "Hamilton", "Toronto", 17),
+            (3, "Toronto", "Ottawa", 9),
+        ] {
+            let mut train = Train::new(
+                line,
+                format!("Train {}", line),
+                100,
+                origin.to_string(),
+                destination.to_string(),
+                NaiveTime::from_hms_opt(departure, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(departure + 1, 0, 0).unwrap(),
+            );
+            train.fare = 1500;
+            trains.insert(line, train);
+        }
+
+        (BookingList::new(), passengers, trains)
+    }
+
+    fn book(
+        bookings: &mut BookingList,
+        passengers: &mut PassengerList,
+        trains: &mut TrainList,
+        line: u32,
+        date: NaiveDate,
+    ) -> String {
+        add_booking(
+            bookings,
+            passengers,
+            trains,
+            &RulePipeline::default(),
+            "P1".to_string(),
+            line,
+            date,
+            Supervision::Alone,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_pass_validity() {
+        let (_, mut passengers, trains) = setup();
+        let first_day = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let pass_id = sell_pass(
+            &mut passengers,
+            "P1",
+            PassKind::Unlimited,
+            "Toronto".to_string(),
+            "Hamilton".to_string(),
+            first_day,
+            15000,
+        )
+        .unwrap();
+        let pass = &passengers["P1"].passes[0];
+        assert_eq!(pass_id, "P1_PASS1");
+        assert_eq!(pass.valid_until, NaiveDate::from_ymd_opt(2024, 6, 30).unwrap());
+
+        assert!(pass.check(&trains[&1], travel_date()).is_ok());
+        assert!(pass.check(&trains[&2], travel_date()).is_ok());
+        assert_eq!(
+            pass.check(&trains[&3], travel_date()).unwrap_err().to_string(),
+            "Pass P1_PASS1 covers Toronto to Hamilton, not train 3 from Toronto to Ottawa"
+        );
+        assert_eq!(
+            pass.check(&trains[&1], NaiveDate::from_ymd_opt(2024, 7, 1).unwrap())
+                .unwrap_err()
+                .to_string(),
+            "Pass P1_PASS1 is valid from 2024-06-01 to 2024-06-30, not on 2024-07-01"
+        );
+    }
+
+    #[test]
+    fn test_carnet_rides() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        sell_pass(
+            &mut passengers,
+            "P1",
+            PassKind::Carnet(1),
+            "Toronto".to_string(),
+            "Hamilton".to_string(),
+            travel_date(),
+            2500,
+        )
+        .unwrap();
+
+        let outbound = book(&mut bookings, &mut passengers, &mut trains, 1, travel_date());
+        let pass_id = find_pass(&passengers["P1"], &trains[&1], travel_date())
+            .unwrap()
+            .id
+            .clone();
+        use_pass(&mut bookings, &mut passengers, &mut trains, &outbound, &pass_id).unwrap();
+        assert_eq!(bookings[&outbound].total(), 0);
+        assert_eq!(passengers["P1"].passes[0].rides_left(), Some(0));
+        assert!(find_pass(&passengers["P1"], &trains[&2], travel_date()).is_none());
+
+        let back = book(&mut bookings, &mut passengers, &mut trains, 2, travel_date());
+        assert_eq!(
+            use_pass(&mut bookings, &mut passengers, &mut trains, &back, &pass_id)
+                .unwrap_err()
+                .to_string(),
+            "Pass P1_PASS1 has no rides left"
+        );
+        assert_eq!(bookings[&back].total(), 1500);
+
+        // Moving the trip keeps it on the pass, cancelling gives the ride back
+        edit_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            outbound.clone(),
+            1,
+            travel_date().succ_opt().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(bookings[&outbound].total(), 0);
+        assert!(edit_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &RulePipeline::default(),
+            outbound.clone(),
+            3,
+            travel_date().succ_opt().unwrap(),
+        )
+        .is_err());
+        remove_booking(&mut bookings, &mut passengers, &mut trains, outbound).unwrap();
+        assert_eq!(passengers["P1"].passes[0].rides_left(), Some(1));
+    }
+
+    #[test]
+    fn test_pass_on_the_same_train_on_consecutive_days() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let pass_id = sell_pass(
+            &mut passengers,
+            "P1",
+            PassKind::Carnet(2),
+            "Toronto".to_string(),
+            "Hamilton".to_string(),
+            travel_date(),
+            2500,
+        )
+        .unwrap();
+        let next_day = travel_date().succ_opt().unwrap();
+
+        let monday = book(&mut bookings, &mut passengers, &mut trains, 1, travel_date());
+        use_pass(&mut bookings, &mut passengers, &mut trains, &monday, &pass_id).unwrap();
+        let tuesday = book(&mut bookings, &mut passengers, &mut trains, 1, next_day);
+        use_pass(&mut bookings, &mut passengers, &mut trains, &tuesday, &pass_id).unwrap();
+
+        assert_ne!(monday, tuesday);
+        assert_eq!(bookings[&monday].total(), 0);
+        assert_eq!(bookings[&tuesday].total(), 0);
+        assert_eq!(passengers["P1"].passes[0].rides_left(), Some(0));
+
+        // Cancelling one day gives back only that day's ride
+        remove_booking(&mut bookings, &mut passengers, &mut trains, tuesday).unwrap();
+        assert_eq!(passengers["P1"].passes[0].rides_left(), Some(1));
+        assert_eq!(bookings[&monday].pass.as_deref(), Some(pass_id.as_str()));
+    }
+
+    #[test]
+    fn test_pass_checked_at_boarding() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let pass_id = sell_pass(
+            &mut passengers,
+            "P1",
+            PassKind::Unlimited,
+            "Toronto".to_string(),
+            "Hamilton".to_string(),
+            travel_date(),
+            15000,
+        )
+        .unwrap();
+        let booking_id = book(&mut bookings, &mut passengers, &mut trains, 1, travel_date());
+        use_pass(&mut bookings, &mut passengers, &mut trains, &booking_id, &pass_id).unwrap();
+        assert!(check_pass_at_boarding(&passengers, &bookings[&booking_id]).is_ok());
+
+        passengers.get_mut("P1").unwrap().passes.clear();
+        assert_eq!(
+            check_pass_at_boarding(&passengers, &bookings[&booking_id])
+                .unwrap_err()
+                .to_string(),
+            "Pass P1_PASS1 for booking P1_1_20240610 has been cancelled"
+        );
+    }
+}
@@ -0,0 +1,554 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use std::collections::HashMap;
+
+use crate::corporate::{next_month, CorporateAccount};
+use crate::helper::format_money;
+use crate::vouchers::{VoucherBook, VoucherKind};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaymentMethod {
+    Card,
+    Cash,
+    BankTransfer,
+    Points,  // Loyalty points, settled by the loyalty programme instead of the processor
+    Voucher, // Voucher, travel credit or gift card balance, settled by the voucher book
+    Account, // Billed to a corporate account and settled by its monthly invoice
+}
+
+impl PaymentMethod {
+    pub const ALL: [PaymentMethod; 6] = [
+        PaymentMethod::Card,
+        PaymentMethod::Cash,
+        PaymentMethod::BankTransfer,
+        PaymentMethod::Points,
+        PaymentMethod::Voucher,
+        PaymentMethod::Account,
+    ];
+}
+
+impl std::fmt::Display for PaymentMethod {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            PaymentMethod::Card => write!(f, "Card"),
+            PaymentMethod::Cash => write!(f, "Cash"),
+            PaymentMethod::BankTransfer => write!(f, "Bank transfer"),
+            PaymentMethod::Points => write!(f, "Loyalty points"),
+            PaymentMethod::Voucher => write!(f, "Voucher"),
+            PaymentMethod::Account => write!(f, "Corporate account"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryKind {
+    Charge,
+    Refund,
+}
+
+pub struct LedgerEntry {
+    pub booking_id: String,
+    pub kind: EntryKind,
+    pub amount: u32, // Amount in cents
+    pub method: PaymentMethod,
+    pub reference: String, // Reference returned by the payment processor, the voucher code or the account ID
+    pub timestamp: NaiveDateTime,
+    pub charge: Option<usize>, // For a refund, the index of the charge entry it gives back
+}
+
+/// Moves money for the ledger. Implementations return a processor reference for each transaction.
+pub trait PaymentProcessor {
+    fn charge(
+        &mut self,
+        amount: u32,
+        method: PaymentMethod,
+    ) -> Result<String>;
+
+    fn refund(
+        &mut self,
+        charge_reference: &str,
+        amount: u32,
+    ) -> Result<String>;
+}
+
+/// An offline processor that approves everything unless told to decline, for exercising the
+/// payment flow without a real gateway.
+#[derive(Default)]
+pub struct MockProcessor {
+    pub decline: bool,
+    transactions: u32,
+}
+
+impl MockProcessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn next_reference(&mut self) -> String {
+        self.transactions += 1;
+        format!("MOCK-{:06}", self.transactions)
+    }
+}
+
+impl PaymentProcessor for MockProcessor {
+    fn charge(
+        &mut self,
+        _amount: u32,
+        _method: PaymentMethod,
+    ) -> Result<String> {
+        if self.decline {
+            return Err(anyhow!("Payment declined by the mock processor"));
+        }
+        Ok(self.next_reference())
+    }
+
+    fn refund(
+        &mut self,
+        _charge_reference: &str,
+        _amount: u32,
+    ) -> Result<String> {
+        if self.decline {
+            return Err(anyhow!("Refund declined by the mock processor"));
+        }
+        Ok(self.next_reference())
+    }
+}
+
+pub struct Ledger {
+    pub entries: Vec<LedgerEntry>,
+    pub vouchers: VoucherBook,
+    invoice_numbers: HashMap<String, u32>, // Invoice number issued for each booking
+    processor: Box<dyn PaymentProcessor>,
+}
+
+impl Ledger {
+    pub fn new(processor: Box<dyn PaymentProcessor>) -> Self {
+        Self {
+            entries: Vec::new(),
+            vouchers: VoucherBook::default(),
+            invoice_numbers: HashMap::new(),
+            processor,
+        }
+    }
+
+    /// Returns the booking's invoice number, issuing the next one in sequence the first time so
+    /// reprints keep their original number.
+    pub fn invoice_number(
+        &mut self,
+        booking_id: &str,
+    ) -> u32 {
+        let next = self.invoice_numbers.len() as u32 + 1;
+        *self.invoice_numbers.entry(booking_id.to_string()).or_insert(next)
+    }
+
+    /// Reference for a points payment, which never reaches the processor.
+    fn points_reference(&self) -> String {
+        format!("POINTS-{:06}", self.entries.len() + 1)
+    }
+
+    pub fn charge(
+        &mut self,
+        booking_id: &str,
+        amount: u32,
+        method: PaymentMethod,
+        timestamp: NaiveDateTime,
+    ) -> Result<()> {
+        let reference = match method {
+            PaymentMethod::Points => self.points_reference(),
+            PaymentMethod::Voucher => return Err(anyhow!("Voucher payments need a voucher code")),
+            PaymentMethod::Account => return Err(anyhow!("Corporate account charges need an account")),
+            _ => self.processor.charge(amount, method)?,
+        };
+        self.entries.push(LedgerEntry {
+            booking_id: booking_id.to_string(),
+            kind: EntryKind::Charge,
+            amount,
+            method,
+            reference,
+            timestamp,
+            charge: None,
+        });
+        Ok(())
+    }
+
+    /// Pays as much of the amount as the voucher's balance covers and returns what was taken,
+    /// leaving the rest to be charged another way.
+    pub fn pay_with_voucher(
+        &mut self,
+        booking_id: &str,
+        code: &str,
+        amount: u32,
+        timestamp: NaiveDateTime,
+    ) -> Result<u32> {
+        let taken = self.vouchers.redeem(code, amount, timestamp.date())?;
+        self.entries.push(LedgerEntry {
+            booking_id: booking_id.to_string(),
+            kind: EntryKind::Charge,
+            amount: taken,
+            method: PaymentMethod::Voucher,
+            reference: code.trim().to_uppercase(),
+            timestamp,
+            charge: None,
+        });
+        Ok(taken)
+    }
+
+    /// Net amount billed to a corporate account in the month of the given date.
+    pub fn billed_to(
+        &self,
+        account_id: &str,
+        month: NaiveDate,
+    ) -> i64 {
+        let start = month.with_day(1).unwrap();
+        let end = next_month(start);
+        self.entries
+            .iter()
+            .filter(|entry| entry.method == PaymentMethod::Account && entry.reference == account_id)
+            .filter(|entry| entry.timestamp.date() >= start && entry.timestamp.date() < end)
+            .map(|entry| match entry.kind {
+                EntryKind::Charge => i64::from(entry.amount),
+                EntryKind::Refund => -i64::from(entry.amount),
+            })
+            .sum()
+    }
+
+    /// Bills an amount to a corporate account, to be settled by the account's monthly invoice.
+    /// Nothing is billed if it would take the month over the account's credit limit.
+    pub fn bill_account(
+        &mut self,
+        booking_id: &str,
+        account: &CorporateAccount,
+        amount: u32,
+        timestamp: NaiveDateTime,
+    ) -> Result<()> {
+        let billed = self.billed_to(&account.id, timestamp.date()).max(0);
+        if account.credit_limit > 0 && billed + i64::from(amount) > i64::from(account.credit_limit) {
+            return Err(anyhow!(
+                "Account {} has {} of its {} monthly credit limit left",
+                account.id,
+                format_money(u32::try_from(i64::from(account.credit_limit) - billed).unwrap_or(0)),
+                format_money(account.credit_limit)
+            ));
+        }
+        self.entries.push(LedgerEntry {
+            booking_id: booking_id.to_string(),
+            kind: EntryKind::Charge,
+            amount,
+            method: PaymentMethod::Account,
+            reference: account.id.clone(),
+            timestamp,
+            charge: None,
+        });
+        Ok(())
+    }
+
+    fn check_refundable(
+        &self,
+        booking_id: &str,
+        amount: u32,
+    ) -> Result<()> {
+        let balance = self.balance(booking_id);
+        if i64::from(amount) > balance {
+            return Err(anyhow!(
+                "Cannot refund {}, booking {} has a balance of {}",
+                format_money(amount),
+                booking_id,
+                format_money(balance.max(0) as u32)
+            ));
+        }
+        Ok(())
+    }
+
+    /// What is still left to refund on each of the booking's charges, newest charge first.
+    fn refundable_charges(
+        &self,
+        booking_id: &str,
+    ) -> Vec<(usize, u32)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, entry)| entry.booking_id == booking_id && entry.kind == EntryKind::Charge)
+            .map(|(index, entry)| {
+                let refunded: u32 = self
+                    .entries
+                    .iter()
+                    .filter(|refund| refund.charge == Some(index))
+                    .map(|refund| refund.amount)
+                    .sum();
+                (index, entry.amount.saturating_sub(refunded))
+            })
+            .filter(|(_, left)| *left > 0)
+            .collect()
+    }
+
+    /// Refunds up to the booking's balance, giving each charge back to the method that paid it,
+    /// newest charge first. Returns how much went back to each method.
+    pub fn refund(
+        &mut self,
+        booking_id: &str,
+        amount: u32,
+        timestamp: NaiveDateTime,
+    ) -> Result<Vec<(PaymentMethod, u32)>> {
+        self.check_refundable(booking_id, amount)?;
+        let mut refunded = Vec::new();
+        let mut remaining = amount;
+        for (index, left) in self.refundable_charges(booking_id) {
+            if remaining == 0 {
+                break;
+            }
+            let part = remaining.min(left);
+            let charge = &self.entries[index];
+            let method = charge.method;
+            let reference = match method {
+                PaymentMethod::Points => self.points_reference(),
+                PaymentMethod::Voucher => {
+                    let code = charge.reference.clone();
+                    self.vouchers.credit(&code, part)?;
+                    code
+                }
+                PaymentMethod::Account => charge.reference.clone(),
+                _ => self.processor.refund(&charge.reference, part)?,
+            };
+            self.entries.push(LedgerEntry {
+                booking_id: booking_id.to_string(),
+                kind: EntryKind::Refund,
+                amount: part,
+                method,
+                reference,
+                timestamp,
+                charge: Some(index),
+            });
+            refunded.push((method, part));
+            remaining -= part;
+        }
+        Ok(refunded)
+    }
+
+    /// Refunds up to the booking's balance as travel credit for the passenger instead of back to
+    /// the original payment, and returns the code of the credit issued.
+    pub fn refund_as_credit(
+        &mut self,
+        booking_id: &str,
+        passenger_id: &str,
+        amount: u32,
+        timestamp: NaiveDateTime,
+    ) -> Result<String> {
+        self.check_refundable(booking_id, amount)?;
+        let code = self.vouchers.issue(
+            VoucherKind::Credit,
+            amount,
+            Some(passenger_id.to_string()),
+            Some(booking_id.to_string()),
+            timestamp.date(),
+        );
+        // The credit stands in for the newest charges, so they cannot be refunded again
+        let mut remaining = amount;
+        for (index, left) in self.refundable_charges(booking_id) {
+            if remaining == 0 {
+                break;
+            }
+            let part = remaining.min(left);
+            self.entries.push(LedgerEntry {
+                booking_id: booking_id.to_string(),
+                kind: EntryKind::Refund,
+                amount: part,
+                method: PaymentMethod::Voucher,
+                reference: code.clone(),
+                timestamp,
+                charge: Some(index),
+            });
+            remaining -= part;
+        }
+        Ok(code)
+    }
+
+    /// The method of the booking's most recent charge, used to collect later fees the same way.
+    pub fn charge_method(
+        &self,
+        booking_id: &str,
+    ) -> Option<PaymentMethod> {
+        self.entries_for(booking_id)
+            .filter(|entry| entry.kind == EntryKind::Charge)
+            .last()
+            .map(|entry| entry.method)
+    }
+
+    /// Amount charged minus amount refunded for a booking, in cents.
+    pub fn balance(
+        &self,
+        booking_id: &str,
+    ) -> i64 {
+        self.entries_for(booking_id)
+            .map(|entry| match entry.kind {
+                EntryKind::Charge => i64::from(entry.amount),
+                EntryKind::Refund => -i64::from(entry.amount),
+            })
+            .sum()
+    }
+
+    pub fn entries_for<'a>(
+        &'a self,
+        booking_id: &'a str,
+    ) -> impl Iterator<Item = &'a LedgerEntry> + 'a {
+        self.entries.iter().filter(move |entry| entry.booking_id == booking_id)
+    }
+}
+
+pub fn list_ledger(ledger: &Ledger) {
+    if ledger.entries.is_empty() {
+        println!("No payments recorded");
+        return;
+    }
+
+    let mut booking_ids: Vec<&str> = Vec::new();
+    for entry in &ledger.entries {
+        println!(
+            "{} {:?} {} for booking {} via {} ({})",
+            entry.timestamp.format("%Y-%m-%d %H:%M"),
+            entry.kind,
+            format_money(entry.amount),
+            entry.booking_id,
+            entry.method,
+            entry.reference
+        );
+        if !booking_ids.contains(&entry.booking_id.as_str()) {
+            booking_ids.push(&entry.booking_id);
+        }
+    }
+
+    println!("====================");
+    for booking_id in booking_ids {
+        println!(
+            "Booking {} balance: {}",
+            booking_id,
+            format_money(ledger.balance(booking_id).max(0) as u32)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 6, 1)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_charge_and_refund() {
+        let mut ledger = Ledger::new(Box::new(MockProcessor::new()));
+
+        ledger.charge("P1_1", 2500, PaymentMethod::Card, now()).unwrap();
+        assert_eq!(ledger.balance("P1_1"), 2500);
+
+        ledger.refund("P1_1", 1000, now()).unwrap();
+        assert_eq!(ledger.balance("P1_1"), 1500);
+        assert_eq!(ledger.entries[1].kind, EntryKind::Refund);
+        assert_eq!(ledger.entries[1].method, PaymentMethod::Card);
+        assert_eq!(ledger.entries[1].reference, "MOCK-000002");
+
+        ledger.refund("P1_1", 1500, now()).unwrap();
+        assert_eq!(ledger.balance("P1_1"), 0);
+    }
+
+    #[test]
+    fn test_refund_cannot_exceed_balance() {
+        let mut ledger = Ledger::new(Box::new(MockProcessor::new()));
+        ledger.charge("P1_1", 2500, PaymentMethod::Cash, now()).unwrap();
+
+        assert!(ledger.refund("P1_1", 2501, now()).is_err());
+        assert!(ledger.refund("P2_1", 100, now()).is_err());
+        assert_eq!(ledger.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_invoice_numbers_are_sequential() {
+        let mut ledger = Ledger::new(Box::new(MockProcessor::new()));
+
+        assert_eq!(ledger.invoice_number("P1_1"), 1);
+        assert_eq!(ledger.invoice_number("P2_1"), 2);
+        assert_eq!(ledger.invoice_number("P1_1"), 1);
+        assert_eq!(ledger.invoice_number("P3_2"), 3);
+    }
+
+    #[test]
+    fn test_points_skip_the_processor() {
+        let mut processor = MockProcessor::new();
+        processor.decline = true;
+        let mut ledger = Ledger::new(Box::new(processor));
+
+        ledger.charge("P1_1", 2500, PaymentMethod::Points, now()).unwrap();
+        ledger.refund("P1_1", 2500, now()).unwrap();
+        assert_eq!(ledger.entries[0].reference, "POINTS-000001");
+        assert_eq!(ledger.entries[1].method, PaymentMethod::Points);
+        assert_eq!(ledger.balance("P1_1"), 0);
+    }
+
+    #[test]
+    fn test_vouchers_skip_the_processor() {
+        let mut processor = MockProcessor::new();
+        processor.decline = true;
+        let mut ledger = Ledger::new(Box::new(processor));
+        let code = ledger
+            .vouchers
+            .issue(VoucherKind::GiftCard, 1000, None, None, now().date());
+
+        assert!(ledger.charge("P1_1", 2500, PaymentMethod::Voucher, now()).is_err());
+        assert_eq!(ledger.pay_with_voucher("P1_1", &code, 2500, now()).unwrap(), 1000);
+        assert_eq!(ledger.entries[0].reference, code);
+        assert_eq!(ledger.vouchers.get(&code).unwrap().balance, 0);
+
+        ledger.refund("P1_1", 400, now()).unwrap();
+        assert_eq!(ledger.vouchers.get(&code).unwrap().balance, 400);
+        assert_eq!(ledger.balance("P1_1"), 600);
+
+        let credit = ledger.refund_as_credit("P1_1", "P1", 600, now()).unwrap();
+        assert_eq!(ledger.entries[2].method, PaymentMethod::Voucher);
+        assert_eq!(ledger.vouchers.get(&credit).unwrap().balance, 600);
+        assert_eq!(ledger.balance("P1_1"), 0);
+        assert!(ledger.refund_as_credit("P1_1", "P1", 1, now()).is_err());
+    }
+
+    #[test]
+    fn test_split_payment_refunds_each_part_to_its_method() {
+        let mut ledger = Ledger::new(Box::new(MockProcessor::new()));
+        let code = ledger
+            .vouchers
+            .issue(VoucherKind::GiftCard, 1000, None, None, now().date());
+        assert_eq!(ledger.pay_with_voucher("P1_1", &code, 2500, now()).unwrap(), 1000);
+        ledger.charge("P1_1", 1500, PaymentMethod::Card, now()).unwrap();
+
+        // The card is refunded first and only up to what it paid, the rest goes back on the voucher
+        assert_eq!(
+            ledger.refund("P1_1", 2000, now()).unwrap(),
+            vec![(PaymentMethod::Card, 1500), (PaymentMethod::Voucher, 500)]
+        );
+        assert_eq!(ledger.vouchers.get(&code).unwrap().balance, 500);
+        assert_eq!(
+            ledger.refund("P1_1", 500, now()).unwrap(),
+            vec![(PaymentMethod::Voucher, 500)]
+        );
+        assert_eq!(ledger.vouchers.get(&code).unwrap().balance, 1000);
+        assert_eq!(ledger.balance("P1_1"), 0);
+        assert!(ledger.refund("P1_1", 1, now()).is_err());
+    }
+
+    #[test]
+    fn test_declined_charge_is_not_recorded() {
+        let mut processor = MockProcessor::new();
+        processor.decline = true;
+        let mut ledger = Ledger::new(Box::new(processor));
+
+        assert!(ledger.charge("P1_1", 2500, PaymentMethod::Card, now()).is_err());
+        assert!(ledger.entries.is_empty());
+        assert_eq!(ledger.balance("P1_1"), 0);
+    }
+}
@@ -0,0 +1,363 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use requestty::{self, Answers, Question};
+
+use crate::actions::{add_remove_prompt, Action};
+use crate::helper::{clear_screen, continue_prompt, format_money, parse_money};
+
+/// A fee rule applies from `min_hours` before departure until the next rule with fewer hours.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PolicyRule {
+    pub fare_bucket: Option<String>, // None applies to any fare without rules of its own
+    pub min_hours: u32,
+    pub refund_percent: u32, // Percentage of the amount paid refunded on cancellation
+    pub change_fee: u32,     // Fee in cents for moving to another train
+}
+
+impl std::fmt::Display for PolicyRule {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(
+            f,
+            "{} fares, {}h+ before departure: {}% refund, {} change fee",
+            self.fare_bucket.as_deref().unwrap_or("All"),
+            self.min_hours,
+            self.refund_percent,
+            format_money(self.change_fee)
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PolicyOutcome {
+    pub hours_before: i64,
+    pub refund: u32, // Amount refunded on cancellation in cents
+    pub fee: u32,    // Cancellation or change fee in cents
+    pub rule: Option<PolicyRule>,
+}
+
+impl std::fmt::Display for PolicyOutcome {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(
+            f,
+            "{}h before departure: refund {}, fee {} ({})",
+            self.hours_before,
+            format_money(self.refund),
+            format_money(self.fee),
+            self.rule
+                .as_ref()
+                .map(|rule| rule.to_string())
+                .unwrap_or_else(|| "no rule applies".to_string())
+        )
+    }
+}
+
+pub struct FeePolicy {
+    pub rules: Vec<PolicyRule>,
+}
+
+impl Default for FeePolicy {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                PolicyRule {
+                    fare_bucket: None,
+                    min_hours: 48,
+                    refund_percent: 100,
+                    change_fee: 0,
+                },
+                PolicyRule {
+                    fare_bucket: None,
+                    min_hours: 24,
+                    refund_percent: 50,
+                    change_fee: 500,
+                },
+                PolicyRule {
+                    fare_bucket: None,
+                    min_hours: 0,
+                    refund_percent: 0,
+                    change_fee: 1000,
+                },
+            ],
+        }
+    }
+}
+
+impl FeePolicy {
+    /// Finds the rule for a fare type. Rules for the booking's fare bucket take precedence over the
+    /// rules that apply to all fares.
+    fn rule_for(
+        &self,
+        fare_bucket: Option<&str>,
+        minutes_before: i64,
+    ) -> Option<&PolicyRule> {
+        let has_own_rules = fare_bucket.is_some_and(|bucket| {
+            self.rules
+                .iter()
+                .any(|rule| rule.fare_bucket.as_deref() == Some(bucket))
+        });
+        self.rules
+            .iter()
+            .filter(|rule| {
+                if has_own_rules {
+                    rule.fare_bucket.as_deref() == fare_bucket
+                } else {
+                    rule.fare_bucket.is_none()
+                }
+            })
+            .filter(|rule| i64::from(rule.min_hours) * 60 <= minutes_before)
+            .max_by_key(|rule| rule.min_hours)
+    }
+
+    pub fn cancellation(
+        &self,
+        fare_bucket: Option<&str>,
+        paid: u32,
+        departure: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> Result<PolicyOutcome> {
+        let minutes_before = (departure - now).num_minutes();
+        if minutes_before < 0 {
+            return Err(anyhow!("The train has already departed"));
+        }
+
+        let rule = self.rule_for(fare_bucket, minutes_before);
+        let refund = rule.map_or(0, |rule| paid * rule.refund_percent.min(100) / 100);
+        Ok(PolicyOutcome {
+            hours_before: minutes_before / 60,
+            refund,
+            fee: paid - refund,
+            rule: rule.cloned(),
+        })
+    }
+
+    pub fn change(
+        &self,
+        fare_bucket: Option<&str>,
+        departure: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> Result<PolicyOutcome> {
+        let minutes_before = (departure - now).num_minutes();
+        if minutes_before < 0 {
+            return Err(anyhow!("The train has already departed"));
+        }
+
+        let rule = self
+            .rule_for(fare_bucket, minutes_before)
+            .ok_or_else(|| anyhow!("Changes are not allowed this close to departure"))?;
+        Ok(PolicyOutcome {
+            hours_before: minutes_before / 60,
+            refund: 0,
+            fee: rule.change_fee,
+            rule: Some(rule.clone()),
+        })
+    }
+}
+
+pub fn manage_fee_policy(policy: &mut FeePolicy) -> Result<()> {
+    loop {
+        clear_screen();
+        match add_remove_prompt()? {
+            Action::List => {
+                if policy.rules.is_empty() {
+                    println!("No fee rules found, cancellations are not refunded");
+                } else {
+                    for rule in &policy.rules {
+                        println!("{}", rule);
+                    }
+                }
+                continue_prompt();
+            }
+            Action::Add => {
+                let answers = requestty::prompt(rule_questions(None))?;
+                match parse_rule(&answers) {
+                    Ok(rule) => policy.rules.push(rule),
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        continue_prompt();
+                    }
+                }
+            }
+            Action::Remove => {
+                if policy.rules.is_empty() {
+                    println!("No fee rules to remove");
+                    continue_prompt();
+                } else {
+                    let index = select_rule(policy, "Select fee rule to delete")?;
+                    policy.rules.remove(index);
+                }
+            }
+            Action::Edit => {
+                if policy.rules.is_empty() {
+                    println!("No fee rules to edit");
+                    continue_prompt();
+                } else {
+                    let index = select_rule(policy, "Select fee rule to edit")?;
+                    let answers = requestty::prompt(rule_questions(Some(&policy.rules[index])))?;
+                    match parse_rule(&answers) {
+                        Ok(rule) => policy.rules[index] = rule,
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            continue_prompt();
+                        }
+                    }
+                }
+            }
+            Action::Back => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn select_rule(
+    policy: &FeePolicy,
+    message: &str,
+) -> Result<usize> {
+    let choices: Vec<String> = policy.rules.iter().map(|rule| rule.to_string()).collect();
+    let selection = requestty::prompt_one(Question::select("rule").message(message).choices(choices).build())?;
+    Ok(selection.as_list_item().unwrap().index)
+}
+
+fn rule_questions(rule: Option<&PolicyRule>) -> Vec<Question<'static>> {
+    vec![
+        Question::input("fare_bucket")
+            .message("Enter the fare bucket the rule applies to (blank for all fares)")
+            .default(rule.and_then(|rule| rule.fare_bucket.clone()).unwrap_or_default())
+            .build(),
+        Question::input("min_hours")
+            .message("Enter the minimum hours before departure")
+            .default(rule.map(|rule| rule.min_hours.to_string()).unwrap_or_default())
+            .build(),
+        Question::input("refund_percent")
+            .message("Enter the cancellation refund percentage")
+            .default(rule.map(|rule| rule.refund_percent.to_string()).unwrap_or_default())
+            .build(),
+        Question::input("change_fee")
+            .message("Enter the change fee")
+            .default(rule.map(|rule| format_money(rule.change_fee)).unwrap_or_default())
+            .build(),
+    ]
+}
+
+fn parse_rule(answers: &Answers) -> Result<PolicyRule> {
+    let fare_bucket = answers
+        .get("fare_bucket")
+        .unwrap()
+        .as_string()
+        .unwrap()
+        .trim()
+        .to_uppercase();
+    let refund_percent: u32 = answers
+        .get("refund_percent")
+        .unwrap()
+        .as_string()
+        .unwrap()
+        .trim()
+        .parse()?;
+    if refund_percent > 100 {
+        return Err(anyhow!("Refund percentage cannot exceed 100"));
+    }
+
+    Ok(PolicyRule {
+        fare_bucket: if fare_bucket.is_empty() {
+            None
+        } else {
+            Some(fare_bucket)
+        },
+        min_hours: answers.get("min_hours").unwrap().as_string().unwrap().trim().parse()?,
+        refund_percent,
+        change_fee: parse_money(answers.get("change_fee").unwrap().as_string().unwrap())?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    fn departure() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 6, 10)
+            .unwrap()
+            .and_hms_opt(11, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cancellation_refund_by_time_remaining() {
+        let policy = FeePolicy::default();
+
+        let outcome = policy
+            .cancellation(None, 2000, departure(), departure() - Duration::hours(72))
+            .unwrap();
+        assert_eq!(outcome.refund, 2000);
+        assert_eq!(outcome.fee, 0);
+        assert_eq!(outcome.hours_before, 72);
+
+        let outcome = policy
+            .cancellation(None, 2000, departure(), departure() - Duration::hours(30))
+            .unwrap();
+        assert_eq!(outcome.refund, 1000);
+        assert_eq!(outcome.fee, 1000);
+
+        let outcome = policy
+            .cancellation(None, 2000, departure(), departure() - Duration::minutes(30))
+            .unwrap();
+        assert_eq!(outcome.refund, 0);
+
+        assert!(policy
+            .cancellation(None, 2000, departure(), departure() + Duration::minutes(1))
+            .is_err());
+    }
+
+    #[test]
+    fn test_fare_bucket_rules_take_precedence() {
+        let mut policy = FeePolicy::default();
+        policy.rules.push(PolicyRule {
+            fare_bucket: Some("Q".to_string()),
+            min_hours: 0,
+            refund_percent: 0,
+            change_fee: 2500,
+        });
+        let now = departure() - Duration::hours(72);
+
+        assert_eq!(
+            policy.cancellation(Some("Q"), 1000, departure(), now).unwrap().refund,
+            0
+        );
+        assert_eq!(policy.change(Some("Q"), departure(), now).unwrap().fee, 2500);
+        assert_eq!(
+            policy.cancellation(Some("Y"), 1000, departure(), now).unwrap().refund,
+            1000
+        );
+        assert_eq!(policy.change(None, departure(), now).unwrap().fee, 0);
+    }
+
+    #[test]
+    fn test_change_not_allowed_without_rule() {
+        let policy = FeePolicy {
+            rules: vec![PolicyRule {
+                fare_bucket: None,
+                min_hours: 24,
+                refund_percent: 100,
+                change_fee: 0,
+            }],
+        };
+
+        assert!(policy
+            .change(None, departure(), departure() - Duration::hours(2))
+            .is_err());
+        assert_eq!(
+            policy
+                .cancellation(None, 1500, departure(), departure() - Duration::hours(2))
+                .unwrap()
+                .refund,
+            0
+        );
+    }
+}
@@ -0,0 +1,465 @@
+use anyhow::{anyhow, Result};
+use chrono::{Local, NaiveDate};
+use requestty::{self, Answers, Question};
+use std::collections::{HashMap, HashSet};
+
+use crate::actions::{add_remove_prompt, Action};
+use crate::helper::{clear_screen, continue_prompt, format_money, parse_date, parse_money};
+use crate::trains::Train;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Discount {
+    Percentage(u8), // Percent off the fare, 0-100
+    Fixed(u32),     // Amount off the fare in cents
+}
+
+impl Discount {
+    pub fn amount(
+        &self,
+        fare: u32,
+    ) -> u32 {
+        match self {
+            Discount::Percentage(percent) => fare * u32::from(*percent) / 100,
+            Discount::Fixed(amount) => (*amount).min(fare),
+        }
+    }
+}
+
+impl std::fmt::Display for Discount {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            Discount::Percentage(percent) => write!(f, "{}%", percent),
+            Discount::Fixed(amount) => write!(f, "{}", format_money(*amount)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Promotion {
+    pub code: String,
+    pub discount: Discount,
+    pub valid_from: NaiveDate,
+    pub valid_until: NaiveDate,
+    pub max_redemptions: Option<u32>,    // None means unlimited
+    pub redemptions: u32,                // Number of times the code has been used
+    pub train_lines: HashSet<u32>,       // Empty means any train
+    pub route: Option<(String, String)>, // Origin and destination, None means any route
+}
+
+impl Promotion {
+    pub fn new(
+        code: String,
+        discount: Discount,
+        valid_from: NaiveDate,
+        valid_until: NaiveDate,
+    ) -> Self {
+        Self {
+            code,
+            discount,
+            valid_from,
+            valid_until,
+            max_redemptions: None,
+            redemptions: 0,
+            train_lines: HashSet::new(),
+            route: None,
+        }
+    }
+
+    pub fn check(
+        &self,
+        train: &Train,
+        today: NaiveDate,
+    ) -> Result<()> {
+        if today < self.valid_from || today > self.valid_until {
+            return Err(anyhow!(
+                "Promo code {} is only valid from {} to {}",
+                self.code,
+                self.valid_from,
+                self.valid_until
+            ));
+        }
+        if let Some(max) = self.max_redemptions {
+            if self.redemptions >= max {
+                return Err(anyhow!("Promo code {} has reached its usage limit", self.code));
+            }
+        }
+        if !self.train_lines.is_empty() && !self.train_lines.contains(&train.line) {
+            return Err(anyhow!("Promo code {} is not valid on train {}", self.code, train.line));
+        }
+        if let Some((origin, destination)) = &self.route {
+            if *origin != train.origin || *destination != train.destination {
+                return Err(anyhow!(
+                    "Promo code {} is only valid from {} to {}",
+                    self.code,
+                    origin,
+                    destination
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub type PromotionList = HashMap<String, Promotion>;
+
+/// Validates a promo code against a train and records a redemption, returning the discount in cents.
+pub fn redeem_promotion(
+    promotions: &mut PromotionList,
+    code: &str,
+    train: &Train,
+    fare: u32,
+    today: NaiveDate,
+) -> Result<u32> {
+    let promotion = promotions
+        .get_mut(code)
+        .ok_or_else(|| anyhow!("Promo code {} not found", code))?;
+    promotion.check(train, today)?;
+    promotion.redemptions += 1;
+
+    Ok(promotion.discount.amount(fare))
+}
+
+// Undoes a redemption when the booking it was applied to is rolled back
+pub fn release_promotion(
+    promotions: &mut PromotionList,
+    code: &str,
+) {
+    if let Some(promotion) = promotions.get_mut(code) {
+        promotion.redemptions = promotion.redemptions.saturating_sub(1);
+    }
+}
+
+pub fn check_promotion(
+    promotions: &PromotionList,
+    code: &str,
+    train: &Train,
+    today: NaiveDate,
+) -> Result<()> {
+    promotions
+        .get(code)
+        .ok_or_else(|| anyhow!("Promo code {} not found", code))?
+        .check(train, today)
+}
+
+pub fn manage_promotions(promotions: &mut PromotionList) -> Result<()> {
+    loop {
+        clear_screen();
+        match add_remove_prompt()? {
+            Action::List => {
+                if promotions.is_empty() {
+                    println!("No promotions found");
+                } else {
+                    for (code, promotion) in &*promotions {
+                        println!(
+                            "Code: {}\nDiscount: {}\nValid: {} to {}\nRedemptions: {}{}\nTrains: {}\nRoute: {}\n====================\n",
+                            code,
+                            promotion.discount,
+                            promotion.valid_from,
+                            promotion.valid_until,
+                            promotion.redemptions,
+                            promotion
+                                .max_redemptions
+                                .map(|max| format!(" of {}", max))
+                                .unwrap_or_default(),
+                            format_train_lines(&promotion.train_lines),
+                            promotion
+                                .route
+                                .as_ref()
+                                .map(|(origin, destination)| format!("{} to {}", origin, destination))
+                                .unwrap_or_else(|| "Any".to_string()),
+                        );
+                    }
+                }
+                continue_prompt();
+            }
+            Action::Add => match add_promotion() {
+                Ok(promotion) => {
+                    if !promotions.contains_key(&promotion.code) {
+                        promotions.insert(promotion.code.clone(), promotion);
+                    } else {
+                        println!("Promotion already exists");
+                        continue_prompt();
+                    }
+                }
+                Err(e) => {
+                    println!("Error: {}", e);
+                    continue_prompt();
+                }
+            },
+            Action::Remove => {
+                if promotions.is_empty() {
+                    println!("No promotions to remove");
+                    continue_prompt();
+                } else {
+                    let code = select_promotion(promotions, "Select promotion to delete")?;
+                    promotions.remove(&code);
+                }
+            }
+            Action::Edit => {
+                if promotions.is_empty() {
+                    println!("No promotions to edit");
+                    continue_prompt();
+                } else if let Err(e) = edit_promotion(promotions) {
+                    println!("Error: {}", e);
+                    continue_prompt();
+                }
+            }
+            Action::Back => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn format_train_lines(train_lines: &HashSet<u32>) -> String {
+    if train_lines.is_empty() {
+        return "Any".to_string();
+    }
+    let mut lines: Vec<u32> = train_lines.iter().copied().collect();
+    lines.sort();
+    lines.iter().map(|line| line.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn select_promotion(
+    promotions: &PromotionList,
+    message: &str,
+) -> Result<String> {
+    let codes: Vec<String> = promotions.keys().cloned().collect();
+    let selection = requestty::prompt_one(Question::select("promotion").message(message).choices(codes).build())?;
+    Ok(selection.as_list_item().unwrap().text.clone())
+}
+
+fn promotion_questions(promotion: Option<&Promotion>) -> Vec<Question<'static>> {
+    let today = Local::now().date_naive();
+    let (discount_index, amount) = match promotion.map(|promotion| promotion.discount) {
+        Some(Discount::Percentage(percent)) => (0, percent.to_string()),
+        Some(Discount::Fixed(amount)) => (1, format_money(amount)),
+        None => (0, String::new()),
+    };
+
+    vec![
+        Question::select("discount_type")
+            .message("Select the discount type")
+            .choice("Percentage")
+            .choice("Fixed amount")
+            .default(discount_index)
+            .build(),
+        Question::input("amount")
+            .message("Enter the discount (percent or amount)")
+            .default(amount)
+            .build(),
+        Question::input("valid_from")
+            .message("Enter the first valid date (YYYY-MM-DD)")
+            .default(promotion.map_or(today, |promotion| promotion.valid_from).to_string())
+            .build(),
+        Question::input("valid_until")
+            .message("Enter the last valid date (YYYY-MM-DD)")
+            .default(promotion.map_or(today, |promotion| promotion.valid_until).to_string())
+            .build(),
+        Question::input("max_redemptions")
+            .message("Enter the usage limit (blank for unlimited)")
+            .default(
+                promotion
+                    .and_then(|promotion| promotion.max_redemptions)
+                    .map(|max| max.to_string())
+                    .unwrap_or_default(),
+            )
+            .build(),
+        Question::input("train_lines")
+            .message("Enter the valid train lines, comma separated (blank for any)")
+            .default(
+                promotion
+                    .map(|promotion| format_train_lines(&promotion.train_lines))
+                    .filter(|lines| lines != "Any")
+                    .unwrap_or_default(),
+            )
+            .build(),
+        Question::input("origin")
+            .message("Enter the valid origin (blank for any route)")
+            .default(
+                promotion
+                    .and_then(|promotion| promotion.route.as_ref())
+                    .map(|(origin, _)| origin.clone())
+                    .unwrap_or_default(),
+            )
+            .build(),
+        Question::input("destination")
+            .message("Enter the valid destination (blank for any route)")
+            .default(
+                promotion
+                    .and_then(|promotion| promotion.route.as_ref())
+                    .map(|(_, destination)| destination.clone())
+                    .unwrap_or_default(),
+            )
+            .build(),
+    ]
+}
+
+fn apply_answers(
+    promotion: &mut Promotion,
+    answers: &Answers,
+) -> Result<()> {
+    let amount = answers.get("amount").unwrap().as_string().unwrap().trim();
+    promotion.discount = match answers.get("discount_type").unwrap().as_list_item().unwrap().index {
+        0 => {
+            let percent: u8 = amount.parse()?;
+            if percent > 100 {
+                return Err(anyhow!("Percentage discount cannot exceed 100"));
+            }
+            Discount::Percentage(percent)
+        }
+        1 => Discount::Fixed(parse_money(amount)?),
+        _ => unreachable!(),
+    };
+    promotion.valid_from = parse_date(answers.get("valid_from").unwrap().as_string().unwrap())?;
+    promotion.valid_until = parse_date(answers.get("valid_until").unwrap().as_string().unwrap())?;
+    if promotion.valid_until < promotion.valid_from {
+        return Err(anyhow!("Validity window ends before it starts"));
+    }
+
+    let max_redemptions = answers.get("max_redemptions").unwrap().as_string().unwrap().trim();
+    promotion.max_redemptions = if max_redemptions.is_empty() {
+        None
+    } else {
+        Some(max_redemptions.parse()?)
+    };
+
+    promotion.train_lines = answers
+        .get("train_lines")
+        .unwrap()
+        .as_string()
+        .unwrap()
+        .split(',')
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.parse::<u32>())
+        .collect::<Result<_, _>>()?;
+
+    let origin = answers.get("origin").unwrap().as_string().unwrap().trim();
+    let destination = answers.get("destination").unwrap().as_string().unwrap().trim();
+    promotion.route = if origin.is_empty() && destination.is_empty() {
+        None
+    } else if origin.is_empty() || destination.is_empty() {
+        return Err(anyhow!("A route needs both an origin and a destination"));
+    } else {
+        Some((origin.to_string(), destination.to_string()))
+    };
+
+    Ok(())
+}
+
+fn add_promotion() -> Result<Promotion> {
+    let code = requestty::prompt_one(Question::input("code").message("Enter the promo code").build())?;
+    let code = code.as_string().unwrap().trim().to_uppercase();
+    if code.is_empty() {
+        return Err(anyhow!("Promo code cannot be empty"));
+    }
+
+    let today = Local::now().date_naive();
+    let mut promotion = Promotion::new(code, Discount::Percentage(0), today, today);
+    let answers = requestty::prompt(promotion_questions(None))?;
+    apply_answers(&mut promotion, &answers)?;
+
+    Ok(promotion)
+}
+
+fn edit_promotion(promotions: &mut PromotionList) -> Result<()> {
+    let code = select_promotion(promotions, "Select promotion to edit")?;
+    let answers = requestty::prompt(promotion_questions(promotions.get(&code)))?;
+
+    // Apply to a copy so a bad answer leaves the promotion untouched
+    let mut edited = promotions[&code].clone();
+    apply_answers(&mut edited, &answers)?;
+    promotions.insert(code, edited);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn train() -> Train {
+        let mut train = Train::new(
+            1,
+            "Express".to_string(),
+            100,
+            "Toronto".to_string(),
+            "Hamilton".to_string(),
+            NaiveTime::from_hms_opt(11, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+        );
+        train.fare = 2500;
+        train
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 6, day).unwrap()
+    }
+
+    #[test]
+    fn test_discount_amount() {
+        assert_eq!(Discount::Percentage(20).amount(2500), 500);
+        assert_eq!(Discount::Fixed(300).amount(2500), 300);
+        assert_eq!(Discount::Fixed(3000).amount(2500), 2500);
+    }
+
+    #[test]
+    fn test_redeem_promotion() {
+        let mut promotions = PromotionList::new();
+        promotions.insert(
+            "SUMMER".to_string(),
+            Promotion::new("SUMMER".to_string(), Discount::Percentage(10), date(1), date(30)),
+        );
+
+        let discount = redeem_promotion(&mut promotions, "SUMMER", &train(), 2500, date(15)).unwrap();
+
+        assert_eq!(discount, 250);
+        assert_eq!(promotions["SUMMER"].redemptions, 1);
+    }
+
+    #[test]
+    fn test_promotion_validity_window() {
+        let promotion = Promotion::new("SUMMER".to_string(), Discount::Percentage(10), date(10), date(20));
+
+        assert!(promotion.check(&train(), date(9)).is_err());
+        assert!(promotion.check(&train(), date(10)).is_ok());
+        assert!(promotion.check(&train(), date(20)).is_ok());
+        assert!(promotion.check(&train(), date(21)).is_err());
+    }
+
+    #[test]
+    fn test_promotion_usage_limit() {
+        let mut promotions = PromotionList::new();
+        let mut promotion = Promotion::new("ONCE".to_string(), Discount::Fixed(500), date(1), date(30));
+        promotion.max_redemptions = Some(1);
+        promotions.insert(promotion.code.clone(), promotion);
+
+        assert!(redeem_promotion(&mut promotions, "ONCE", &train(), 2500, date(2)).is_ok());
+        assert!(redeem_promotion(&mut promotions, "ONCE", &train(), 2500, date(2)).is_err());
+        assert_eq!(promotions["ONCE"].redemptions, 1);
+
+        // A rolled back booking gives the redemption back
+        release_promotion(&mut promotions, "ONCE");
+        assert_eq!(promotions["ONCE"].redemptions, 0);
+        assert!(redeem_promotion(&mut promotions, "ONCE", &train(), 2500, date(2)).is_ok());
+    }
+
+    #[test]
+    fn test_promotion_train_and_route_restrictions() {
+        let mut promotion = Promotion::new("LOCAL".to_string(), Discount::Percentage(10), date(1), date(30));
+        promotion.train_lines.insert(2);
+        assert!(promotion.check(&train(), date(5)).is_err());
+
+        promotion.train_lines.insert(1);
+        promotion.route = Some(("Toronto".to_string(), "Ottawa".to_string()));
+        assert!(promotion.check(&train(), date(5)).is_err());
+
+        promotion.route = Some(("Toronto".to_string(), "Hamilton".to_string()));
+        assert!(promotion.check(&train(), date(5)).is_ok());
+    }
+}
@@ -0,0 +1,832 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use requestty::{self, Answers, Question};
+
+use crate::actions::{add_remove_prompt, Action};
+use crate::bookings::{Booking, BookingList};
+use crate::disruptions::scheduled_times;
+use crate::helper::{clear_screen, continue_prompt, parse_date};
+use crate::minors::{check_minor_rules, Supervision};
+use crate::passengers::{Passenger, PassengerList};
+use crate::trains::{Train, TrainList};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,   // The booking is refused
+    Warning, // The booking goes ahead and the warning is kept on it
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+/// A booking about to be made, or an existing booking about to move to another train or date.
+pub struct BookingRequest<'a> {
+    pub bookings: &'a BookingList,
+    pub passengers: &'a PassengerList,
+    pub trains: &'a TrainList,
+    pub passenger: &'a Passenger,
+    pub train: &'a Train,
+    pub travel_date: NaiveDate,
+    pub supervision: &'a Supervision,
+    pub excluding: Option<&'a str>, // Booking being moved, left out of the checks
+}
+
+impl BookingRequest<'_> {
+    /// The passenger's other bookings, leaving out the one being moved.
+    fn other_bookings(&self) -> impl Iterator<Item = &Booking> {
+        self.passenger
+            .bookings
+            .iter()
+            .filter(|booking_id| Some(booking_id.as_str()) != self.excluding)
+            .filter_map(|booking_id| self.bookings.get(booking_id))
+    }
+}
+
+pub trait BookingRule {
+    fn describe(&self) -> String;
+    fn check(
+        &self,
+        request: &BookingRequest,
+    ) -> Result<()>;
+}
+
+/// Rules run in order; the first failing error rule refuses the booking.
+pub struct RulePipeline {
+    pub rules: Vec<(Box<dyn BookingRule>, Severity)>,
+}
+
+impl Default for RulePipeline {
+    fn default() -> Self {
+        let mut pipeline = Self::new();
+        pipeline.register(Box::new(OverlapRule), Severity::Error);
+        pipeline.register(Box::new(SupervisionRule), Severity::Error);
+        pipeline.register(
+            Box::new(ConnectionRule {
+                min_buffer_minutes: DEFAULT_BUFFER_MINUTES,
+                walking_pairs: Vec::new(),
+            }),
+            Severity::Error,
+        );
+        pipeline
+    }
+}
+
+impl RulePipeline {
+    pub fn new() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    pub fn register(
+        &mut self,
+        rule: Box<dyn BookingRule>,
+        severity: Severity,
+    ) {
+        self.rules.push((rule, severity));
+    }
+
+    pub fn run(
+        &self,
+        request: &BookingRequest,
+    ) -> Result<Vec<String>> {
+        let mut warnings = Vec::new();
+        for (rule, severity) in &self.rules {
+            if let Err(e) = rule.check(request) {
+                match severity {
+                    Severity::Error => return Err(e),
+                    Severity::Warning => warnings.push(e.to_string()),
+                }
+            }
+        }
+        Ok(warnings)
+    }
+}
+
+/// A passenger cannot be booked twice on the same train and date or on trips whose times overlap.
+pub struct OverlapRule;
+
+impl BookingRule for OverlapRule {
+    fn describe(&self) -> String {
+        "No overlapping trips".to_string()
+    }
+
+    fn check(
+        &self,
+        request: &BookingRequest,
+    ) -> Result<()> {
+        let train = request.train;
+        let (departure, arrival) = scheduled_times(train, request.travel_date);
+        for booking in request.other_bookings() {
+            let booked_train = request.trains.get(&booking.train_line).unwrap();
+            let (booked_departure, booked_arrival) = scheduled_times(booked_train, booking.travel_date);
+            if (booking.train_line == train.line && booking.travel_date == request.travel_date)
+                || (departure < booked_arrival && arrival > booked_departure)
+            {
+                return Err(anyhow!(
+                    "Passenger already has a booking for this train or overlapping travel times"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Minutes a passenger needs to change trains at the same station.
+pub const DEFAULT_BUFFER_MINUTES: u32 = 10;
+
+/// Two stations close enough to change between on foot. Pairs work in both directions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WalkingPair {
+    pub from: String,
+    pub to: String,
+    pub minutes: u32, // Walking time, added to the buffer
+}
+
+/// Same-day trips must connect at the same station or within walking distance.
+pub struct ConnectionRule {
+    pub min_buffer_minutes: u32,
+    pub walking_pairs: Vec<WalkingPair>,
+}
+
+impl ConnectionRule {
+    /// Minutes needed to change from one station to another, or None when they do not connect.
+    fn transfer_minutes(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Option<u32> {
+        if from.eq_ignore_ascii_case(to) {
+            return Some(self.min_buffer_minutes);
+        }
+        self.walking_pairs
+            .iter()
+            .find(|pair| {
+                (pair.from.eq_ignore_ascii_case(from) && pair.to.eq_ignore_ascii_case(to))
+                    || (pair.from.eq_ignore_ascii_case(to) && pair.to.eq_ignore_ascii_case(from))
+            })
+            .map(|pair| self.min_buffer_minutes + pair.minutes)
+    }
+
+    fn check_connection(
+        &self,
+        arriving: &Train,
+        arrival: NaiveDateTime,
+        departing: &Train,
+        departure: NaiveDateTime,
+    ) -> Result<()> {
+        let gap = (departure - arrival).num_minutes();
+        match self.transfer_minutes(&arriving.destination, &departing.origin) {
+            None => Err(anyhow!(
+                "Train {} arrives at {} but train {} leaves from {}",
+                arriving.line,
+                arriving.destination,
+                departing.line,
+                departing.origin
+            )),
+            Some(needed) if gap < i64::from(needed) => Err(anyhow!(
+                "Only {} minutes to change from train {} to train {}, at least {} needed",
+                gap,
+                arriving.line,
+                departing.line,
+                needed
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+impl BookingRule for ConnectionRule {
+    fn describe(&self) -> String {
+        let mut description = format!(
+            "Same-day trips connect with at least {} minutes to change",
+            self.min_buffer_minutes
+        );
+        if !self.walking_pairs.is_empty() {
+            description.push_str(&format!(" (walking: {})", format_walking_pairs(&self.walking_pairs)));
+        }
+        description
+    }
+
+    fn check(
+        &self,
+        request: &BookingRequest,
+    ) -> Result<()> {
+        let (departure, arrival) = scheduled_times(request.train, request.travel_date);
+        let trips: Vec<(&Train, NaiveDateTime, NaiveDateTime)> = request
+            .other_bookings()
+            .filter(|booking| booking.travel_date == request.travel_date)
+            .filter_map(|booking| request.trains.get(&booking.train_line))
+            .map(|train| {
+                let (departure, arrival) = scheduled_times(train, request.travel_date);
+                (train, departure, arrival)
+            })
+            .collect();
+
+        // Trips that overlap the new one are left to the overlap rule
+        if let Some(&(previous, _, previous_arrival)) = trips
+            .iter()
+            .filter(|(_, _, trip_arrival)| *trip_arrival <= departure)
+            .max_by_key(|(_, _, trip_arrival)| *trip_arrival)
+        {
+            self.check_connection(previous, previous_arrival, request.train, departure)?;
+        }
+        if let Some(&(next, next_departure, _)) = trips
+            .iter()
+            .filter(|(_, trip_departure, _)| *trip_departure >= arrival)
+            .min_by_key(|(_, trip_departure, _)| *trip_departure)
+        {
+            self.check_connection(request.train, arrival, next, next_departure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses pairs written as `station/station:minutes`, e.g. `Union/Union Pearson:8, Bay/Yonge:5`.
+pub fn parse_walking_pairs(input: &str) -> Result<Vec<WalkingPair>> {
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (stations, minutes) = pair
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow!("Invalid walking pair {}, expected station/station:minutes", pair))?;
+            let (from, to) = stations
+                .split_once('/')
+                .filter(|(from, to)| !from.trim().is_empty() && !to.trim().is_empty())
+                .ok_or_else(|| anyhow!("Invalid walking pair {}, expected station/station:minutes", pair))?;
+            Ok(WalkingPair {
+                from: from.trim().to_string(),
+                to: to.trim().to_string(),
+                minutes: minutes.trim().parse().context("Invalid walking time")?,
+            })
+        })
+        .collect()
+}
+
+pub fn format_walking_pairs(pairs: &[WalkingPair]) -> String {
+    pairs
+        .iter()
+        .map(|pair| format!("{}/{}:{}", pair.from, pair.to, pair.minutes))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Applies the train's minor rules, see `minors.rs`.
+pub struct SupervisionRule;
+
+impl BookingRule for SupervisionRule {
+    fn describe(&self) -> String {
+        "Minors travel with the supervision their train requires".to_string()
+    }
+
+    fn check(
+        &self,
+        request: &BookingRequest,
+    ) -> Result<()> {
+        check_minor_rules(
+            request.bookings,
+            request.passengers,
+            request.passenger,
+            request.train,
+            request.travel_date,
+            request.supervision,
+        )
+    }
+}
+
+/// Holds back part of a train, e.g. to keep seats free for passengers on disrupted services.
+pub struct CapacityRule {
+    pub max_load_percent: u32,
+}
+
+impl BookingRule for CapacityRule {
+    fn describe(&self) -> String {
+        format!("Trains at most {}% full", self.max_load_percent)
+    }
+
+    fn check(
+        &self,
+        request: &BookingRequest,
+    ) -> Result<()> {
+        let booked = request
+            .bookings
+            .values()
+            .filter(|booking| booking.train_line == request.train.line && booking.travel_date == request.travel_date)
+            .filter(|booking| Some(booking.id.as_str()) != request.excluding)
+            .count() as u32;
+        if (booked + 1) * 100 > request.train.capacity * self.max_load_percent {
+            return Err(anyhow!(
+                "Train {} would be more than {}% full on {}",
+                request.train.line,
+                self.max_load_percent,
+                request.travel_date
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub struct AgeRule {
+    pub min_age: u16,
+    pub max_age: u16,
+}
+
+impl BookingRule for AgeRule {
+    fn describe(&self) -> String {
+        format!("Passengers aged {} to {}", self.min_age, self.max_age)
+    }
+
+    fn check(
+        &self,
+        request: &BookingRequest,
+    ) -> Result<()> {
+        let age = request.passenger.age_on(request.travel_date);
+        if age < self.min_age || age > self.max_age {
+            return Err(anyhow!(
+                "{} is {} on {}, outside the {} to {} age range",
+                request.passenger.name,
+                age,
+                request.travel_date,
+                self.min_age,
+                self.max_age
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub struct DailyLimitRule {
+    pub max_trips: usize,
+}
+
+impl BookingRule for DailyLimitRule {
+    fn describe(&self) -> String {
+        format!("At most {} trips per passenger per day", self.max_trips)
+    }
+
+    fn check(
+        &self,
+        request: &BookingRequest,
+    ) -> Result<()> {
+        let trips = request
+            .other_bookings()
+            .filter(|booking| booking.travel_date == request.travel_date)
+            .count();
+        if trips >= self.max_trips {
+            return Err(anyhow!(
+                "{} already has {} trip{} on {}, the limit is {}",
+                request.passenger.name,
+                trips,
+                if trips == 1 { "" } else { "s" },
+                request.travel_date,
+                self.max_trips
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Dates on which no bookings are taken, for one train or all of them.
+pub struct BlackoutRule {
+    pub dates: Vec<NaiveDate>,
+    pub train_line: Option<u32>, // None applies to every train
+}
+
+impl BookingRule for BlackoutRule {
+    fn describe(&self) -> String {
+        let dates: Vec<String> = self.dates.iter().map(|date| date.to_string()).collect();
+        match self.train_line {
+            Some(line) => format!("No bookings on train {} on {}", line, dates.join(", ")),
+            None => format!("No bookings on {}", dates.join(", ")),
+        }
+    }
+
+    fn check(
+        &self,
+        request: &BookingRequest,
+    ) -> Result<()> {
+        let applies = self.train_line.is_none_or(|line| line == request.train.line);
+        if applies && self.dates.contains(&request.travel_date) {
+            return Err(anyhow!(
+                "Bookings are closed on train {} on {}",
+                request.train.line,
+                request.travel_date
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn manage_booking_rules(pipeline: &mut RulePipeline) -> Result<()> {
+    loop {
+        clear_screen();
+        match add_remove_prompt()? {
+            Action::List => {
+                if pipeline.rules.is_empty() {
+                    println!("No booking rules found");
+                } else {
+                    for (rule, severity) in &pipeline.rules {
+                        println!("{}: {}", severity, rule.describe());
+                    }
+                }
+                continue_prompt();
+            }
+            Action::Add => match prompt_rule() {
+                Ok((rule, severity)) => pipeline.register(rule, severity),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    continue_prompt();
+                }
+            },
+            Action::Remove => {
+                if pipeline.rules.is_empty() {
+                    println!("No booking rules to remove");
+                    continue_prompt();
+                } else {
+                    let index = select_rule(pipeline, "Select booking rule to delete")?;
+                    pipeline.rules.remove(index);
+                }
+            }
+            Action::Edit => {
+                if pipeline.rules.is_empty() {
+                    println!("No booking rules to edit");
+                    continue_prompt();
+                } else {
+                    let index = select_rule(pipeline, "Select booking rule to edit")?;
+                    let current = pipeline.rules[index].1;
+                    pipeline.rules[index].1 = prompt_severity(Some(current))?;
+                }
+            }
+            Action::Back => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn select_rule(
+    pipeline: &RulePipeline,
+    message: &str,
+) -> Result<usize> {
+    let choices: Vec<String> = pipeline
+        .rules
+        .iter()
+        .map(|(rule, severity)| format!("{}: {}", severity, rule.describe()))
+        .collect();
+    let selection = requestty::prompt_one(Question::select("rule").message(message).choices(choices).build())?;
+    Ok(selection.as_list_item().unwrap().index)
+}
+
+fn prompt_severity(current: Option<Severity>) -> Result<Severity> {
+    let selection = requestty::prompt_one(
+        Question::select("severity")
+            .message("Select what happens when the rule fails")
+            .choice("Refuse the booking")
+            .choice("Warn and allow the booking")
+            .default(match current {
+                Some(Severity::Warning) => 1,
+                _ => 0,
+            })
+            .build(),
+    )?;
+    match selection.as_list_item().unwrap().index {
+        0 => Ok(Severity::Error),
+        1 => Ok(Severity::Warning),
+        _ => unreachable!(),
+    }
+}
+
+fn prompt_rule() -> Result<(Box<dyn BookingRule>, Severity)> {
+    let questions: Vec<Question> = vec![
+        Question::select("kind")
+            .message("Select the kind of rule")
+            .choice("Maximum load")
+            .choice("Age range")
+            .choice("Trips per day")
+            .choice("Blackout dates")
+            .choice("Connections")
+            .build(),
+        Question::input("max_load_percent")
+            .message("Enter the maximum load in percent of capacity")
+            .when(|answers: &Answers| answers["kind"].as_list_item().unwrap().index == 0)
+            .build(),
+        Question::input("min_age")
+            .message("Enter the minimum age")
+            .default("0")
+            .when(|answers: &Answers| answers["kind"].as_list_item().unwrap().index == 1)
+            .build(),
+        Question::input("max_age")
+            .message("Enter the maximum age")
+            .default("130")
+            .when(|answers: &Answers| answers["kind"].as_list_item().unwrap().index == 1)
+            .build(),
+        Question::input("max_trips")
+            .message("Enter the maximum trips per passenger per day")
+            .when(|answers: &Answers| answers["kind"].as_list_item().unwrap().index == 2)
+            .build(),
+        Question::input("dates")
+            .message("Enter the blackout dates (YYYY-MM-DD, comma separated)")
+            .when(|answers: &Answers| answers["kind"].as_list_item().unwrap().index == 3)
+            .build(),
+        Question::input("train_line")
+            .message("Enter the train line (blank for all trains)")
+            .when(|answers: &Answers| answers["kind"].as_list_item().unwrap().index == 3)
+            .build(),
+        Question::input("min_buffer_minutes")
+            .message("Enter the minimum minutes to change trains")
+            .default(DEFAULT_BUFFER_MINUTES.to_string())
+            .when(|answers: &Answers| answers["kind"].as_list_item().unwrap().index == 4)
+            .build(),
+        Question::input("walking_pairs")
+            .message("Enter stations within walking distance as station/station:minutes (blank for none)")
+            .when(|answers: &Answers| answers["kind"].as_list_item().unwrap().index == 4)
+            .build(),
+    ];
+    let answers = requestty::prompt(questions)?;
+    let text = |key: &str| answers.get(key).unwrap().as_string().unwrap().trim().to_string();
+
+    let rule: Box<dyn BookingRule> = match answers.get("kind").unwrap().as_list_item().unwrap().index {
+        0 => Box::new(CapacityRule {
+            max_load_percent: text("max_load_percent").parse()?,
+        }),
+        1 => {
+            let rule = AgeRule {
+                min_age: text("min_age").parse()?,
+                max_age: text("max_age").parse()?,
+            };
+            if rule.min_age > rule.max_age {
+                return Err(anyhow!("The minimum age cannot be above the maximum age"));
+            }
+            Box::new(rule)
+        }
+        2 => Box::new(DailyLimitRule {
+            max_trips: text("max_trips").parse()?,
+        }),
+        3 => {
+            let dates = text("dates")
+                .split(',')
+                .map(str::trim)
+                .filter(|date| !date.is_empty())
+                .map(parse_date)
+                .collect::<Result<Vec<_>>>()?;
+            if dates.is_empty() {
+                return Err(anyhow!("Enter at least one blackout date"));
+            }
+            let train_line = text("train_line");
+            Box::new(BlackoutRule {
+                dates,
+                train_line: if train_line.is_empty() {
+                    None
+                } else {
+                    Some(train_line.parse()?)
+                },
+            })
+        }
+        4 => Box::new(ConnectionRule {
+            min_buffer_minutes: text("min_buffer_minutes").parse()?,
+            walking_pairs: parse_walking_pairs(&text("walking_pairs"))?,
+        }),
+        _ => unreachable!(),
+    };
+    Ok((rule, prompt_severity(None)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::{add_booking, edit_booking};
+    use crate::passengers::approximate_date_of_birth;
+    use crate::test_support::travel_date;
+    use chrono::NaiveTime;
+
+    fn setup() -> (BookingList, PassengerList, TrainList) {
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+        passengers.insert(
+            "P1".to_string(),
+            Passenger::new(
+                "P1".to_string(),
+                "John Doe".to_string(),
+                approximate_date_of_birth(30, travel_date()),
+            ),
+        );
+        for (line, departure) in [(1, 9), (2, 11), (3, 13)] {
+            trains.insert(
+                line,
+                Train::new(
+                    line,
+                    "Express".to_string(),
+                    4,
+                    "Toronto".to_string(),
+                    "Hamilton".to_string(),
+                    NaiveTime::from_hms_opt(departure, 0, 0).unwrap(),
+                    NaiveTime::from_hms_opt(departure + 1, 0, 0).unwrap(),
+                ),
+            );
+        }
+        (BookingList::new(), passengers, trains)
+    }
+
+    fn book(
+        bookings: &mut BookingList,
+        passengers: &mut PassengerList,
+        trains: &mut TrainList,
+        pipeline: &RulePipeline,
+        train_line: u32,
+    ) -> Result<String> {
+        add_booking(
+            bookings,
+            passengers,
+            trains,
+            pipeline,
+            "P1".to_string(),
+            train_line,
+            travel_date(),
+            Supervision::Alone,
+        )
+    }
+
+    #[test]
+    fn test_severity() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let mut pipeline = RulePipeline::new();
+        pipeline.register(Box::new(DailyLimitRule { max_trips: 1 }), Severity::Warning);
+
+        let first = book(&mut bookings, &mut passengers, &mut trains, &pipeline, 1).unwrap();
+        assert!(bookings[&first].warnings.is_empty());
+        let second = book(&mut bookings, &mut passengers, &mut trains, &pipeline, 2).unwrap();
+        assert_eq!(
+            bookings[&second].warnings,
+            vec!["John Doe already has 1 trip on 2024-06-10, the limit is 1"]
+        );
+
+        // The same rule as an error refuses the booking
+        pipeline.rules.last_mut().unwrap().1 = Severity::Error;
+        assert!(book(&mut bookings, &mut passengers, &mut trains, &pipeline, 3).is_err());
+        assert!(!bookings.contains_key("P1_3_20240610"));
+        assert_eq!(bookings.len(), 2);
+    }
+
+    #[test]
+    fn test_overlap_rule() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let pipeline = RulePipeline::default();
+        trains.get_mut(&2).unwrap().departure = NaiveTime::from_hms_opt(9, 30, 0).unwrap();
+        trains.get_mut(&3).unwrap().departure = NaiveTime::from_hms_opt(9, 45, 0).unwrap();
+
+        let first = book(&mut bookings, &mut passengers, &mut trains, &pipeline, 1).unwrap();
+        assert!(book(&mut bookings, &mut passengers, &mut trains, &pipeline, 1).is_err());
+        assert!(book(&mut bookings, &mut passengers, &mut trains, &pipeline, 2).is_err());
+        // Moving a booking does not clash with itself
+        edit_booking(
+            &mut bookings,
+            &mut passengers,
+            &mut trains,
+            &pipeline,
+            first.clone(),
+            2,
+            travel_date(),
+        )
+        .unwrap();
+        assert_eq!(bookings[&first].train_line, 2);
+
+        // Without the rule the overlap goes through
+        let mut pipeline = RulePipeline::new();
+        pipeline.register(Box::new(SupervisionRule), Severity::Error);
+        assert!(book(&mut bookings, &mut passengers, &mut trains, &pipeline, 3).is_ok());
+    }
+
+    #[test]
+    fn test_builtin_rules() {
+        let (bookings, passengers, trains) = setup();
+        let request = |line: u32, travel_date: NaiveDate| BookingRequest {
+            bookings: &bookings,
+            passengers: &passengers,
+            trains: &trains,
+            passenger: &passengers["P1"],
+            train: &trains[&line],
+            travel_date,
+            supervision: &Supervision::Alone,
+            excluding: None,
+        };
+        let blackout = BlackoutRule {
+            dates: vec![travel_date()],
+            train_line: Some(1),
+        };
+        assert!(blackout.check(&request(1, travel_date())).is_err());
+        assert!(blackout.check(&request(2, travel_date())).is_ok());
+        assert!(blackout.check(&request(1, travel_date().succ_opt().unwrap())).is_ok());
+
+        let age = AgeRule {
+            min_age: 60,
+            max_age: 130,
+        };
+        assert_eq!(
+            age.check(&request(1, travel_date())).unwrap_err().to_string(),
+            "John Doe is 30 on 2024-06-10, outside the 60 to 130 age range"
+        );
+
+        // One of four seats may be sold at 25%, the second would go over
+        let capacity = CapacityRule { max_load_percent: 25 };
+        assert!(capacity.check(&request(1, travel_date())).is_ok());
+        let mut bookings = bookings;
+        let mut passengers = passengers;
+        let mut trains = trains;
+        book(&mut bookings, &mut passengers, &mut trains, &RulePipeline::default(), 1).unwrap();
+        let request = BookingRequest {
+            bookings: &bookings,
+            passengers: &passengers,
+            trains: &trains,
+            passenger: &passengers["P1"],
+            train: &trains[&1],
+            travel_date: travel_date(),
+            supervision: &Supervision::Alone,
+            excluding: None,
+        };
+        assert!(capacity.check(&request).is_err());
+    }
+
+    #[test]
+    fn test_connection_rule() {
+        let (bookings, mut passengers, mut trains) = setup();
+        // Train 1 runs Toronto to Hamilton 9:00 to 10:00
+        trains.insert(
+            4,
+            Train::new(
+                4,
+                "Niagara".to_string(),
+                4,
+                "Hamilton".to_string(),
+                "Niagara".to_string(),
+                NaiveTime::from_hms_opt(10, 5, 0).unwrap(),
+                NaiveTime::from_hms_opt(11, 0, 0).unwrap(),
+            ),
+        );
+        let mut bookings = bookings;
+        let mut rule = ConnectionRule {
+            min_buffer_minutes: DEFAULT_BUFFER_MINUTES,
+            walking_pairs: Vec::new(),
+        };
+        let pipeline = RulePipeline::default();
+        book(&mut bookings, &mut passengers, &mut trains, &pipeline, 1).unwrap();
+        let check = |rule: &ConnectionRule, trains: &TrainList, line: u32| {
+            rule.check(&BookingRequest {
+                bookings: &bookings,
+                passengers: &passengers,
+                trains,
+                passenger: &passengers["P1"],
+                train: &trains[&line],
+                travel_date: travel_date(),
+                supervision: &Supervision::Alone,
+                excluding: None,
+            })
+        };
+
+        assert_eq!(
+            check(&rule, &trains, 4).unwrap_err().to_string(),
+            "Only 5 minutes to change from train 1 to train 4, at least 10 needed"
+        );
+        rule.min_buffer_minutes = 5;
+        assert!(check(&rule, &trains, 4).is_ok());
+
+        // Trains after the new one have to connect too
+        trains.get_mut(&2).unwrap().departure = NaiveTime::from_hms_opt(7, 0, 0).unwrap();
+        trains.get_mut(&2).unwrap().arrival = NaiveTime::from_hms_opt(8, 0, 0).unwrap();
+        assert!(check(&rule, &trains, 2).is_err());
+
+        trains.get_mut(&4).unwrap().origin = "Hamilton GO".to_string();
+        assert_eq!(
+            check(&rule, &trains, 4).unwrap_err().to_string(),
+            "Train 1 arrives at Hamilton but train 4 leaves from Hamilton GO"
+        );
+        rule.walking_pairs = parse_walking_pairs("hamilton go/Hamilton:10").unwrap();
+        assert!(check(&rule, &trains, 4).is_err());
+        trains.get_mut(&4).unwrap().departure = NaiveTime::from_hms_opt(10, 15, 0).unwrap();
+        assert!(check(&rule, &trains, 4).is_ok());
+
+        // The default pipeline refuses a booking that does not connect
+        trains.get_mut(&4).unwrap().origin = "Burlington".to_string();
+        assert!(book(&mut bookings, &mut passengers, &mut trains, &pipeline, 4).is_err());
+    }
+
+    #[test]
+    fn test_parse_walking_pairs() {
+        let pairs = parse_walking_pairs("Union/Union Pearson:8, Bay / Yonge : 5").unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[1].from, "Bay");
+        assert_eq!(pairs[1].minutes, 5);
+        assert_eq!(format_walking_pairs(&pairs), "Union/Union Pearson:8, Bay/Yonge:5");
+        assert!(parse_walking_pairs("").unwrap().is_empty());
+        assert!(parse_walking_pairs("Union:8").is_err());
+        assert!(parse_walking_pairs("Union/Bay").is_err());
+    }
+}
@@ -0,0 +1,577 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{Duration, Local, NaiveDateTime};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::bookings::{record_event, remove_booking, BookingHistory, BookingList, BookingStatus, EventKind};
+use crate::disruptions::expected_times;
+use crate::passengers::PassengerList;
+use crate::promotions::{release_promotion, PromotionList};
+use crate::trains::TrainList;
+
+pub const SCHEDULER_PATH: &str = "scheduler-jobs.txt";
+const REMINDER_LEADS_HOURS: [u32; 2] = [1, 24]; // Shortest first
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+pub trait Clock {
+    fn now(&self) -> NaiveDateTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Clone)]
+pub struct ManualClock {
+    now: Rc<Cell<NaiveDateTime>>,
+}
+
+impl ManualClock {
+    pub fn new(now: NaiveDateTime) -> Self {
+        Self {
+            now: Rc::new(Cell::new(now)),
+        }
+    }
+
+    pub fn advance(
+        &self,
+        by: Duration,
+    ) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> NaiveDateTime {
+        self.now.get()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JobKind {
+    DepartureReminders,
+    CompleteTrips,
+    ExpireHolds,
+}
+
+impl JobKind {
+    fn name(&self) -> &'static str {
+        match self {
+            JobKind::DepartureReminders => "departure-reminders",
+            JobKind::CompleteTrips => "complete-trips",
+            JobKind::ExpireHolds => "expire-holds",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "departure-reminders" => Ok(JobKind::DepartureReminders),
+            "complete-trips" => Ok(JobKind::CompleteTrips),
+            "expire-holds" => Ok(JobKind::ExpireHolds),
+            _ => Err(anyhow!("Unknown job {}", name)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Job {
+    pub kind: JobKind,
+    pub interval_minutes: u32,
+    pub next_run: NaiveDateTime,
+}
+
+/// Runs periodic jobs against the booking data whenever `run_due` is called, keeping the job
+/// schedule and the reminders already sent in a file so they survive a restart. There is no
+/// background timer: the application calls `run_due` each time the main menu is shown, so jobs
+/// that fall due while an agent stays in another menu wait until they return to it.
+pub struct Scheduler {
+    pub jobs: Vec<Job>,
+    reminders_sent: HashSet<String>, // Booking, train, date and lead of every reminder sent
+    clock: Box<dyn Clock>,
+    path: Option<PathBuf>, // None keeps the schedule in memory only
+}
+
+impl Scheduler {
+    pub fn new(
+        clock: Box<dyn Clock>,
+        path: Option<PathBuf>,
+    ) -> Self {
+        let now = clock.now();
+        Self {
+            jobs: vec![
+                Job {
+                    kind: JobKind::DepartureReminders,
+                    interval_minutes: 5,
+                    next_run: now,
+                },
+                Job {
+                    kind: JobKind::CompleteTrips,
+                    interval_minutes: 15,
+                    next_run: now,
+                },
+                Job {
+                    kind: JobKind::ExpireHolds,
+                    interval_minutes: 5,
+                    next_run: now,
+                },
+            ],
+            reminders_sent: HashSet::new(),
+            clock,
+            path,
+        }
+    }
+
+    /// Loads the saved schedule, or starts the default one when there is no file yet.
+    pub fn load(
+        path: &Path,
+        clock: Box<dyn Clock>,
+    ) -> Result<Self> {
+        let mut scheduler = Self::new(clock, Some(path.to_path_buf()));
+        if !path.exists() {
+            return Ok(scheduler);
+        }
+
+        let defaults = std::mem::take(&mut scheduler.jobs);
+        let contents = fs::read_to_string(path).with_context(|| format!("Could not read {}", path.display()))?;
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["job", name, interval, next_run] => scheduler.jobs.push(Job {
+                    kind: JobKind::from_name(name)?,
+                    interval_minutes: interval.parse().context("Malformed job interval")?,
+                    next_run: NaiveDateTime::parse_from_str(next_run, TIMESTAMP_FORMAT)
+                        .context("Malformed job time")?,
+                }),
+                ["sent", key] => {
+                    scheduler.reminders_sent.insert(key.to_string());
+                }
+                _ => return Err(anyhow!("Malformed line in {}: {}", path.display(), line)),
+            }
+        }
+        // Jobs added since the schedule was saved start straight away
+        for job in defaults {
+            if !scheduler.jobs.iter().any(|saved| saved.kind == job.kind) {
+                scheduler.jobs.push(job);
+            }
+        }
+        Ok(scheduler)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut contents = String::new();
+        for job in &self.jobs {
+            contents.push_str(&format!(
+                "job\t{}\t{}\t{}\n",
+                job.kind.name(),
+                job.interval_minutes,
+                job.next_run.format(TIMESTAMP_FORMAT)
+            ));
+        }
+        let mut sent: Vec<&String> = self.reminders_sent.iter().collect();
+        sent.sort();
+        for key in sent {
+            contents.push_str(&format!("sent\t{}\n", key));
+        }
+        fs::write(path, contents).with_context(|| format!("Could not write {}", path.display()))
+    }
+
+    /// Runs every job that is due and schedules its next run. Runs missed while the application
+    /// was closed are not repeated.
+    pub fn run_due(
+        &mut self,
+        bookings: &mut BookingList,
+        passengers: &mut PassengerList,
+        trains: &mut TrainList,
+        promotions: &mut PromotionList,
+        history: &mut BookingHistory,
+    ) -> Result<()> {
+        let now = self.clock.now();
+        let mut ran = false;
+        for index in 0..self.jobs.len() {
+            if self.jobs[index].next_run > now {
+                continue;
+            }
+            match self.jobs[index].kind {
+                JobKind::DepartureReminders => self.send_reminders(bookings, trains, history, now),
+                JobKind::CompleteTrips => complete_trips(bookings, trains, history, now),
+                JobKind::ExpireHolds => expire_holds(bookings, passengers, trains, promotions, history, now)?,
+            }
+            let job = &mut self.jobs[index];
+            job.next_run = now + Duration::minutes(i64::from(job.interval_minutes));
+            ran = true;
+        }
+
+        if ran {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Records a reminder in the booking history once a reminder window opens before departure.
+    /// Only the shortest open window is sent, so a late booking is not reminded twice.
+    fn send_reminders(
+        &mut self,
+        bookings: &BookingList,
+        trains: &TrainList,
+        history: &mut BookingHistory,
+        now: NaiveDateTime,
+    ) {
+        let mut booking_ids: Vec<&String> = bookings.keys().collect();
+        booking_ids.sort();
+        for booking_id in booking_ids {
+            let booking = &bookings[booking_id];
+            if booking.status != BookingStatus::Booked {
+                continue;
+            }
+            let Some((departure, _)) = trains
+                .get(&booking.train_line)
+                .and_then(|train| expected_times(train, booking.travel_date))
+            else {
+                continue;
+            };
+            if now >= departure {
+                continue;
+            }
+
+            let key = |hours: u32| {
+                format!(
+                    "{}|{}|{}|{}",
+                    booking.id, booking.train_line, booking.travel_date, hours
+                )
+            };
+            let Some(lead) = REMINDER_LEADS_HOURS
+                .iter()
+                .find(|hours| now >= departure - Duration::hours(i64::from(**hours)))
+            else {
+                continue;
+            };
+            if self.reminders_sent.contains(&key(*lead)) {
+                continue;
+            }
+
+            record_event(
+                history,
+                booking,
+                now,
+                EventKind::Reminder,
+                format!(
+                    "{}h reminder: train {} departs at {} on {}",
+                    lead,
+                    booking.train_line,
+                    departure.format("%H:%M"),
+                    departure.date()
+                ),
+            );
+            for hours in REMINDER_LEADS_HOURS.iter().filter(|hours| *hours >= lead) {
+                self.reminders_sent.insert(key(*hours));
+            }
+        }
+    }
+}
+
+/// Marks boarded trips as completed once the train has arrived.
+fn complete_trips(
+    bookings: &mut BookingList,
+    trains: &TrainList,
+    history: &mut BookingHistory,
+    now: NaiveDateTime,
+) {
+    for booking in bookings.values_mut() {
+        if booking.status != BookingStatus::Boarded {
+            continue;
+        }
+        let arrived = trains
+            .get(&booking.train_line)
+            .and_then(|train| expected_times(train, booking.travel_date))
+            .is_some_and(|(_, arrival)| now >= arrival);
+        if arrived {
+            booking.status = BookingStatus::Completed;
+            record_event(
+                history,
+                booking,
+                now,
+                EventKind::Completed,
+                "Trip completed".to_string(),
+            );
+        }
+    }
+}
+
+/// Cancels held bookings that were not paid in time, giving back the seat and any promo redemption.
+fn expire_holds(
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    trains: &mut TrainList,
+    promotions: &mut PromotionList,
+    history: &mut BookingHistory,
+    now: NaiveDateTime,
+) -> Result<()> {
+    let mut expired: Vec<String> = bookings
+        .values()
+        .filter(|booking| booking.held_until.is_some_and(|held_until| now >= held_until))
+        .map(|booking| booking.id.clone())
+        .collect();
+    expired.sort();
+    for booking_id in expired {
+        let booking = &bookings[&booking_id];
+        record_event(
+            history,
+            booking,
+            now,
+            EventKind::Cancelled,
+            "Seat released, the hold expired unpaid".to_string(),
+        );
+        if let Some(code) = &booking.promo_code {
+            release_promotion(promotions, code);
+        }
+        remove_booking(bookings, passengers, trains, booking_id)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boarding::board;
+    use crate::passengers::PassengerList;
+    use crate::test_support::{adult, book, express, travel_date};
+
+    fn setup() -> (BookingList, PassengerList, TrainList) {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+
+        passengers.insert("P1".to_string(), adult("P1", "John Doe"));
+        trains.insert(1, express());
+        book(&mut bookings, &mut passengers, &mut trains, "P1", 1).unwrap();
+
+        (bookings, passengers, trains)
+    }
+
+    fn reminders(history: &BookingHistory) -> usize {
+        history.iter().filter(|event| event.kind == EventKind::Reminder).count()
+    }
+
+    #[test]
+    fn test_reminders_24_hours_and_1_hour_before() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let mut promotions = PromotionList::new();
+        let mut history = BookingHistory::new();
+        let clock = ManualClock::new(travel_date().pred_opt().unwrap().and_hms_opt(9, 0, 0).unwrap());
+        let mut scheduler = Scheduler::new(Box::new(clock.clone()), None);
+
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert_eq!(reminders(&history), 0);
+
+        clock.advance(Duration::hours(2));
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert_eq!(reminders(&history), 1);
+        assert!(history[0]
+            .description
+            .starts_with("24h reminder: train 1 departs at 11:00"));
+
+        clock.advance(Duration::hours(1));
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert_eq!(reminders(&history), 1);
+
+        clock.advance(Duration::hours(22) + Duration::minutes(30));
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert_eq!(reminders(&history), 2);
+        assert!(history[1].description.starts_with("1h reminder"));
+    }
+
+    #[test]
+    fn test_jobs_wait_for_their_interval() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let mut promotions = PromotionList::new();
+        let mut history = BookingHistory::new();
+        let day_before = travel_date().pred_opt().unwrap();
+        let clock = ManualClock::new(day_before.and_hms_opt(10, 58, 0).unwrap());
+        let mut scheduler = Scheduler::new(Box::new(clock.clone()), None);
+
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert_eq!(scheduler.jobs[0].next_run, day_before.and_hms_opt(11, 3, 0).unwrap());
+
+        // The reminder window has opened, but the job is not due again yet
+        clock.advance(Duration::minutes(3));
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert_eq!(reminders(&history), 0);
+
+        clock.advance(Duration::minutes(2));
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert_eq!(reminders(&history), 1);
+    }
+
+    #[test]
+    fn test_boarded_trips_complete_after_arrival() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let mut promotions = PromotionList::new();
+        let mut history = BookingHistory::new();
+        let clock = ManualClock::new(travel_date().and_hms_opt(11, 30, 0).unwrap());
+        let mut scheduler = Scheduler::new(Box::new(clock.clone()), None);
+        board(&mut bookings, &passengers, 1, travel_date(), "P1_1_20240610").unwrap();
+
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert_eq!(bookings["P1_1_20240610"].status, BookingStatus::Boarded);
+
+        clock.advance(Duration::minutes(45));
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert_eq!(bookings["P1_1_20240610"].status, BookingStatus::Completed);
+        assert_eq!(history.last().unwrap().kind, EventKind::Completed);
+    }
+
+    #[test]
+    fn test_schedule_survives_restart() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let mut promotions = PromotionList::new();
+        let mut history = BookingHistory::new();
+        let path = std::env::temp_dir().join(format!("train-rs-scheduler-{}.txt", std::process::id()));
+        let clock = ManualClock::new(travel_date().and_hms_opt(10, 30, 0).unwrap());
+
+        let mut scheduler = Scheduler::load(&path, Box::new(clock.clone())).unwrap();
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert_eq!(reminders(&history), 1);
+
+        clock.advance(Duration::minutes(10));
+        let mut restarted = Scheduler::load(&path, Box::new(clock.clone())).unwrap();
+        restarted
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restarted.jobs[1], scheduler.jobs[1]);
+        assert_eq!(reminders(&history), 1);
+    }
+
+    #[test]
+    fn test_unpaid_holds_expire() {
+        let (mut bookings, mut passengers, mut trains) = setup();
+        let mut promotions = PromotionList::new();
+        let mut history = BookingHistory::new();
+        let clock = ManualClock::new(travel_date().and_hms_opt(8, 0, 0).unwrap());
+        let mut scheduler = Scheduler::new(Box::new(clock.clone()), None);
+        bookings.get_mut("P1_1_20240610").unwrap().held_until = Some(travel_date().and_hms_opt(8, 30, 0).unwrap());
+
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert!(bookings.contains_key("P1_1_20240610"));
+
+        clock.advance(Duration::minutes(30));
+        scheduler
+            .run_due(
+                &mut bookings,
+                &mut passengers,
+                &mut trains,
+                &mut promotions,
+                &mut history,
+            )
+            .unwrap();
+        assert!(bookings.is_empty());
+        assert!(trains[&1].passengers.is_empty());
+        assert_eq!(history.last().unwrap().kind, EventKind::Cancelled);
+    }
+}
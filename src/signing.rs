@@ -0,0 +1,248 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::NaiveDate;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::OsRng;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use crate::bookings::Booking;
+
+pub const SIGNING_KEY_PATH: &str = "ticket-signing.key";
+pub const VERIFYING_KEY_PATH: &str = "ticket-signing.pub";
+
+/// The ticket details a conductor can check offline.
+#[derive(Debug, PartialEq)]
+pub struct TicketPayload {
+    pub booking_id: String,
+    pub passenger_id: String,
+    pub train_line: u32,
+    pub travel_date: NaiveDate,
+    pub seat: u32,
+}
+
+impl TicketPayload {
+    pub fn from_booking(booking: &Booking) -> Self {
+        Self {
+            booking_id: booking.id.clone(),
+            passenger_id: booking.passenger_id.clone(),
+            train_line: booking.train_line,
+            travel_date: booking.travel_date,
+            seat: booking.seat,
+        }
+    }
+
+    /// Fails when the booking has moved since the ticket was issued, e.g. to another train or seat.
+    pub fn check_against(
+        &self,
+        booking: &Booking,
+    ) -> Result<()> {
+        if self.passenger_id != booking.passenger_id
+            || self.train_line != booking.train_line
+            || self.travel_date != booking.travel_date
+            || self.seat != booking.seat
+        {
+            return Err(anyhow!(
+                "Ticket for booking {} shows train {} on {} seat {}, but the booking is now train {} on {} seat {}",
+                self.booking_id,
+                self.train_line,
+                self.travel_date,
+                self.seat,
+                booking.train_line,
+                booking.travel_date,
+                booking.seat
+            ));
+        }
+        Ok(())
+    }
+
+    fn encode(&self) -> String {
+        [
+            self.booking_id.clone(),
+            self.passenger_id.clone(),
+            self.train_line.to_string(),
+            self.travel_date.to_string(),
+            self.seat.to_string(),
+        ]
+        .join("\n")
+    }
+
+    fn decode(input: &str) -> Result<Self> {
+        let fields: Vec<&str> = input.split('\n').collect();
+        if fields.len() != 5 {
+            return Err(anyhow!("Malformed ticket payload"));
+        }
+        Ok(Self {
+            booking_id: fields[0].to_string(),
+            passenger_id: fields[1].to_string(),
+            train_line: fields[2].parse().context("Malformed train line")?,
+            travel_date: NaiveDate::parse_from_str(fields[3], "%Y-%m-%d").context("Malformed travel date")?,
+            seat: fields[4].parse().context("Malformed seat")?,
+        })
+    }
+}
+
+/// Signs the payload, returning `payload.signature` with both parts base64url encoded.
+pub fn sign_ticket(
+    key: &SigningKey,
+    payload: &TicketPayload,
+) -> String {
+    let encoded = payload.encode();
+    let signature = key.sign(encoded.as_bytes());
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(encoded),
+        URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    )
+}
+
+/// Checks a scanned ticket against the public key, without needing the booking database.
+pub fn verify_ticket(
+    key: &VerifyingKey,
+    scanned: &str,
+) -> Result<TicketPayload> {
+    let (payload, signature) = scanned
+        .trim()
+        .split_once('.')
+        .ok_or_else(|| anyhow!("Not a signed ticket"))?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).context("Malformed ticket payload")?;
+    let signature: [u8; 64] = URL_SAFE_NO_PAD
+        .decode(signature)
+        .context("Malformed ticket signature")?
+        .try_into()
+        .map_err(|_| anyhow!("Malformed ticket signature"))?;
+
+    key.verify(&payload, &Signature::from_bytes(&signature))
+        .map_err(|_| anyhow!("Invalid ticket signature"))?;
+    TicketPayload::decode(&String::from_utf8(payload).context("Malformed ticket payload")?)
+}
+
+/// Loads the signing key, generating a new key pair and writing the public key next to it the
+/// first time.
+pub fn load_or_create_signing_key(
+    path: &Path,
+    public_path: &Path,
+) -> Result<SigningKey> {
+    if path.exists() {
+        let bytes: [u8; 32] = URL_SAFE_NO_PAD
+            .decode(fs::read_to_string(path)?.trim())
+            .context("Malformed signing key")?
+            .try_into()
+            .map_err(|_| anyhow!("Malformed signing key"))?;
+        return Ok(SigningKey::from_bytes(&bytes));
+    }
+
+    let key = SigningKey::generate(&mut OsRng);
+    write_private_key(path, &key).with_context(|| format!("Could not write {}", path.display()))?;
+    fs::write(public_path, URL_SAFE_NO_PAD.encode(key.verifying_key().to_bytes()))
+        .with_context(|| format!("Could not write {}", public_path.display()))?;
+    Ok(key)
+}
+
+// Only the owner may read the private key
+fn write_private_key(
+    path: &Path,
+    key: &SigningKey,
+) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options
+        .open(path)?
+        .write_all(URL_SAFE_NO_PAD.encode(key.to_bytes()).as_bytes())
+}
+
+pub fn load_verifying_key(path: &Path) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = URL_SAFE_NO_PAD
+        .decode(
+            fs::read_to_string(path)
+                .with_context(|| format!("Could not read {}", path.display()))?
+                .trim(),
+        )
+        .context("Malformed public key")?
+        .try_into()
+        .map_err(|_| anyhow!("Malformed public key"))?;
+    VerifyingKey::from_bytes(&bytes).context("Invalid public key")
+}
+
+/// Runs `train-rs verify <ticket> [public key file]`.
+pub fn verify_command(args: &[String]) -> Result<()> {
+    let scanned = args
+        .first()
+        .ok_or_else(|| anyhow!("Usage: train-rs verify <ticket> [public key file]"))?;
+    let key_path = args.get(1).map_or(VERIFYING_KEY_PATH, String::as_str);
+    let payload = verify_ticket(&load_verifying_key(Path::new(key_path))?, scanned)?;
+
+    println!(
+        "Valid ticket\nBooking: {}\nPassenger ID: {}\nTrain Line: {}\nDate: {}\nSeat: {}",
+        payload.booking_id, payload.passenger_id, payload.train_line, payload.travel_date, payload.seat
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload() -> TicketPayload {
+        TicketPayload {
+            booking_id: "P1_1".to_string(),
+            passenger_id: "P1".to_string(),
+            train_line: 1,
+            travel_date: NaiveDate::from_ymd_opt(2024, 6, 10).unwrap(),
+            seat: 12,
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify_ticket() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let scanned = sign_ticket(&key, &payload());
+
+        assert_eq!(verify_ticket(&key.verifying_key(), &scanned).unwrap(), payload());
+    }
+
+    #[test]
+    fn test_tampered_ticket_is_rejected() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let scanned = sign_ticket(&key, &payload());
+        let (_, signature) = scanned.split_once('.').unwrap();
+
+        let mut forged = payload();
+        forged.seat = 1;
+        let forged = format!("{}.{}", URL_SAFE_NO_PAD.encode(forged.encode()), signature);
+        assert!(verify_ticket(&key.verifying_key(), &forged).is_err());
+
+        let other_key = SigningKey::from_bytes(&[8; 32]);
+        assert!(verify_ticket(&other_key.verifying_key(), &scanned).is_err());
+        assert!(verify_ticket(&key.verifying_key(), "not a ticket").is_err());
+    }
+
+    #[test]
+    fn test_key_files_round_trip() {
+        let dir = std::env::temp_dir().join(format!("train-rs-keys-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (private, public) = (dir.join("ticket.key"), dir.join("ticket.pub"));
+
+        let key = load_or_create_signing_key(&private, &public).unwrap();
+        let reloaded = load_or_create_signing_key(&private, &public).unwrap();
+        let verifying_key = load_verifying_key(&public).unwrap();
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            fs::metadata(&private).unwrap().permissions().mode() & 0o777
+        };
+        fs::remove_dir_all(&dir).unwrap();
+
+        #[cfg(unix)]
+        assert_eq!(mode, 0o600);
+
+        assert_eq!(key.to_bytes(), reloaded.to_bytes());
+        assert_eq!(key.verifying_key(), verifying_key);
+    }
+}
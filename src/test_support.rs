@@ -0,0 +1,57 @@
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveTime};
+
+use crate::bookings::{add_booking, BookingList};
+use crate::minors::Supervision;
+use crate::passengers::{approximate_date_of_birth, Passenger, PassengerList};
+use crate::rules::RulePipeline;
+use crate::trains::{Train, TrainList};
+
+pub fn travel_date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()
+}
+
+// Train 1 from Toronto to Hamilton, 11:00 to 12:00 with 100 seats
+pub fn express() -> Train {
+    Train::new(
+        1,
+        "Express".to_string(),
+        100,
+        "Toronto".to_string(),
+        "Hamilton".to_string(),
+        NaiveTime::from_hms_opt(11, 0, 0).unwrap(),
+        NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+    )
+}
+
+// Aged 30 on the travel date
+pub fn adult(
+    id: &str,
+    name: &str,
+) -> Passenger {
+    Passenger::new(
+        id.to_string(),
+        name.to_string(),
+        approximate_date_of_birth(30, travel_date()),
+    )
+}
+
+// Books the passenger alone on the travel date through the default rules
+pub fn book(
+    bookings: &mut BookingList,
+    passengers: &mut PassengerList,
+    trains: &mut TrainList,
+    passenger_id: &str,
+    train_line: u32,
+) -> Result<String> {
+    add_booking(
+        bookings,
+        passengers,
+        trains,
+        &RulePipeline::default(),
+        passenger_id.to_string(),
+        train_line,
+        travel_date(),
+        Supervision::Alone,
+    )
+}
@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::SigningKey;
+use qrcode::render::{svg, unicode};
+use qrcode::{Color, QrCode};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::ancillaries::format_ancillaries;
+use crate::bookings::Booking;
+use crate::passengers::Passenger;
+use crate::signing::{sign_ticket, TicketPayload};
+use crate::trains::Train;
+
+const PNG_MODULE_SIZE: usize = 8; // Pixels per QR module
+const PNG_QUIET_ZONE: usize = 4; // Light modules around the code
+
+pub struct Ticket<'a> {
+    pub booking: &'a Booking,
+    pub passenger: &'a Passenger,
+    pub train: &'a Train,
+    pub code: String, // Signed payload encoded in the QR code
+}
+
+impl<'a> Ticket<'a> {
+    pub fn new(
+        booking: &'a Booking,
+        passenger: &'a Passenger,
+        train: &'a Train,
+        key: &SigningKey,
+    ) -> Self {
+        Self {
+            booking,
+            passenger,
+            train,
+            code: sign_ticket(key, &TicketPayload::from_booking(booking)),
+        }
+    }
+
+    pub fn reference(&self) -> &str {
+        &self.booking.id
+    }
+
+    pub fn qr_code(&self) -> Result<QrCode> {
+        QrCode::new(self.code.as_bytes()).context("Could not encode the ticket")
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "TICKET {}\nPassenger: {}\nLine: {}\nTrain: {}\nFrom: {}\nTo: {}\nDate: {}\nDeparture: {}\nArrival: {}\nSeat: {}\nExtras: {}\nCode: {}\n",
+            self.reference(),
+            self.passenger.name,
+            self.train.line,
+            self.train.name,
+            self.train.origin,
+            self.train.destination,
+            self.booking.travel_date,
+            self.train.departure.format("%H:%M"),
+            self.train.arrival.format("%H:%M"),
+            self.booking.seat,
+            format_ancillaries(&self.booking.ancillaries),
+            self.code,
+        )
+    }
+
+    /// Renders the QR code with unicode half blocks, inverted so it scans on dark terminals.
+    pub fn qr_terminal(&self) -> Result<String> {
+        Ok(self
+            .qr_code()?
+            .render::<unicode::Dense1x2>()
+            .dark_color(unicode::Dense1x2::Light)
+            .light_color(unicode::Dense1x2::Dark)
+            .build())
+    }
+
+    pub fn qr_svg(&self) -> Result<String> {
+        Ok(self.qr_code()?.render::<svg::Color>().min_dimensions(200, 200).build())
+    }
+
+    pub fn write_qr_png(
+        &self,
+        path: &Path,
+    ) -> Result<()> {
+        let code = self.qr_code()?;
+        let modules = code.width();
+        let colors = code.to_colors();
+        let size = (modules + PNG_QUIET_ZONE * 2) * PNG_MODULE_SIZE;
+
+        let mut pixels = vec![255u8; size * size];
+        for y in 0..size {
+            for x in 0..size {
+                let module_x = (x / PNG_MODULE_SIZE).checked_sub(PNG_QUIET_ZONE);
+                let module_y = (y / PNG_MODULE_SIZE).checked_sub(PNG_QUIET_ZONE);
+                if let (Some(module_x), Some(module_y)) = (module_x, module_y) {
+                    if module_x < modules && module_y < modules && colors[module_y * modules + module_x] == Color::Dark
+                    {
+                        pixels[y * size + x] = 0;
+                    }
+                }
+            }
+        }
+
+        let file = File::create(path).with_context(|| format!("Could not create {}", path.display()))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), size as u32, size as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookings::BookingList;
+    use crate::passengers::PassengerList;
+    use crate::test_support::{adult, book, express};
+    use crate::trains::TrainList;
+
+    fn setup() -> (BookingList, PassengerList, TrainList) {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+
+        passengers.insert("P1".to_string(), adult("P1", "John Doe"));
+        trains.insert(1, express());
+        book(&mut bookings, &mut passengers, &mut trains, "P1", 1).unwrap();
+
+        (bookings, passengers, trains)
+    }
+
+    #[test]
+    fn test_ticket_text() {
+        let (bookings, passengers, trains) = setup();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let ticket = Ticket::new(&bookings["P1_1_20240610"], &passengers["P1"], &trains[&1], &key);
+
+        let text = ticket.to_text();
+        assert!(text.starts_with("TICKET P1_1"));
+        assert!(text.contains("Passenger: John Doe"));
+        assert!(text.contains("From: Toronto\nTo: Hamilton"));
+        assert!(text.contains("Departure: 11:00\nArrival: 12:00"));
+        assert!(text.contains("Seat: 1\nExtras: None"));
+        assert!(text.contains(&format!("Code: {}", ticket.code)));
+    }
+
+    #[test]
+    fn test_ticket_code_verifies() {
+        let (bookings, passengers, trains) = setup();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let ticket = Ticket::new(&bookings["P1_1_20240610"], &passengers["P1"], &trains[&1], &key);
+
+        let payload = crate::signing::verify_ticket(&key.verifying_key(), &ticket.code).unwrap();
+        assert_eq!(payload.booking_id, "P1_1_20240610");
+        assert_eq!(payload.seat, 1);
+    }
+
+    #[test]
+    fn test_ticket_qr_exports() {
+        let (bookings, passengers, trains) = setup();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let ticket = Ticket::new(&bookings["P1_1_20240610"], &passengers["P1"], &trains[&1], &key);
+
+        assert!(!ticket.qr_terminal().unwrap().is_empty());
+        assert!(ticket.qr_svg().unwrap().starts_with("<?xml"));
+
+        let path = std::env::temp_dir().join(format!("ticket-test-{}.png", std::process::id()));
+        ticket.write_qr_png(&path).unwrap();
+        let header = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&header[1..4], b"PNG");
+    }
+}
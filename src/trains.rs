@@ -1,19 +1,35 @@
 use crate::actions::{add_remove_prompt, Action};
-use crate::helper::{clear_screen, continue_prompt};
+use crate::ancillaries::{format_ancillary_offers, parse_ancillary_offers, AncillaryOffer};
+use crate::assistance::DEFAULT_ACCESSIBLE_SPACES;
+use crate::disruptions::ServiceDisruption;
+use crate::fares::{
+    format_fare_buckets, format_load_bands, parse_fare_buckets, parse_load_bands, FareBucket, PricingMode,
+    DEFAULT_LOAD_BANDS,
+};
+use crate::helper::{clear_screen, continue_prompt, format_money, parse_money};
+use crate::minors::{default_minor_rules, format_minor_rules, parse_minor_rules, MinorRule};
 use anyhow::{Context, Result};
-use chrono::NaiveTime;
-use requestty::{self, Question};
+use chrono::{NaiveDate, NaiveTime};
+use requestty::{self, Answers, Question};
 use std::collections::{HashMap, HashSet};
 
 pub struct Train {
     pub line: u32,
     pub name: String,
     pub capacity: u32,
+    pub distance_km: u32,       // Route length, used for distance-based loyalty points
+    pub accessible_spaces: u32, // Wheelchair spaces, counted within the capacity
     pub origin: String,
     pub destination: String,
     pub departure: NaiveTime,
     pub arrival: NaiveTime,
-    pub passengers: HashSet<String>, // Set of passenger IDs
+    pub fare: u32, // Base fare in cents
+    pub pricing: PricingMode,
+    pub fare_buckets: Vec<FareBucket>, // Sorted from cheapest to most expensive, empty uses the base fare
+    pub minor_rules: Vec<MinorRule>,   // Supervision required for young passengers
+    pub ancillaries: Vec<AncillaryOffer>, // Extras sold on the train, empty sells none
+    pub passengers: HashSet<String>,   // Set of passenger IDs
+    pub disruptions: HashMap<NaiveDate, ServiceDisruption>, // Delays and cancellations by travel date
 }
 
 impl Train {
@@ -30,11 +46,19 @@ impl Train {
             line,
             name,
             capacity,
+            distance_km: 0,
+            accessible_spaces: DEFAULT_ACCESSIBLE_SPACES,
             origin,
             destination,
             departure,
             arrival,
+            fare: 0,
+            pricing: PricingMode::Fixed,
+            fare_buckets: Vec::new(),
+            minor_rules: default_minor_rules(),
+            ancillaries: Vec::new(),
             passengers: HashSet::new(),
+            disruptions: HashMap::new(),
         }
     }
 }
@@ -51,8 +75,21 @@ pub fn manage_trains(trains: &mut TrainList) -> Result<()> {
                 } else {
                     for (line, train) in &mut *trains {
                         println!(
-                            "Line: {}\nName: {}\nCapacity: {}\nOrigin: {}\nDestination: {}\nDeparture: {}\nArrival: {}\n====================\n",
-                            line, train.name, train.capacity, train.origin, train.destination, train.departure, train.arrival
+                            "Line: {}\nName: {}\nCapacity: {}\nWheelchair spaces: {}\nOrigin: {}\nDestination: {}\nDistance: {} km\nDeparture: {}\nArrival: {}\nFare: {}\nPricing: {}\nFare buckets: {}\nMinor rules: {}\nExtras: {}\n====================\n",
+                            line,
+                            train.name,
+                            train.capacity,
+                            train.accessible_spaces,
+                            train.origin,
+                            train.destination,
+                            train.distance_km,
+                            train.departure,
+                            train.arrival,
+                            format_money(train.fare),
+                            format_pricing(&train.pricing),
+                            format_bucket_availability(&train.fare_buckets),
+                            format_minor_rules(&train.minor_rules),
+                            format_offers(&train.ancillaries)
                         );
                     }
                 }
@@ -105,12 +142,23 @@ fn add_train() -> Result<Train> {
         Question::input("capacity")
             .message("Enter the capacity of the train")
             .build(),
+        Question::input("accessible_spaces")
+            .message("Enter the number of wheelchair spaces")
+            .default(DEFAULT_ACCESSIBLE_SPACES.to_string())
+            .build(),
+        Question::input("distance_km")
+            .message("Enter the route length in km")
+            .default("0")
+            .build(),
         Question::input("departure")
             .message("Enter the departure time of the train (HH:MM)")
             .build(),
         Question::input("arrival")
             .message("Enter the arrival time of the train (HH:MM)")
             .build(),
+        Question::input("fare")
+            .message("Enter the base fare of the train (e.g. 12.50)")
+            .build(),
     ];
 
     let train = requestty::prompt(questions)?;
@@ -121,7 +169,7 @@ fn add_train() -> Result<Train> {
     let arrival_time = NaiveTime::parse_from_str(train.get("arrival").unwrap().as_string().unwrap(), "%H:%M")
         .context("Invalid arrival time")?;
 
-    Ok(Train::new(
+    let mut new_train = Train::new(
         train.get("line").unwrap().as_string().unwrap().parse()?,
         train.get("name").unwrap().as_string().unwrap().to_string(),
         train.get("capacity").unwrap().as_string().unwrap().parse()?,
@@ -129,7 +177,49 @@ fn add_train() -> Result<Train> {
         train.get("destination").unwrap().as_string().unwrap().to_string(),
         departure_time,
         arrival_time,
-    ))
+    );
+    new_train.fare = parse_money(train.get("fare").unwrap().as_string().unwrap())?;
+    new_train.distance_km = train.get("distance_km").unwrap().as_string().unwrap().parse()?;
+    new_train.accessible_spaces = train.get("accessible_spaces").unwrap().as_string().unwrap().parse()?;
+
+    Ok(new_train)
+}
+
+fn format_pricing(pricing: &PricingMode) -> String {
+    match pricing {
+        PricingMode::Fixed => "Fixed".to_string(),
+        PricingMode::LoadBased(bands) => format!("Load-based ({})", format_load_bands(bands)),
+    }
+}
+
+fn format_bucket_availability(buckets: &[FareBucket]) -> String {
+    if buckets.is_empty() {
+        return "None".to_string();
+    }
+    buckets
+        .iter()
+        .map(|bucket| {
+            format!(
+                "{} {} (limit {} per date, {} sold)",
+                bucket.code,
+                format_money(bucket.fare),
+                bucket.limit,
+                bucket.total_sold()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_offers(offers: &[AncillaryOffer]) -> String {
+    if offers.is_empty() {
+        return "None".to_string();
+    }
+    offers
+        .iter()
+        .map(|offer| format!("{} {} (limit {})", offer.kind, format_money(offer.price), offer.limit))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 fn remove_train(trains: &mut TrainList) -> Result<()> {
@@ -166,6 +256,10 @@ fn edit_train(trains: &mut TrainList) -> Result<()> {
             .message("Enter the new capacity of the train")
             .default(trains[&line].capacity.to_string())
             .build(),
+        Question::input("accessible_spaces")
+            .message("Enter the new number of wheelchair spaces")
+            .default(trains[&line].accessible_spaces.to_string())
+            .build(),
         Question::input("origin")
             .message("Enter the new origin of the train")
             .default(trains[&line].origin.clone())
@@ -174,6 +268,10 @@ fn edit_train(trains: &mut TrainList) -> Result<()> {
             .message("Enter the new destination of the train")
             .default(trains[&line].destination.clone())
             .build(),
+        Question::input("distance_km")
+            .message("Enter the new route length in km")
+            .default(trains[&line].distance_km.to_string())
+            .build(),
         Question::input("departure")
             .message("Enter the new departure time of the train (HH:MM)")
             .default(trains[&line].departure.format("%H:%M").to_string())
@@ -182,6 +280,39 @@ fn edit_train(trains: &mut TrainList) -> Result<()> {
             .message("Enter the new arrival time of the train (HH:MM)")
             .default(trains[&line].arrival.format("%H:%M").to_string())
             .build(),
+        Question::input("fare")
+            .message("Enter the new base fare of the train")
+            .default(format_money(trains[&line].fare))
+            .build(),
+        Question::select("pricing")
+            .message("Select the pricing mode")
+            .choice("Fixed")
+            .choice("Load-based")
+            .default(match trains[&line].pricing {
+                PricingMode::Fixed => 0,
+                PricingMode::LoadBased(_) => 1,
+            })
+            .build(),
+        Question::input("load_bands")
+            .message("Enter the load bands as load%:fare% pairs (e.g. 0:100, 50:125, 80:150)")
+            .default(match &trains[&line].pricing {
+                PricingMode::Fixed => format_load_bands(&DEFAULT_LOAD_BANDS),
+                PricingMode::LoadBased(bands) => format_load_bands(bands),
+            })
+            .when(|answers: &Answers| answers["pricing"].as_list_item().unwrap().index == 1)
+            .build(),
+        Question::input("fare_buckets")
+            .message("Enter the fare buckets as code:fare:limit (e.g. Q:15.00:20, Y:30.00:100, blank for none)")
+            .default(format_fare_buckets(&trains[&line].fare_buckets))
+            .build(),
+        Question::input("minor_rules")
+            .message("Enter the minor rules as age[@HH:MM]:adult|escort (e.g. 8:adult, 16:escort, blank for none)")
+            .default(format_minor_rules(&trains[&line].minor_rules))
+            .build(),
+        Question::input("ancillaries")
+            .message("Enter the extras as bike|luggage|pet|meal:price:limit (e.g. bike:5.00:4, blank for none)")
+            .default(format_ancillary_offers(&trains[&line].ancillaries))
+            .build(),
     ];
 
     let answers = requestty::prompt(questions)?;
@@ -189,12 +320,28 @@ fn edit_train(trains: &mut TrainList) -> Result<()> {
     if let Some(train) = trains.get_mut(&line) {
         train.name = answers.get("name").unwrap().as_string().unwrap().to_string();
         train.capacity = answers.get("capacity").unwrap().as_string().unwrap().parse()?;
+        train.accessible_spaces = answers.get("accessible_spaces").unwrap().as_string().unwrap().parse()?;
         train.origin = answers.get("origin").unwrap().as_string().unwrap().to_string();
         train.destination = answers.get("destination").unwrap().as_string().unwrap().to_string();
+        train.distance_km = answers.get("distance_km").unwrap().as_string().unwrap().parse()?;
         train.departure = NaiveTime::parse_from_str(answers.get("departure").unwrap().as_string().unwrap(), "%H:%M")
             .context("Invalid departure time")?;
         train.arrival = NaiveTime::parse_from_str(answers.get("arrival").unwrap().as_string().unwrap(), "%H:%M")
             .context("Invalid arrival time")?;
+        train.fare = parse_money(answers.get("fare").unwrap().as_string().unwrap())?;
+        train.pricing = match answers.get("pricing").unwrap().as_list_item().unwrap().index {
+            0 => PricingMode::Fixed,
+            1 => PricingMode::LoadBased(parse_load_bands(
+                answers.get("load_bands").unwrap().as_string().unwrap(),
+            )?),
+            _ => unreachable!(),
+        };
+        train.fare_buckets = parse_fare_buckets(
+            answers.get("fare_buckets").unwrap().as_string().unwrap(),
+            &train.fare_buckets,
+        )?;
+        train.minor_rules = parse_minor_rules(answers.get("minor_rules").unwrap().as_string().unwrap())?;
+        train.ancillaries = parse_ancillary_offers(answers.get("ancillaries").unwrap().as_string().unwrap())?;
     }
 
     Ok(())
@@ -0,0 +1,542 @@
+use anyhow::{anyhow, Result};
+use chrono::{Local, Months, NaiveDate};
+use rand_core::{OsRng, RngCore};
+use requestty::{self, Answers, Question};
+use std::collections::HashMap;
+
+use crate::actions::{add_remove_prompt, Action};
+use crate::bookings::{BookingList, BookingStatus};
+use crate::disruptions::Disruption;
+use crate::helper::{clear_screen, continue_prompt, format_money, parse_date, parse_money};
+use crate::passengers::PassengerList;
+use crate::payments::{Ledger, PaymentMethod};
+use crate::trains::TrainList;
+
+pub const CREDIT_VALID_MONTHS: u32 = 12;
+pub const GIFT_CARD_VALID_MONTHS: u32 = 36;
+pub const COMPENSATION_BANDS: [(u32, u32); 2] = [(60, 25), (120, 50)]; // (Minutes late, percent of the fare)
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoucherKind {
+    Credit,       // Refund kept as travel credit instead of going back to the original payment
+    Compensation, // Issued automatically for a delayed or cancelled train
+    GiftCard,     // Sold for money and redeemable by anyone holding the code
+}
+
+impl VoucherKind {
+    fn prefix(&self) -> &'static str {
+        match self {
+            VoucherKind::Credit => "CR",
+            VoucherKind::Compensation => "CP",
+            VoucherKind::GiftCard => "GC",
+        }
+    }
+
+    fn valid_months(&self) -> u32 {
+        match self {
+            VoucherKind::Credit | VoucherKind::Compensation => CREDIT_VALID_MONTHS,
+            VoucherKind::GiftCard => GIFT_CARD_VALID_MONTHS,
+        }
+    }
+}
+
+impl std::fmt::Display for VoucherKind {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            VoucherKind::Credit => write!(f, "Travel credit"),
+            VoucherKind::Compensation => write!(f, "Compensation"),
+            VoucherKind::GiftCard => write!(f, "Gift card"),
+        }
+    }
+}
+
+pub struct Voucher {
+    pub code: String,
+    pub kind: VoucherKind,
+    pub passenger_id: Option<String>, // Passenger the voucher was issued to, if any
+    pub booking_id: Option<String>,   // Booking the voucher was issued for, if any
+    pub amount: u32,                  // Amount issued in cents
+    pub balance: u32,                 // Amount left to redeem in cents
+    pub issued: NaiveDate,
+    pub expires: NaiveDate, // Last day the voucher can be redeemed
+}
+
+/// Every voucher, travel credit and gift card issued, by code.
+#[derive(Default)]
+pub struct VoucherBook {
+    pub vouchers: HashMap<String, Voucher>,
+}
+
+impl VoucherBook {
+    /// Random code such as "GC-7K2M-Q9XA", so codes cannot be guessed from one another.
+    fn new_code(
+        &self,
+        kind: VoucherKind,
+    ) -> String {
+        loop {
+            let mut chars = (0..8).map(|_| CODE_ALPHABET[OsRng.next_u32() as usize % CODE_ALPHABET.len()] as char);
+            let first: String = chars.by_ref().take(4).collect();
+            let second: String = chars.collect();
+            let code = format!("{}-{}-{}", kind.prefix(), first, second);
+            if !self.vouchers.contains_key(&code) {
+                return code;
+            }
+        }
+    }
+
+    pub fn issue(
+        &mut self,
+        kind: VoucherKind,
+        amount: u32,
+        passenger_id: Option<String>,
+        booking_id: Option<String>,
+        issued: NaiveDate,
+    ) -> String {
+        let code = self.new_code(kind);
+        self.vouchers.insert(
+            code.clone(),
+            Voucher {
+                code: code.clone(),
+                kind,
+                passenger_id,
+                booking_id,
+                amount,
+                balance: amount,
+                issued,
+                expires: issued + Months::new(kind.valid_months()),
+            },
+        );
+        code
+    }
+
+    pub fn get(
+        &self,
+        code: &str,
+    ) -> Option<&Voucher> {
+        self.vouchers.get(&code.trim().to_uppercase())
+    }
+
+    /// Takes up to the amount from the voucher's balance and returns what was taken.
+    pub fn redeem(
+        &mut self,
+        code: &str,
+        amount: u32,
+        today: NaiveDate,
+    ) -> Result<u32> {
+        let voucher = self
+            .vouchers
+            .get_mut(&code.trim().to_uppercase())
+            .ok_or_else(|| anyhow!("Voucher {} not found", code.trim()))?;
+        if today > voucher.expires {
+            return Err(anyhow!("Voucher {} expired on {}", voucher.code, voucher.expires));
+        }
+        if voucher.balance == 0 {
+            return Err(anyhow!("Voucher {} has been used up", voucher.code));
+        }
+        let taken = amount.min(voucher.balance);
+        voucher.balance -= taken;
+        Ok(taken)
+    }
+
+    /// Puts an amount back on a voucher when a payment made with it is refunded.
+    pub fn credit(
+        &mut self,
+        code: &str,
+        amount: u32,
+    ) -> Result<()> {
+        let voucher = self
+            .vouchers
+            .get_mut(code)
+            .ok_or_else(|| anyhow!("Voucher {} not found", code))?;
+        voucher.balance += amount;
+        Ok(())
+    }
+
+    /// Total compensation already issued for a booking.
+    fn compensation_issued(
+        &self,
+        booking_id: &str,
+    ) -> u32 {
+        self.vouchers
+            .values()
+            .filter(|voucher| {
+                voucher.kind == VoucherKind::Compensation && voucher.booking_id.as_deref() == Some(booking_id)
+            })
+            .map(|voucher| voucher.amount)
+            .sum()
+    }
+}
+
+/// Percent of the fare owed for a disruption. Cancellations are owed the highest band.
+pub fn compensation_percent(disruption: &Disruption) -> u32 {
+    match disruption {
+        Disruption::Cancelled => COMPENSATION_BANDS
+            .iter()
+            .map(|(_, percent)| *percent)
+            .max()
+            .unwrap_or(0),
+        Disruption::Delayed(minutes) => COMPENSATION_BANDS
+            .iter()
+            .filter(|(late, _)| minutes >= late)
+            .map(|(_, percent)| *percent)
+            .max()
+            .unwrap_or(0),
+    }
+}
+
+/// Compensates each booking on the disrupted train for what is owed but not yet issued.
+pub fn issue_compensation(
+    vouchers: &mut VoucherBook,
+    bookings: &BookingList,
+    trains: &TrainList,
+    train_line: u32,
+    travel_date: NaiveDate,
+    today: NaiveDate,
+) -> Vec<(String, String, u32)> {
+    let Some(service) = trains
+        .get(&train_line)
+        .and_then(|train| train.disruptions.get(&travel_date))
+    else {
+        return Vec::new();
+    };
+    let percent = compensation_percent(&service.disruption);
+
+    let mut affected: Vec<_> = bookings
+        .values()
+        .filter(|booking| booking.train_line == train_line && booking.travel_date == travel_date)
+        .filter(|booking| matches!(booking.status, BookingStatus::Booked | BookingStatus::Boarded))
+        .collect();
+    affected.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut issued = Vec::new();
+    for booking in affected {
+        let due = booking.fare.saturating_sub(booking.discount) * percent / 100;
+        let owed = due.saturating_sub(vouchers.compensation_issued(&booking.id));
+        if owed == 0 {
+            continue;
+        }
+        let code = vouchers.issue(
+            VoucherKind::Compensation,
+            owed,
+            Some(booking.passenger_id.clone()),
+            Some(booking.id.clone()),
+            today,
+        );
+        issued.push((booking.id.clone(), code, owed));
+    }
+    issued
+}
+
+fn print_voucher(
+    voucher: &Voucher,
+    passengers: &PassengerList,
+) {
+    let holder = match &voucher.passenger_id {
+        Some(passenger_id) => format!(
+            ", {}",
+            passengers
+                .get(passenger_id)
+                .map_or(passenger_id.as_str(), |passenger| passenger.name.as_str())
+        ),
+        None => String::new(),
+    };
+    println!(
+        "{}: {}{}, {} of {} left, issued {}, expires {}{}",
+        voucher.code,
+        voucher.kind,
+        holder,
+        format_money(voucher.balance),
+        format_money(voucher.amount),
+        voucher.issued,
+        voucher.expires,
+        voucher
+            .booking_id
+            .as_ref()
+            .map(|booking_id| format!(", for booking {}", booking_id))
+            .unwrap_or_default()
+    );
+}
+
+fn select_voucher(
+    vouchers: &VoucherBook,
+    message: &str,
+) -> Result<String> {
+    let mut codes: Vec<&String> = vouchers.vouchers.keys().collect();
+    codes.sort();
+    let selection = requestty::prompt_one(Question::select("voucher").message(message).choices(codes).build())?;
+    Ok(selection.as_list_item().unwrap().text.clone())
+}
+
+/// Sells a gift card or issues goodwill travel credit.
+fn add_voucher(
+    ledger: &mut Ledger,
+    passengers: &PassengerList,
+) -> Result<()> {
+    let mut passenger_choices: Vec<String> = passengers
+        .values()
+        .map(|passenger| format!("{}, {}", passenger.id, passenger.name))
+        .collect();
+    passenger_choices.sort();
+    let has_passengers = !passenger_choices.is_empty();
+    let methods: Vec<PaymentMethod> = PaymentMethod::ALL
+        .into_iter()
+        .filter(|method| {
+            !matches!(
+                method,
+                PaymentMethod::Points | PaymentMethod::Voucher | PaymentMethod::Account
+            )
+        })
+        .collect();
+
+    let answers = requestty::prompt(vec![
+        Question::select("kind")
+            .message("Select voucher type")
+            .choice("Gift card")
+            .choice("Travel credit")
+            .build(),
+        Question::select("passenger")
+            .message("Select passenger")
+            .choices(passenger_choices)
+            .when(move |answers: &Answers| has_passengers && answers["kind"].as_list_item().unwrap().index == 1)
+            .build(),
+        Question::input("amount")
+            .message("Enter the amount (e.g. 50.00)")
+            .build(),
+        Question::select("payment_method")
+            .message("Select payment method")
+            .choices(methods.iter().map(|method| method.to_string()))
+            .when(|answers: &Answers| answers["kind"].as_list_item().unwrap().index == 0)
+            .build(),
+    ])?;
+
+    let amount = parse_money(answers["amount"].as_string().unwrap())?;
+    if amount == 0 {
+        return Err(anyhow!("A voucher must be worth more than {}", format_money(0)));
+    }
+    let now = Local::now().naive_local();
+    match answers["kind"].as_list_item().unwrap().index {
+        0 => {
+            let method = methods[answers["payment_method"].as_list_item().unwrap().index];
+            let code = ledger
+                .vouchers
+                .issue(VoucherKind::GiftCard, amount, None, None, now.date());
+            if let Err(e) = ledger.charge(&code, amount, method, now) {
+                ledger.vouchers.vouchers.remove(&code);
+                return Err(e);
+            }
+            println!(
+                "Sold gift card {}, charged {} by {}",
+                code,
+                format_money(amount),
+                method
+            );
+        }
+        _ => {
+            let passenger_id = answers
+                .get("passenger")
+                .ok_or_else(|| anyhow!("Add passengers before issuing travel credit"))?
+                .as_list_item()
+                .unwrap()
+                .text
+                .split(',')
+                .next()
+                .unwrap()
+                .trim()
+                .to_string();
+            let code = ledger
+                .vouchers
+                .issue(VoucherKind::Credit, amount, Some(passenger_id), None, now.date());
+            println!("Issued travel credit {} for {}", code, format_money(amount));
+        }
+    }
+    Ok(())
+}
+
+fn edit_voucher(vouchers: &mut VoucherBook) -> Result<()> {
+    let code = select_voucher(vouchers, "Select voucher to edit")?;
+    let voucher = vouchers.vouchers.get_mut(&code).unwrap();
+    let answer = requestty::prompt_one(
+        Question::input("expires")
+            .message("Enter the last day the voucher can be used (YYYY-MM-DD)")
+            .default(voucher.expires.to_string())
+            .build(),
+    )?;
+    let expires = parse_date(answer.as_string().unwrap())?;
+    if expires < voucher.issued {
+        return Err(anyhow!("Voucher {} cannot expire before {}", code, voucher.issued));
+    }
+    voucher.expires = expires;
+    Ok(())
+}
+
+/// Cancels a voucher that has not been redeemed yet. Refunds for gift cards are left to the agent.
+fn remove_voucher(vouchers: &mut VoucherBook) -> Result<()> {
+    let code = select_voucher(vouchers, "Select voucher to cancel")?;
+    let voucher = &vouchers.vouchers[&code];
+    if voucher.balance < voucher.amount {
+        return Err(anyhow!(
+            "Voucher {} has already been used for {}",
+            code,
+            format_money(voucher.amount - voucher.balance)
+        ));
+    }
+    vouchers.vouchers.remove(&code);
+    println!("Cancelled voucher {}", code);
+    Ok(())
+}
+
+pub fn manage_vouchers(
+    ledger: &mut Ledger,
+    passengers: &PassengerList,
+) -> Result<()> {
+    loop {
+        clear_screen();
+        let has_vouchers = !ledger.vouchers.vouchers.is_empty();
+        match add_remove_prompt()? {
+            Action::List => {
+                if !has_vouchers {
+                    println!("No vouchers found");
+                }
+                let mut vouchers: Vec<&Voucher> = ledger.vouchers.vouchers.values().collect();
+                vouchers.sort_by(|a, b| (a.issued, &a.code).cmp(&(b.issued, &b.code)));
+                for voucher in vouchers {
+                    print_voucher(voucher, passengers);
+                }
+                continue_prompt();
+            }
+            Action::Add => {
+                if let Err(e) = add_voucher(ledger, passengers) {
+                    println!("Error: {}", e);
+                }
+                continue_prompt();
+            }
+            Action::Edit => {
+                if !has_vouchers {
+                    println!("No vouchers to edit");
+                    continue_prompt();
+                } else if let Err(e) = edit_voucher(&mut ledger.vouchers) {
+                    println!("Error: {}", e);
+                    continue_prompt();
+                }
+            }
+            Action::Remove => {
+                if !has_vouchers {
+                    println!("No vouchers to cancel");
+                } else if let Err(e) = remove_voucher(&mut ledger.vouchers) {
+                    println!("Error: {}", e);
+                }
+                continue_prompt();
+            }
+            Action::Back => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disruptions::{record_disruption, ServiceDisruption};
+    use crate::payments::{Ledger, MockProcessor};
+    use crate::test_support::{adult, book, express, travel_date};
+
+    #[test]
+    fn test_redeem_and_expiry() {
+        let mut vouchers = VoucherBook::default();
+        let code = vouchers.issue(VoucherKind::GiftCard, 5000, None, None, travel_date());
+        assert!(code.starts_with("GC-"));
+        assert_eq!(code.len(), 12);
+        assert_eq!(
+            vouchers.get(&code.to_lowercase()).unwrap().expires,
+            NaiveDate::from_ymd_opt(2027, 6, 10).unwrap()
+        );
+
+        assert_eq!(vouchers.redeem(&code, 3000, travel_date()).unwrap(), 3000);
+        assert_eq!(vouchers.redeem(&code, 3000, travel_date()).unwrap(), 2000);
+        assert!(vouchers.redeem(&code, 100, travel_date()).is_err());
+        vouchers.credit(&code, 1000).unwrap();
+        assert_eq!(vouchers.get(&code).unwrap().balance, 1000);
+
+        let credit = vouchers.issue(VoucherKind::Credit, 1000, Some("P1".to_string()), None, travel_date());
+        let expired = NaiveDate::from_ymd_opt(2025, 6, 11).unwrap();
+        assert!(vouchers.redeem(&credit, 500, expired).is_err());
+        assert!(vouchers.redeem("CR-NONE-NONE", 500, travel_date()).is_err());
+    }
+
+    #[test]
+    fn test_redeem_on_expiry_day_and_after_use() {
+        let mut vouchers = VoucherBook::default();
+        let code = vouchers.issue(VoucherKind::Credit, 1000, Some("P1".to_string()), None, travel_date());
+        let last_day = vouchers.get(&code).unwrap().expires;
+        assert_eq!(last_day, NaiveDate::from_ymd_opt(2025, 6, 10).unwrap());
+
+        // Still good on its last day, and a partial redeem leaves the rest for later
+        assert_eq!(vouchers.redeem(&code, 400, last_day).unwrap(), 400);
+        assert_eq!(vouchers.get(&code).unwrap().balance, 600);
+        assert_eq!(
+            vouchers
+                .redeem(&code, 400, last_day.succ_opt().unwrap())
+                .unwrap_err()
+                .to_string(),
+            format!("Voucher {} expired on 2025-06-10", code)
+        );
+        assert_eq!(vouchers.get(&code).unwrap().balance, 600);
+
+        assert_eq!(vouchers.redeem(&code, 5000, last_day).unwrap(), 600);
+        assert_eq!(
+            vouchers.redeem(&code, 1, last_day).unwrap_err().to_string(),
+            format!("Voucher {} has been used up", code)
+        );
+
+        // An expired voucher leaves nothing in the ledger and the amount to pay another way
+        let mut ledger = Ledger::new(Box::new(MockProcessor::new()));
+        let code = ledger
+            .vouchers
+            .issue(VoucherKind::GiftCard, 1000, None, None, travel_date());
+        let expires = ledger.vouchers.get(&code).unwrap().expires;
+        let after = expires.succ_opt().unwrap().and_hms_opt(9, 0, 0).unwrap();
+        assert!(ledger.pay_with_voucher("P1_1_20240610", &code, 500, after).is_err());
+        assert!(ledger.entries.is_empty());
+        assert_eq!(ledger.vouchers.get(&code).unwrap().balance, 1000);
+    }
+
+    #[test]
+    fn test_compensation_is_topped_up() {
+        let mut bookings = BookingList::new();
+        let mut passengers = PassengerList::new();
+        let mut trains = TrainList::new();
+        passengers.insert("P1".to_string(), adult("P1", "John Doe"));
+        trains.insert(1, express());
+        trains.get_mut(&1).unwrap().fare = 4000;
+        let booking_id = book(&mut bookings, &mut passengers, &mut trains, "P1", 1).unwrap();
+        let mut vouchers = VoucherBook::default();
+        let disrupt = |trains: &mut TrainList, disruption: Disruption| {
+            let service = ServiceDisruption {
+                disruption,
+                reason: "Signal failure".to_string(),
+                recorded: travel_date().and_hms_opt(9, 0, 0).unwrap(),
+            };
+            record_disruption(trains, 1, travel_date(), service).unwrap();
+        };
+
+        disrupt(&mut trains, Disruption::Delayed(30));
+        assert!(issue_compensation(&mut vouchers, &bookings, &trains, 1, travel_date(), travel_date()).is_empty());
+
+        disrupt(&mut trains, Disruption::Delayed(75));
+        let issued = issue_compensation(&mut vouchers, &bookings, &trains, 1, travel_date(), travel_date());
+        assert_eq!(issued.len(), 1);
+        assert_eq!(issued[0].2, 1000);
+        assert!(issue_compensation(&mut vouchers, &bookings, &trains, 1, travel_date(), travel_date()).is_empty());
+
+        disrupt(&mut trains, Disruption::Cancelled);
+        let issued = issue_compensation(&mut vouchers, &bookings, &trains, 1, travel_date(), travel_date());
+        assert_eq!(issued[0].2, 1000);
+        assert!(issued[0].1.starts_with("CP-"));
+        assert_eq!(vouchers.compensation_issued(&booking_id), 2000);
+    }
+}